@@ -89,12 +89,20 @@ impl CBuffer {
 
     #[inline]
     pub fn as_slice(&self) -> &[u8] {
-        unsafe { slice::from_raw_parts(self.ptr, self.len()) }
+        if self.ptr.is_null() {
+            &[]
+        } else {
+            unsafe { slice::from_raw_parts(self.ptr, self.len()) }
+        }
     }
 
     #[inline]
     pub fn as_mut_slice(&mut self) -> &mut [u8] {
-        unsafe { slice::from_raw_parts_mut(self.ptr, self.len()) }
+        if self.ptr.is_null() {
+            &mut []
+        } else {
+            unsafe { slice::from_raw_parts_mut(self.ptr, self.len()) }
+        }
     }
 
     pub unsafe fn into_raw_parts(mut self) -> (*mut i8, usize) {
@@ -38,6 +38,10 @@ where
 
     loop {
         match reader.fill_buf() {
+            Ok([]) => {
+                ok!(feed.finish());
+                return Ok(feed);
+            }
             Ok(buf) => match ok!(feed.feed(buf)) {
                 ControlFlow::Continue(consumed) => reader.consume(consumed),
                 ControlFlow::Break(consumed) => {
@@ -113,3 +117,14 @@ where
     let _noecho = noecho(reader)?;
     _secure_read(reader, feed, timeout)
 }
+
+/// Like [`secure_read`], for readers that need not be terminals, as pipes.
+pub fn secure_read_notty<B, F, E>(reader: &mut B, feed: F, timeout: u32) -> Result<F, E>
+where
+    B: io::BufRead + io::AsRawFd,
+    F: FeedRead,
+    F::Error: Into<E>,
+    E: From<io::Error>,
+{
+    _secure_read(reader, feed, timeout)
+}
@@ -0,0 +1,379 @@
+//! doas.conf frontend, every line compiles into the same [`Rule`] the pezzo
+//! syntax would produce:
+//!
+//! ```text
+//! permit|deny [options] identity [as target] [cmd command [args ...]]
+//! ```
+//!
+//! doas lets the last matching rule win, pezzo lets a matching `deny` win over
+//! any `permit`. The two only differ when a `permit` follows a `deny` it
+//! overlaps, which is rejected.
+
+use super::{
+    Action, ArgPattern, Args, Env, EnvTemplate, EnvTemplatePart, Error, Ident, Origin, Patterns,
+    Rule, Statement, Target, VarPattern,
+};
+use globset::{Glob, GlobBuilder};
+use std::{
+    ffi::{CString, OsStr, OsString},
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    rc::Rc,
+};
+
+/// Directories doas looks relative commands up in.
+const SAFE_PATH: &[&str] = &[
+    "/bin",
+    "/sbin",
+    "/usr/bin",
+    "/usr/sbin",
+    "/usr/local/bin",
+    "/usr/local/sbin",
+];
+
+/// A command with its arguments, if restricted.
+type Cmd = (Vec<u8>, Option<Vec<Vec<u8>>>);
+
+/// What a line applies to, as written.
+struct Scope {
+    group: bool,
+    identity: Vec<u8>,
+    target: Option<Vec<u8>>,
+    cmd: Option<Cmd>,
+}
+
+impl Scope {
+    /// Whether no request can match both, as far as can be told without
+    /// looking users and groups up.
+    fn is_disjoint(&self, other: &Self) -> bool {
+        /// Names and IDs may be the same user.
+        fn distinct(a: &[u8], b: &[u8]) -> bool {
+            let numeric = |s: &[u8]| s.iter().all(u8::is_ascii_digit);
+            a != b && numeric(a) == numeric(b)
+        }
+
+        if !self.group && !other.group && distinct(&self.identity, &other.identity) {
+            return true;
+        }
+        if let (Some(a), Some(b)) = (&self.target, &other.target) {
+            if distinct(a, b) {
+                return true;
+            }
+        }
+        match (&self.cmd, &other.cmd) {
+            // A relative command can name the same file as an absolute one.
+            (Some((a, _)), Some((b, _))) if a != b => {
+                (a.first() == Some(&b'/')) == (b.first() == Some(&b'/'))
+            }
+            (Some((_, Some(a))), Some((_, Some(b)))) => a != b,
+            _ => false,
+        }
+    }
+}
+
+enum Opt {
+    NoPass,
+    NoLog,
+    Persist,
+    KeepEnv,
+    SetEnv(Vec<Env>),
+}
+
+/// A glob matching `path` literally.
+fn literal(path: &[u8]) -> Result<Glob, &'static str> {
+    let path = std::str::from_utf8(path).map_err(|_| "invalid utf8")?;
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            '*' | '?' | '[' | ']' | '{' | '}' => {
+                escaped.push('[');
+                escaped.push(c);
+                escaped.push(']');
+            }
+            '\\' => escaped.push_str("\\\\"),
+            c => escaped.push(c),
+        }
+    }
+
+    GlobBuilder::new(&escaped)
+        .literal_separator(true)
+        .build()
+        .map_err(|_| "invalid command")
+}
+
+/// Relative commands match in any of the [`SAFE_PATH`] directories.
+fn command(cmd: &[u8]) -> Result<Patterns, &'static str> {
+    let globs = if cmd.first() == Some(&b'/') {
+        vec![literal(cmd)?]
+    } else {
+        SAFE_PATH
+            .iter()
+            .map(|dir| literal(&[dir.as_bytes(), b"/", cmd].concat()))
+            .collect::<Result<_, _>>()?
+    };
+    Patterns::new(globs).map_err(|_| "invalid command")
+}
+
+/// Numeric identities are IDs, as doas falls back to them.
+fn ident(name: Vec<u8>) -> Result<Ident, &'static str> {
+    if !name.is_empty() && name.iter().all(u8::is_ascii_digit) {
+        if let Some(id) = std::str::from_utf8(&name).ok().and_then(|s| s.parse().ok()) {
+            return Ok(Ident::Id(id));
+        }
+    }
+    CString::new(name)
+        .map(Ident::Name)
+        .map_err(|_| "invalid name")
+}
+
+fn var_name(name: &[u8]) -> Result<Rc<Box<OsStr>>, &'static str> {
+    if name.is_empty() || name.contains(&b'=') {
+        return Err("variable name");
+    }
+    Ok(Rc::new(OsStr::from_bytes(name).into()))
+}
+
+/// `VAR` copies, `-VAR` removes, `VAR=value` sets and `VAR=$OTHER` copies
+/// `OTHER` from the environment of the invoking user.
+fn env(word: Vec<u8>) -> Result<Env, &'static str> {
+    if let Some(name) = word.strip_prefix(b"-") {
+        return Ok(Env::Unset(VarPattern::literal(var_name(name)?)));
+    }
+
+    match memchr::memchr(b'=', &word) {
+        None => Ok(Env::Copy(VarPattern::literal(var_name(&word)?))),
+        Some(pos) => {
+            let name = var_name(&word[..pos])?;
+            let value = &word[pos + 1..];
+            let part = match value.strip_prefix(b"$") {
+                Some(var) if !var.is_empty() => {
+                    EnvTemplatePart::Var(OsString::from_vec(var.to_vec()).into_boxed_os_str())
+                }
+                _ => EnvTemplatePart::Str(OsString::from_vec(value.to_vec()).into_boxed_os_str()),
+            };
+            Ok(Env::Set(name, EnvTemplate::new(vec![part])))
+        }
+    }
+}
+
+fn build(
+    action: Action,
+    opts: Vec<Opt>,
+    identity: Vec<u8>,
+    target: Option<Vec<u8>>,
+    cmd: Option<Cmd>,
+) -> Result<Rule, &'static str> {
+    let mut rule = Rule {
+        action,
+        origin: vec![match identity.strip_prefix(b":") {
+            Some(group) => Origin::Group(vec![ident(group.to_vec())?]),
+            None => Origin::User(vec![ident(identity)?]),
+        }],
+        target: target
+            .map(|t| ident(t).map(|t| vec![Target::User(vec![t])]))
+            .transpose()?,
+        // The password is asked every time unless `persist` is given.
+        timeout: Some(0),
+        ..Default::default()
+    };
+
+    let mut persist = false;
+    let mut setenv: Option<Vec<Env>> = None;
+    for opt in opts {
+        match opt {
+            Opt::NoPass => rule.askpass = Some(false),
+            Opt::NoLog => (),
+            Opt::Persist => persist = true,
+            Opt::KeepEnv => rule.keepenv = Some(true),
+            Opt::SetEnv(envs) => setenv.get_or_insert_with(Vec::new).extend(envs),
+        }
+    }
+    rule.setenv = setenv.map(Vec::into_boxed_slice);
+    if persist {
+        if rule.askpass == Some(false) {
+            return Err("nopass and persist cannot be combined");
+        }
+        rule.timeout = None;
+    }
+
+    if let Some((cmd, args)) = cmd {
+        rule.exe = Some(command(&cmd)?);
+        rule.args = args.map(|args| {
+            vec![Args {
+                patterns: args
+                    .into_iter()
+                    .map(|a| ArgPattern::Exact(OsString::from_vec(a).into_boxed_os_str()))
+                    .collect(),
+                variadic: false,
+            }]
+        });
+    }
+
+    Ok(rule)
+}
+
+peg::parser! {
+    grammar doas() for [u8] {
+        rule sp() = quiet!{([b' ' | b'\t'] / "\\\n")+}
+        rule _ = quiet!{sp()?}
+        rule comment() = quiet!{[b'#'] [^ b'\n']*}
+
+        pub rule parse() -> Vec<(usize, Rule, Scope)>
+            = lines:line()* _ comment()? ![_] { lines.into_iter().flatten().collect() }
+
+        rule line() -> Option<(usize, Rule, Scope)>
+            = _ comment()? [b'\n'] { None }
+            / _ p:position!() r:permit_deny() _ comment()? ([b'\n'] / ![_]) { Some((p, r.0, r.1)) }
+
+        rule word_char() -> u8
+            = [b'\\'] c:[^ b'\n' | b'\0'] { c }
+            / c:[^ b' ' | b'\t' | b'\n' | b'"' | b'#' | b'{' | b'}' | b'\\' | b'\0'] { c }
+
+        rule quoted_char() -> u8
+            = [b'\\'] c:[^ b'\0'] { c }
+            / c:[^ b'"' | b'\n' | b'\0'] { c }
+
+        rule word_part() -> Vec<u8>
+            = [b'"'] s:quoted_char()* [b'"'] { s }
+            / c:word_char() { vec![c] }
+
+        rule word() -> Vec<u8>
+            = quiet!{parts:word_part()+ { parts.concat() }} / expected!("word")
+
+        rule kw(k: &'static str)
+            = w:word() {? if w == k.as_bytes() { Ok(()) } else { Err(k) } }
+
+        rule action() -> Action
+            = kw("permit") { Action::Permit }
+            / kw("deny") { Action::Deny }
+
+        rule env() -> Env
+            = w:word() {? env(w) }
+
+        rule option() -> Opt
+            = kw("nopass") { Opt::NoPass }
+            / kw("nolog") { Opt::NoLog }
+            / kw("persist") { Opt::Persist }
+            / kw("keepenv") { Opt::KeepEnv }
+            / kw("setenv") _ "{" e:(_ e:env() { e })* _ "}" { Opt::SetEnv(e) }
+
+        rule args() -> Vec<Vec<u8>>
+            = kw("args") a:(sp() w:word() { w })* { a }
+
+        rule cmd() -> Cmd
+            = kw("cmd") sp() c:word() a:(sp() a:args() { a })? { (c, a) }
+
+        rule permit_deny() -> (Rule, Scope)
+            = a:action() o:(sp() o:option() { o })* sp() i:word()
+              t:(sp() kw("as") sp() t:word() { t })?
+              c:(sp() c:cmd() { c })? {?
+                let scope = Scope {
+                    group: i.first() == Some(&b':'),
+                    identity: i.clone(),
+                    target: t.clone(),
+                    cmd: c.clone(),
+                };
+                build(a, o, i, t, c).map(|r| (r, scope))
+            }
+    }
+}
+
+/// Parses a doas.conf buffer.
+pub fn parse(buf: &[u8]) -> Result<Vec<Statement>, Error> {
+    let lines = doas::parse(buf).map_err(Error::Syntax)?;
+
+    for (i, (location, rule, scope)) in lines.iter().enumerate() {
+        if rule.action != Action::Permit {
+            continue;
+        }
+        let denied = lines[..i]
+            .iter()
+            .any(|(_, r, s)| r.action == Action::Deny && !s.is_disjoint(scope));
+        if denied {
+            return Err(Error::PermitAfterDeny {
+                location: *location,
+            });
+        }
+    }
+
+    Ok(lines
+        .into_iter()
+        .map(|(_, rule, _)| Statement::Rule(Box::new(rule)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        conf::rules,
+        policy::{evaluate, Verdict},
+    };
+
+    fn verdict(rules: &[Rule], command: &str) -> Option<Verdict> {
+        evaluate::<(), _>(rules, |rule| {
+            Ok(rule.exe.as_ref().is_none_or(|exe| exe.is_match(command)))
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn permit_after_deny() {
+        let err = |conf: &str| parse(conf.as_bytes()).err();
+
+        assert!(matches!(
+            err("deny alice cmd passwd\npermit :wheel\n"),
+            Some(Error::PermitAfterDeny { location: 22 })
+        ));
+        assert!(err("deny alice\npermit alice as postgres\n").is_some());
+        assert!(err("deny alice cmd passwd\npermit alice cmd /usr/bin/passwd\n").is_some());
+        assert!(err("deny 1000\npermit alice\n").is_some());
+
+        // Permits before the deny, other users, targets and commands.
+        assert!(err("permit :wheel\ndeny alice cmd passwd\n").is_none());
+        assert!(err("deny bob\npermit alice\n").is_none());
+        assert!(err("deny alice as root\npermit alice as postgres\n").is_none());
+        assert!(err("deny alice cmd passwd\npermit alice cmd id\n").is_none());
+        assert!(err("deny alice cmd id args -u\npermit alice cmd id args -g\n").is_none());
+    }
+
+    #[test]
+    fn doas_syntax() {
+        let rules = rules(
+            "# syntax: doas
+            permit persist :wheel
+            permit nopass keepenv alice as root cmd /usr/bin/id args -u
+            deny bob cmd passwd # comment",
+        );
+        assert_eq!(rules.len(), 3);
+
+        assert!(matches!(&rules[0].origin[..], [Origin::Group(g)] if g.len() == 1));
+        assert_eq!(rules[0].timeout, None);
+        assert_eq!(
+            verdict(&rules[..1], "/usr/bin/id"),
+            Some(Verdict::Permit(0))
+        );
+
+        assert_eq!(rules[1].askpass, Some(false));
+        assert_eq!(rules[1].keepenv, Some(true));
+        assert_eq!(rules[1].timeout, Some(0));
+        assert!(matches!(
+            rules[1].target.as_deref(),
+            Some([Target::User(_)])
+        ));
+        let args = rules[1].args.as_ref().unwrap();
+        assert!(args.iter().any(|a| a.is_match(&["-u"])));
+        assert!(!args.iter().any(|a| a.is_match::<&str>(&[])));
+
+        assert_eq!(
+            verdict(&rules[2..], "/usr/bin/passwd"),
+            Some(Verdict::Deny(0))
+        );
+        assert_eq!(
+            verdict(&rules[2..], "/usr/local/bin/passwd"),
+            Some(Verdict::Deny(0))
+        );
+        assert_eq!(verdict(&rules[2..], "/opt/passwd"), None);
+
+        assert!(crate::conf::parse("# syntax: doas\npermit nopass persist alice\n").is_err());
+    }
+}
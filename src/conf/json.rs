@@ -0,0 +1,381 @@
+//! JSON export of a compiled policy.
+
+use super::{
+    ArgPattern, Args, DateTime, Defaults, Env, EnvTemplatePart, Hours, Ident, Origin, Patterns,
+    Rule, Rules, Target, VarPattern,
+};
+use std::{fmt, os::unix::ffi::OsStrExt};
+
+/// Version of the layout, bumped on incompatible changes.
+const VERSION: u32 = 1;
+
+/// Displays a [`Rules`] as JSON. The layout is versioned so tools can rely
+/// on it: every rule has every key, `null` when the rule leaves the
+/// setting to its default.
+///
+/// ```text
+/// { "version": 1, "timezone": null | "Zone", "defaults": [ {
+///     "origin": null | [ORIGIN],
+///     "timeout": null | seconds,
+///     "askpass": null | bool,
+///     "keepenv": null | bool,
+///     "use_pty": null | bool,
+///     "prompt_timeout": null | seconds,
+///     "max_retries": null | count,
+///     "secure_path": null | "dir:dir",
+///     "prompt": null | "text",
+///     "env_check": null | ["VAR"],
+///     "password_from": null | ["tty" | "stdin" | "askpass"]
+/// } ], "rules": [ {
+///     "action": "permit" | "deny",
+///     "origin": [ORIGIN],
+///     "target": null | [ { "users": [IDENT] } | { "users": [IDENT], "groups": [IDENT] } ],
+///     "host": null | ["glob"],
+///     "exe": null | ["glob"],
+///     "args": null | [ { "patterns": [ { "exact": "arg" } | { "glob": "glob" } ], "variadic": bool } ],
+///     "edit": null | ["glob"],
+///     "timeout": null | seconds,
+///     "askpass": null | bool,
+///     "keepenv": null | bool,
+///     "use_pty": null | bool,
+///     "setenv": null | [ { "unset": "VAR" } | { "copy": "VAR" }
+///                       | { "set": "VAR", "value": [ { "str": "text" } | { "var": "VAR" }
+///                                                  | { "var": "VAR", "default": "text" }
+///                                                  | { "var": "VAR", "required": true } ] } ],
+///     "valid_from": null | "YYYY-MM-DDTHH:MM",
+///     "valid_until": null | "YYYY-MM-DDTHH:MM",
+///     "hours": null | [ { "days": ["Sun", ...], "start": "HH:MM", "end": "HH:MM" } ],
+///     "prompt_timeout": null | seconds,
+///     "max_retries": null | count,
+///     "prompt": null | "text",
+///     "secure_path": null | "dir:dir",
+///     "env_check": null | ["VAR"],
+///     "envfile": null | "path",
+///     "password_from": null | ["tty" | "stdin" | "askpass"]
+/// } ] }
+/// ```
+///
+/// where `VAR` can have `*` and `?` wildcards, `ORIGIN` is `{ "users": [IDENT] }` or `{ "groups": [IDENT] }` and
+/// `IDENT` is `{ "name": "alice" }` or `{ "id": 1001 }`. A string that is
+/// not valid UTF-8 is written as the array of its bytes instead.
+pub struct Json<'a>(pub(super) &'a Rules);
+
+struct Str<'a>(&'a [u8]);
+
+impl fmt::Display for Str<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Ok(s) = std::str::from_utf8(self.0) else {
+            return write_array(f, self.0, |f, b| write!(f, "{}", b));
+        };
+        f.write_str("\"")?;
+        for c in s.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\t' => f.write_str("\\t")?,
+                c if c < ' ' || c == '\u{7f}' => write!(f, "\\u{:04x}", c as u32)?,
+                c => write!(f, "{}", c)?,
+            }
+        }
+        f.write_str("\"")
+    }
+}
+
+fn write_array<T, F>(f: &mut fmt::Formatter<'_>, items: &[T], mut write: F) -> fmt::Result
+where
+    F: FnMut(&mut fmt::Formatter<'_>, &T) -> fmt::Result,
+{
+    f.write_str("[")?;
+    for (i, item) in items.iter().enumerate() {
+        if i != 0 {
+            f.write_str(",")?;
+        }
+        write(f, item)?;
+    }
+    f.write_str("]")
+}
+
+fn write_option<T, F>(f: &mut fmt::Formatter<'_>, value: Option<T>, write: F) -> fmt::Result
+where
+    F: FnOnce(&mut fmt::Formatter<'_>, T) -> fmt::Result,
+{
+    match value {
+        Some(value) => write(f, value),
+        None => f.write_str("null"),
+    }
+}
+
+fn write_ident(f: &mut fmt::Formatter<'_>, ident: &Ident) -> fmt::Result {
+    match ident {
+        Ident::Name(name) => write!(f, "{{\"name\":{}}}", Str(name.to_bytes())),
+        Ident::Id(id) => write!(f, "{{\"id\":{}}}", id),
+    }
+}
+
+fn write_idents(f: &mut fmt::Formatter<'_>, key: &str, idents: &[Ident]) -> fmt::Result {
+    write!(f, "\"{}\":", key)?;
+    write_array(f, idents, write_ident)
+}
+
+fn write_origin(f: &mut fmt::Formatter<'_>, origin: &Origin) -> fmt::Result {
+    f.write_str("{")?;
+    match origin {
+        Origin::User(users) => write_idents(f, "users", users)?,
+        Origin::Group(groups) => write_idents(f, "groups", groups)?,
+    }
+    f.write_str("}")
+}
+
+fn write_target(f: &mut fmt::Formatter<'_>, target: &Target) -> fmt::Result {
+    f.write_str("{")?;
+    match target {
+        Target::User(users) => write_idents(f, "users", users)?,
+        Target::UserGroup(users, groups) => {
+            write_idents(f, "users", users)?;
+            f.write_str(",")?;
+            write_idents(f, "groups", groups)?;
+        }
+    }
+    f.write_str("}")
+}
+
+fn write_patterns(f: &mut fmt::Formatter<'_>, patterns: &Patterns) -> fmt::Result {
+    write_array(f, patterns.globs(), |f, glob| {
+        write!(f, "{}", Str(glob.glob().as_bytes()))
+    })
+}
+
+fn write_args(f: &mut fmt::Formatter<'_>, args: &Args) -> fmt::Result {
+    f.write_str("{\"patterns\":")?;
+    write_array(f, &args.patterns, |f, pattern| match pattern {
+        ArgPattern::Exact(exact) => write!(f, "{{\"exact\":{}}}", Str(exact.as_bytes())),
+        ArgPattern::Glob(glob) => write!(f, "{{\"glob\":{}}}", Str(glob.glob().glob().as_bytes())),
+    })?;
+    write!(f, ",\"variadic\":{}}}", args.variadic)
+}
+
+fn write_env(f: &mut fmt::Formatter<'_>, env: &Env) -> fmt::Result {
+    match env {
+        Env::Unset(pattern) => write!(f, "{{\"unset\":{}}}", Str(pattern.name().as_bytes())),
+        Env::Copy(pattern) => write!(f, "{{\"copy\":{}}}", Str(pattern.name().as_bytes())),
+        Env::Set(name, template) => {
+            write!(f, "{{\"set\":{},\"value\":", Str(name.as_bytes()))?;
+            write_array(f, template.parts(), |f, part| match part {
+                EnvTemplatePart::Str(txt) => write!(f, "{{\"str\":{}}}", Str(txt.as_bytes())),
+                EnvTemplatePart::Var(name) => write!(f, "{{\"var\":{}}}", Str(name.as_bytes())),
+                EnvTemplatePart::VarOr(name, default) => write!(
+                    f,
+                    "{{\"var\":{},\"default\":{}}}",
+                    Str(name.as_bytes()),
+                    Str(default.as_bytes())
+                ),
+                EnvTemplatePart::Required(name) => {
+                    write!(f, "{{\"var\":{},\"required\":true}}", Str(name.as_bytes()))
+                }
+            })?;
+            f.write_str("}")
+        }
+    }
+}
+
+fn write_var_patterns(f: &mut fmt::Formatter<'_>, patterns: &[VarPattern]) -> fmt::Result {
+    write_array(f, patterns, |f, p| {
+        write!(f, "{}", Str(p.name().as_bytes()))
+    })
+}
+
+fn write_datetime(f: &mut fmt::Formatter<'_>, datetime: DateTime) -> fmt::Result {
+    write!(
+        f,
+        "\"{:04}-{:02}-{:02}T{:02}:{:02}\"",
+        datetime.year,
+        datetime.month,
+        datetime.day,
+        datetime.minutes / 60,
+        datetime.minutes % 60
+    )
+}
+
+fn write_hours(f: &mut fmt::Formatter<'_>, hours: &Hours) -> fmt::Result {
+    const NAMES: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+    let days = (0..7)
+        .filter(|day| hours.days & (1 << day) != 0)
+        .map(|day| NAMES[day])
+        .collect::<Vec<_>>();
+    f.write_str("{\"days\":")?;
+    write_array(f, &days, |f, day| write!(f, "\"{}\"", day))?;
+    write!(
+        f,
+        ",\"start\":\"{:02}:{:02}\",\"end\":\"{:02}:{:02}\"}}",
+        hours.start / 60,
+        hours.start % 60,
+        hours.end / 60,
+        hours.end % 60
+    )
+}
+
+fn write_defaults(f: &mut fmt::Formatter<'_>, defaults: &Defaults) -> fmt::Result {
+    f.write_str("{\"origin\":")?;
+    write_option(f, defaults.origin.as_deref(), |f, o| {
+        write_array(f, o, write_origin)
+    })?;
+    f.write_str(",\"timeout\":")?;
+    write_option(f, defaults.timeout, |f, t| write!(f, "{}", t))?;
+    f.write_str(",\"askpass\":")?;
+    write_option(f, defaults.askpass, |f, b| write!(f, "{}", b))?;
+    f.write_str(",\"keepenv\":")?;
+    write_option(f, defaults.keepenv, |f, b| write!(f, "{}", b))?;
+    f.write_str(",\"use_pty\":")?;
+    write_option(f, defaults.use_pty, |f, b| write!(f, "{}", b))?;
+    f.write_str(",\"prompt_timeout\":")?;
+    write_option(f, defaults.prompt_timeout, |f, t| write!(f, "{}", t))?;
+    f.write_str(",\"max_retries\":")?;
+    write_option(f, defaults.max_retries, |f, n| write!(f, "{}", n))?;
+    f.write_str(",\"secure_path\":")?;
+    write_option(f, defaults.secure_path.as_deref(), |f, p| {
+        write!(f, "{}", Str(p.to_bytes()))
+    })?;
+    f.write_str(",\"prompt\":")?;
+    write_option(f, defaults.prompt.as_deref(), |f, p| {
+        write!(f, "{}", Str(p.to_bytes()))
+    })?;
+    f.write_str(",\"env_check\":")?;
+    write_option(f, defaults.env_check.as_deref(), write_var_patterns)?;
+    f.write_str(",\"password_from\":")?;
+    write_option(f, defaults.password_from.as_deref(), |f, p| {
+        write_array(f, p, |f, p| write!(f, "\"{}\"", p))
+    })?;
+    f.write_str("}")
+}
+
+fn write_rule(f: &mut fmt::Formatter<'_>, rule: &Rule) -> fmt::Result {
+    write!(f, "{{\"action\":\"{}\",\"origin\":", rule.action)?;
+    write_array(f, &rule.origin, write_origin)?;
+    f.write_str(",\"target\":")?;
+    write_option(f, rule.target.as_deref(), |f, t| {
+        write_array(f, t, write_target)
+    })?;
+    f.write_str(",\"host\":")?;
+    write_option(f, rule.host.as_ref(), write_patterns)?;
+    f.write_str(",\"exe\":")?;
+    write_option(f, rule.exe.as_ref(), write_patterns)?;
+    f.write_str(",\"args\":")?;
+    write_option(f, rule.args.as_deref(), |f, a| {
+        write_array(f, a, write_args)
+    })?;
+    f.write_str(",\"edit\":")?;
+    write_option(f, rule.edit.as_ref(), write_patterns)?;
+    f.write_str(",\"timeout\":")?;
+    write_option(f, rule.timeout, |f, t| write!(f, "{}", t))?;
+    f.write_str(",\"askpass\":")?;
+    write_option(f, rule.askpass, |f, b| write!(f, "{}", b))?;
+    f.write_str(",\"keepenv\":")?;
+    write_option(f, rule.keepenv, |f, b| write!(f, "{}", b))?;
+    f.write_str(",\"use_pty\":")?;
+    write_option(f, rule.use_pty, |f, b| write!(f, "{}", b))?;
+    f.write_str(",\"setenv\":")?;
+    write_option(f, rule.setenv.as_deref(), |f, e| {
+        write_array(f, e, write_env)
+    })?;
+    f.write_str(",\"valid_from\":")?;
+    write_option(f, rule.valid_from, write_datetime)?;
+    f.write_str(",\"valid_until\":")?;
+    write_option(f, rule.valid_until, write_datetime)?;
+    f.write_str(",\"hours\":")?;
+    write_option(f, rule.hours.as_deref(), |f, h| {
+        write_array(f, h, write_hours)
+    })?;
+    f.write_str(",\"prompt_timeout\":")?;
+    write_option(f, rule.prompt_timeout, |f, t| write!(f, "{}", t))?;
+    f.write_str(",\"max_retries\":")?;
+    write_option(f, rule.max_retries, |f, n| write!(f, "{}", n))?;
+    f.write_str(",\"prompt\":")?;
+    write_option(f, rule.prompt.as_deref(), |f, p| {
+        write!(f, "{}", Str(p.to_bytes()))
+    })?;
+    f.write_str(",\"secure_path\":")?;
+    write_option(f, rule.secure_path.as_deref(), |f, p| {
+        write!(f, "{}", Str(p.to_bytes()))
+    })?;
+    f.write_str(",\"env_check\":")?;
+    write_option(f, rule.env_check.as_deref(), write_var_patterns)?;
+    f.write_str(",\"envfile\":")?;
+    write_option(f, rule.envfile.as_deref(), |f, p| {
+        write!(f, "{}", Str(p.to_bytes()))
+    })?;
+    f.write_str(",\"password_from\":")?;
+    write_option(f, rule.password_from.as_deref(), |f, p| {
+        write_array(f, p, |f, p| write!(f, "\"{}\"", p))
+    })?;
+    f.write_str("}")
+}
+
+impl fmt::Display for Json<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{\"version\":{},\"timezone\":", VERSION)?;
+        write_option(f, self.0.timezone(), |f, tz| {
+            write!(f, "{}", Str(tz.to_bytes()))
+        })?;
+        f.write_str(",\"defaults\":")?;
+        write_array(f, self.0.defaults(), write_defaults)?;
+        f.write_str(",\"rules\":")?;
+        write_array(f, self.0.rules(), write_rule)?;
+        f.write_str("}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf::{rules, Rules};
+
+    #[test]
+    fn dump_policy() {
+        let conf = r#"rule {
+                origin = (alice | #1001) | :wheel;
+                target = root | postgres:"dba";
+                host = web-*;
+                exe = /usr/bin/a\ b | /usr/bin/*;
+                args = ["-h" *.conf ...] | [];
+                timeout = 0;
+                askpass = false;
+                keepenv = true;
+                setenv = { -FOO, BAR, BAZ="x${HOME}" };
+                valid_from = 2024-01-01;
+                valid_until = 2024-12-31 00:00;
+                hours = Mon-Fri 08:00-18:00;
+            }
+            rule { action = deny; origin = bob; }"#;
+        let policy =
+            |conf: &str| Rules::new(rules(conf), Vec::new(), Some(c"Europe/Rome".to_owned()));
+
+        let text = policy(conf).to_string();
+        assert_eq!(
+            policy(&text.replace("timezone \"Europe/Rome\";", "")).to_string(),
+            text
+        );
+        assert!(text.starts_with("timezone \"Europe/Rome\";\n\nrule {\n    action = permit;\n"));
+        assert!(
+            text.contains("\n    valid_from = 2024-01-01;\n    valid_until = 2024-12-31 00:00;\n")
+        );
+
+        let json = policy(conf).json().to_string();
+        assert!(json.starts_with(r#"{"version":1,"timezone":"Europe/Rome","defaults":[],"rules":[{"action":"permit","origin":[{"users":[{"name":"alice"},{"id":1001}]},{"groups":[{"name":"wheel"}]}]"#));
+        assert!(json.contains(r#""exe":["/usr/bin/a b","/usr/bin/*"],"args":[{"patterns":[{"exact":"-h"},{"glob":"*.conf"}],"variadic":true},{"patterns":[],"variadic":false}]"#));
+        assert!(json.contains(r#""setenv":[{"unset":"FOO"},{"copy":"BAR"},{"set":"BAZ","value":[{"str":"x"},{"var":"HOME"}]}]"#));
+        assert!(json.contains(r#""valid_until":"2024-12-31T00:00","hours":[{"days":["Mon","Tue","Wed","Thu","Fri"],"start":"08:00","end":"18:00"}],"prompt_timeout":null"#));
+        assert!(json.ends_with(r#"{"action":"deny","origin":[{"users":[{"name":"bob"}]}],"target":null,"host":null,"exe":null,"args":null,"edit":null,"timeout":null,"askpass":null,"keepenv":null,"use_pty":null,"setenv":null,"valid_from":null,"valid_until":null,"hours":null,"prompt_timeout":null,"max_retries":null,"prompt":null,"secure_path":null,"env_check":null,"envfile":null,"password_from":null}]}"#));
+
+        let json = policy(r#"rule { origin = "caf\xe9" | "café"; }"#)
+            .json()
+            .to_string();
+        assert!(
+            json.contains(
+                r#""origin":[{"users":[{"name":[99,97,102,233]}]},{"users":[{"name":"café"}]}]"#
+            ),
+            "{}",
+            json
+        );
+    }
+}
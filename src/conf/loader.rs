@@ -0,0 +1,376 @@
+use std::{
+    ffi::{CStr, CString, OsStr},
+    fs::Metadata,
+    io::Read,
+    os::unix::prelude::{OsStrExt, OsStringExt},
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+
+use super::{Aliases, Defaults, Error, Rule, Rules, Statement, Syntax};
+use crate::util::open_nofollow;
+
+#[inline(always)]
+fn _parse_conf(
+    path: &CStr,
+    syntax: Option<Syntax>,
+    aliases: &mut Aliases,
+    content: &[u8],
+) -> Result<Vec<Statement>> {
+    let syntax = Syntax::from_header(content)
+        .or(syntax)
+        .or_else(|| Syntax::from_path(path.to_bytes()))
+        .unwrap_or_default();
+    match super::parse_as(content, syntax, aliases) {
+        Ok(c) => Ok(c),
+        Err(err) => {
+            let buf = &content[..err.location()];
+            let line = memchr::memchr_iter(b'\n', buf).count() + 1;
+            let col = match memchr::memrchr(b'\n', buf) {
+                Some(pos) => buf.len() - pos - 1,
+                None => buf.len(),
+            };
+            if let Error::Syntax(_) = err {
+                let got = content.get(err.location()).map_or_else(
+                    || "end of file".to_string(),
+                    |&c| format!("{:?}", c as char),
+                );
+                bail!(
+                    "{}:{}:{}: {}, got {}",
+                    path.to_string_lossy(),
+                    line,
+                    col + 1,
+                    err,
+                    got
+                );
+            }
+            bail!("{}:{}:{}: {}", path.to_string_lossy(), line, col + 1, err);
+        }
+    }
+}
+
+fn resolve_include(parent: &CStr, path: CString) -> CString {
+    if path.as_bytes().first() == Some(&b'/') {
+        return path;
+    }
+
+    let parent = parent.to_bytes();
+    let dir = match memchr::memrchr(b'/', parent) {
+        Some(0) => b"/".as_slice(),
+        Some(pos) => &parent[..pos],
+        None => b".".as_slice(),
+    };
+
+    let mut buf = Vec::with_capacity(dir.len() + path.as_bytes().len() + 2);
+    buf.extend_from_slice(dir);
+    if buf.last() != Some(&b'/') {
+        buf.push(b'/');
+    }
+    buf.extend_from_slice(path.as_bytes());
+    unsafe { CString::from_vec_unchecked(buf) }
+}
+
+/// Files in an `includedir` are read in byte order of their names, hidden
+/// files and editor backups (`~` suffix) are skipped.
+fn list_include_dir(dir: &CStr) -> Result<Vec<CString>> {
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(OsStr::from_bytes(dir.to_bytes()))
+        .with_context(|| format!("Cannot read directory {:?}", dir))?
+    {
+        let entry = entry.with_context(|| format!("Cannot read directory {:?}", dir))?;
+        let name = entry.file_name().into_vec();
+        if name.first() == Some(&b'.') || name.last() == Some(&b'~') {
+            continue;
+        }
+        if !std::fs::metadata(entry.path())
+            .map(|md| md.is_file())
+            .unwrap_or(false)
+        {
+            continue;
+        }
+        names.push(name);
+    }
+    names.sort();
+
+    let dir = dir.to_bytes();
+    Ok(names
+        .into_iter()
+        .map(|name| {
+            let mut buf = Vec::with_capacity(dir.len() + name.len() + 2);
+            buf.extend_from_slice(dir);
+            if buf.last() != Some(&b'/') {
+                buf.push(b'/');
+            }
+            buf.extend_from_slice(&name);
+            unsafe { CString::from_vec_unchecked(buf) }
+        })
+        .collect())
+}
+
+/// Rejects timezone names that would make the C library silently fall back to
+/// UTC. POSIX `TZ` strings (e.g. `CET-1CEST`) always contain a digit and are
+/// passed through unchecked.
+fn check_timezone(tz: &CStr) -> Result<()> {
+    let name = tz.to_bytes();
+    let name = name.strip_prefix(b":").unwrap_or(name);
+    if name.iter().any(u8::is_ascii_digit) && tz.to_bytes().first() != Some(&b':') {
+        return Ok(());
+    }
+
+    let path = if name.first() == Some(&b'/') {
+        OsStr::from_bytes(name).into()
+    } else if name.split(|&c| c == b'/').any(|c| c == b"..") {
+        bail!("Invalid timezone {:?}", tz);
+    } else {
+        Path::new("/usr/share/zoneinfo").join(OsStr::from_bytes(name))
+    };
+
+    if !path.is_file() {
+        bail!("Unknown timezone {:?}", tz);
+    }
+    Ok(())
+}
+
+struct Loader<F> {
+    check_permissions: F,
+    stack: Vec<CString>,
+    aliases: Aliases,
+    rules: Vec<Rule>,
+    defaults: Vec<Defaults>,
+    timezone: Option<CString>,
+}
+
+impl<F: FnMut(&CStr, &Metadata) -> Result<()>> Loader<F> {
+    fn load(&mut self, path: &CStr, syntax: Option<Syntax>) -> Result<()> {
+        let canonical = crate::io::canonicalize(path)
+            .with_context(|| format!("Cannot resolve configuration file {:?}", path))?;
+        if self.stack.contains(&canonical) {
+            bail!("{}: include cycle detected", path.to_string_lossy());
+        }
+
+        let (mut file, md) = open_nofollow(&canonical, false)
+            .with_context(|| format!("Cannot open configuration file {:?}", path))?;
+        (self.check_permissions)(path, &md)?;
+        let mut content = Vec::with_capacity(md.len() as usize);
+        file.read_to_end(&mut content)
+            .with_context(|| format!("Cannot read configuration file {:?}", path))?;
+        drop(file);
+
+        let statements = _parse_conf(path, syntax, &mut self.aliases, &content)?;
+
+        self.stack.push(canonical.clone());
+        for statement in statements {
+            match statement {
+                Statement::Rule(rule) => self.rules.push(*rule),
+                Statement::Defaults(defaults) => self.defaults.push(*defaults),
+                Statement::Include(include) => {
+                    let include = resolve_include(&canonical, include);
+                    self.load(&include, None)
+                        .with_context(|| format!("included from {}", path.to_string_lossy()))?;
+                }
+                Statement::IncludeDir(dir) => {
+                    let dir = resolve_include(&canonical, dir);
+                    let canonical = crate::io::canonicalize(&dir)
+                        .with_context(|| format!("Cannot resolve directory {:?}", dir))?;
+                    let (_, md) = open_nofollow(&canonical, true)
+                        .with_context(|| format!("Cannot open directory {:?}", dir))?;
+                    (self.check_permissions)(&dir, &md)?;
+                    for include in list_include_dir(&dir)? {
+                        self.load(&include, None)
+                            .with_context(|| format!("included from {}", path.to_string_lossy()))?;
+                    }
+                }
+                Statement::Timezone(tz) => {
+                    if self.timezone.is_some() {
+                        bail!(
+                            "{}: timezone has already been defined",
+                            path.to_string_lossy()
+                        );
+                    }
+                    check_timezone(&tz)
+                        .with_context(|| format!("in {}", path.to_string_lossy()))?;
+                    self.timezone = Some(tz);
+                }
+            }
+        }
+        self.stack.pop();
+
+        Ok(())
+    }
+}
+
+/// Loads the configuration at `path` with its includes. `check_permissions`
+/// is called with the metadata of every file and directory once it is open,
+/// before it is read.
+#[inline]
+pub fn load<F>(path: &CStr, check_permissions: F) -> Result<Rules>
+where
+    F: FnMut(&CStr, &Metadata) -> Result<()>,
+{
+    load_as(path, None, check_permissions)
+}
+
+/// Like [`load`], but `path` is read in `syntax` unless its header says
+/// otherwise, for copies whose name does not tell.
+pub fn load_as<F>(path: &CStr, syntax: Option<Syntax>, check_permissions: F) -> Result<Rules>
+where
+    F: FnMut(&CStr, &Metadata) -> Result<()>,
+{
+    let mut loader = Loader {
+        check_permissions,
+        stack: Vec::new(),
+        aliases: Aliases::default(),
+        rules: Vec::new(),
+        defaults: Vec::new(),
+        timezone: None,
+    };
+    loader.load(path, syntax)?;
+    Ok(Rules::new(loader.rules, loader.defaults, loader.timezone))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A fresh directory with `files` in it, removed on drop.
+    struct Tree(PathBuf);
+
+    impl Tree {
+        fn new(name: &str, files: &[(&str, &str)]) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("pezzo-loader-{}-{}", name, std::process::id()));
+            _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir(&dir).unwrap();
+            for (name, content) in files {
+                let path = dir.join(name);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, content).unwrap();
+            }
+            Self(dir)
+        }
+
+        fn path(&self, name: &str) -> CString {
+            CString::new(self.0.join(name).into_os_string().into_vec()).unwrap()
+        }
+
+        /// The `timeout` of every rule, in order.
+        fn timeouts(&self) -> Result<Vec<u64>> {
+            let rules = load(&self.path("pezzo.conf"), |_, _| Ok(()))?;
+            Ok(rules.rules().iter().filter_map(|r| r.timeout).collect())
+        }
+    }
+
+    impl Drop for Tree {
+        fn drop(&mut self) {
+            _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn include_order() {
+        let tree = Tree::new(
+            "order",
+            &[
+                (
+                    "pezzo.conf",
+                    "rule { origin = a; timeout = 1; }\ninclude \"a.conf\";\nrule { origin = a; timeout = 4; }\n",
+                ),
+                ("a.conf", "rule { origin = a; timeout = 2; }\ninclude \"sub/b.conf\";\n"),
+                ("sub/b.conf", "rule { origin = a; timeout = 3; }\n"),
+            ],
+        );
+        assert_eq!(tree.timeouts().unwrap(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn include_dir() {
+        let tree = Tree::new(
+            "dir",
+            &[
+                ("pezzo.conf", "includedir \"pezzo.d\";\n"),
+                ("pezzo.d/20-b", "rule { origin = a; timeout = 2; }\n"),
+                ("pezzo.d/10-a", "rule { origin = a; timeout = 1; }\n"),
+                ("pezzo.d/30-c", "rule { origin = a; timeout = 3; }\n"),
+                ("pezzo.d/.hidden", "rule { origin = a; timeout = 5; }\n"),
+                ("pezzo.d/30-c~", "rule { origin = a; timeout = 6; }\n"),
+            ],
+        );
+        assert_eq!(tree.timeouts().unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn include_cycle() {
+        let tree = Tree::new(
+            "cycle",
+            &[
+                ("pezzo.conf", "include \"a.conf\";\n"),
+                ("a.conf", "include \"pezzo.conf\";\n"),
+            ],
+        );
+        let err = format!("{:#}", tree.timeouts().unwrap_err());
+        assert!(err.contains("include cycle detected"), "{}", err);
+    }
+
+    #[test]
+    fn error_location() {
+        let tree = Tree::new(
+            "location",
+            &[
+                ("pezzo.conf", "# main\ninclude \"a.conf\";\n"),
+                ("a.conf", "rule { origin = a; }\n\nrule {\n  origin = ; }\n"),
+            ],
+        );
+        let err = format!("{:#}", tree.timeouts().unwrap_err());
+        let a = tree.0.join("a.conf");
+        assert!(
+            err.contains(&format!("{}:4:12: expected ", a.display())),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains(&format!(
+                "included from {}",
+                tree.0.join("pezzo.conf").display()
+            )),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn permissions_of_open_files() {
+        let tree = Tree::new(
+            "permissions",
+            &[
+                ("pezzo.conf", "includedir \"pezzo.d\";\n"),
+                ("pezzo.d/a", "rule { origin = a; }\n"),
+            ],
+        );
+        let mut checked = Vec::new();
+        load(&tree.path("pezzo.conf"), |path, md| {
+            checked.push((path.to_owned(), md.is_dir()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            checked,
+            [
+                (tree.path("pezzo.conf"), false),
+                (tree.path("pezzo.d"), true),
+                (tree.path("pezzo.d/a"), false),
+            ]
+        );
+
+        let err = load(&tree.path("pezzo.conf"), |path, _| {
+            if path.to_bytes().ends_with(b"/a") {
+                bail!("rejected")
+            }
+            Ok(())
+        })
+        .map(|_| ())
+        .unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "rejected");
+    }
+}
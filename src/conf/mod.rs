@@ -1,18 +1,481 @@
+mod doas;
+mod json;
+#[cfg(unix)]
+mod loader;
 mod parser;
 
+use std::{
+    ffi::{CStr, CString},
+    fmt,
+};
+
 pub use globset::GlobSet;
-pub use parser::{Env, Origin, Rule, Target};
+pub use json::Json;
+#[cfg(unix)]
+pub use loader::{load, load_as};
+pub use parser::{
+    Action, AliasKind, Aliases, ArgPattern, Args, DateTime, Defaults, Env, EnvTemplate,
+    EnvTemplatePart, Hours, Ident, LocalTime, Origin, PasswordFrom, Patterns, Rule, Statement,
+    Target, VarPattern,
+};
+
+use crate::{
+    DEFAULT_MAX_RETRIES, DEFAULT_PROMPT_TIMEOUT, DEFAULT_SECURE_PATH, DEFAULT_SESSION_TIMEOUT,
+};
 
-pub struct Rules(Vec<parser::Rule>);
+pub struct Rules {
+    rules: Vec<parser::Rule>,
+    defaults: Vec<Defaults>,
+    timezone: Option<CString>,
+}
 
 impl Rules {
+    #[inline]
+    pub fn new(
+        rules: Vec<parser::Rule>,
+        defaults: Vec<Defaults>,
+        timezone: Option<CString>,
+    ) -> Self {
+        Self {
+            rules,
+            defaults,
+            timezone,
+        }
+    }
+
     #[inline]
     pub fn rules(&self) -> &[parser::Rule] {
-        &self.0
+        &self.rules
+    }
+
+    /// `defaults` blocks, in the order they apply.
+    #[inline]
+    pub fn defaults(&self) -> &[Defaults] {
+        &self.defaults
+    }
+
+    /// Timezone time constraints are evaluated in, the system one if `None`.
+    #[inline]
+    pub fn timezone(&self) -> Option<&CStr> {
+        self.timezone.as_deref()
+    }
+
+    /// The policy as JSON, see [`Json`] for the layout.
+    #[inline]
+    pub fn json(&self) -> Json<'_> {
+        Json(self)
+    }
+}
+
+/// The canonical form of the policy, with includes and aliases expanded.
+impl fmt::Display for Rules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(ref timezone) = self.timezone {
+            f.write_str("timezone \"")?;
+            parser::write_escaped(f, timezone.to_bytes(), b"\"")?;
+            writeln!(f, "\";")?;
+        }
+        let blocks = self
+            .defaults
+            .iter()
+            .map(|d| d as &dyn fmt::Display)
+            .chain(self.rules.iter().map(|r| r as &dyn fmt::Display));
+        for (i, block) in blocks.enumerate() {
+            if i != 0 || self.timezone.is_some() {
+                writeln!(f)?;
+            }
+            writeln!(f, "{}", block)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Vec<parser::Rule>> for Rules {
+    #[inline]
+    fn from(rules: Vec<parser::Rule>) -> Self {
+        Self::new(rules, Vec::new(), None)
+    }
+}
+
+/// The values in effect for a command: the built-in ones, overridden by the
+/// `defaults` blocks that apply and then by the matching rule, see
+/// [`crate::policy::settings`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    pub timeout: u64,
+    pub askpass: bool,
+    pub keepenv: bool,
+    pub use_pty: bool,
+    pub prompt_timeout: u32,
+    pub max_retries: usize,
+    pub secure_path: CString,
+    /// Password prompt template, the built-in one if `None`.
+    pub prompt: Option<CString>,
+    /// Variables whose values are checked, the built-in list if `None`, see
+    /// [`crate::env::is_safe`].
+    pub env_check: Option<Vec<VarPattern>>,
+    /// Where the password can be read from, anywhere if `None`.
+    pub password_from: Option<Vec<PasswordFrom>>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_SESSION_TIMEOUT,
+            askpass: true,
+            keepenv: false,
+            use_pty: false,
+            prompt_timeout: DEFAULT_PROMPT_TIMEOUT,
+            max_retries: DEFAULT_MAX_RETRIES,
+            secure_path: DEFAULT_SECURE_PATH.to_owned(),
+            prompt: None,
+            env_check: None,
+            password_from: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Whether the password can be read `from`.
+    #[inline]
+    pub fn allows_password_from(&self, from: PasswordFrom) -> bool {
+        self.password_from
+            .as_ref()
+            .is_none_or(|sources| sources.contains(&from))
+    }
+
+    pub fn apply_defaults(&mut self, defaults: &Defaults) {
+        if let Some(timeout) = defaults.timeout {
+            self.timeout = timeout;
+        }
+        if let Some(askpass) = defaults.askpass {
+            self.askpass = askpass;
+        }
+        if let Some(keepenv) = defaults.keepenv {
+            self.keepenv = keepenv;
+        }
+        if let Some(use_pty) = defaults.use_pty {
+            self.use_pty = use_pty;
+        }
+        if let Some(prompt_timeout) = defaults.prompt_timeout {
+            self.prompt_timeout = prompt_timeout;
+        }
+        if let Some(max_retries) = defaults.max_retries {
+            self.max_retries = max_retries;
+        }
+        if let Some(ref secure_path) = defaults.secure_path {
+            self.secure_path = secure_path.clone();
+        }
+        if let Some(ref prompt) = defaults.prompt {
+            self.prompt = Some(prompt.clone());
+        }
+        if let Some(ref env_check) = defaults.env_check {
+            self.env_check = Some(env_check.clone());
+        }
+        if let Some(ref password_from) = defaults.password_from {
+            self.password_from = Some(password_from.clone());
+        }
+    }
+
+    pub fn apply_rule(&mut self, rule: &Rule) {
+        if let Some(timeout) = rule.timeout {
+            self.timeout = timeout;
+        }
+        if let Some(askpass) = rule.askpass {
+            self.askpass = askpass;
+        }
+        if let Some(keepenv) = rule.keepenv {
+            self.keepenv = keepenv;
+        }
+        if let Some(use_pty) = rule.use_pty {
+            self.use_pty = use_pty;
+        }
+        if let Some(prompt_timeout) = rule.prompt_timeout {
+            self.prompt_timeout = prompt_timeout;
+        }
+        if let Some(max_retries) = rule.max_retries {
+            self.max_retries = max_retries;
+        }
+        if let Some(ref prompt) = rule.prompt {
+            self.prompt = Some(prompt.clone());
+        }
+        if let Some(ref secure_path) = rule.secure_path {
+            self.secure_path = secure_path.clone();
+        }
+        if let Some(ref env_check) = rule.env_check {
+            self.env_check = Some(env_check.clone());
+        }
+        if let Some(ref password_from) = rule.password_from {
+            self.password_from = Some(password_from.clone());
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Syntax(peg::error::ParseError<usize>),
+    UndefinedAlias {
+        location: usize,
+        name: String,
+    },
+    DuplicateAlias {
+        location: usize,
+        name: String,
+    },
+    RecursiveAlias {
+        location: usize,
+        name: String,
+    },
+    AliasKind {
+        location: usize,
+        name: String,
+        expected: AliasKind,
+    },
+    /// A doas `permit` after a `deny` that can match the same requests,
+    /// which doas would let win and pezzo would not.
+    PermitAfterDeny {
+        location: usize,
+    },
+}
+
+impl Error {
+    /// Byte offset in the parsed buffer.
+    pub fn location(&self) -> usize {
+        match self {
+            Self::Syntax(err) => err.location,
+            Self::UndefinedAlias { location, .. }
+            | Self::DuplicateAlias { location, .. }
+            | Self::RecursiveAlias { location, .. }
+            | Self::AliasKind { location, .. }
+            | Self::PermitAfterDeny { location } => *location,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax(err) => write!(f, "expected {}", err.expected),
+            Self::UndefinedAlias { name, .. } => write!(f, "undefined alias {name}"),
+            Self::DuplicateAlias { name, .. } => write!(f, "alias {name} has already been defined"),
+            Self::RecursiveAlias { name, .. } => write!(f, "alias {name} references itself"),
+            Self::AliasKind { name, expected, .. } => write!(f, "{name} is not a {expected} alias"),
+            Self::PermitAfterDeny { .. } => f.write_str(
+                "permit after a deny it overlaps, the deny would win; move it before the deny",
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The syntaxes a configuration file can be written in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Syntax {
+    #[default]
+    Pezzo,
+    Doas,
+}
+
+impl Syntax {
+    /// The syntax selected by a `# syntax: NAME` first line, if any.
+    pub fn from_header(buf: &[u8]) -> Option<Self> {
+        let line = buf.split(|&c| c == b'\n').next()?;
+        let name = std::str::from_utf8(line)
+            .ok()?
+            .strip_prefix('#')?
+            .trim_start()
+            .strip_prefix("syntax:")?
+            .trim();
+        match name {
+            "pezzo" => Some(Self::Pezzo),
+            "doas" => Some(Self::Doas),
+            _ => None,
+        }
+    }
+
+    /// `doas.conf` and `*.doas` files are in doas syntax.
+    pub fn from_path(path: &[u8]) -> Option<Self> {
+        let name = match memchr::memrchr(b'/', path) {
+            Some(pos) => &path[pos + 1..],
+            None => path,
+        };
+        if name == b"doas.conf" || name.ends_with(b".doas") {
+            Some(Self::Doas)
+        } else {
+            None
+        }
     }
 }
 
 #[inline]
-pub fn parse<B: AsRef<[u8]>>(buf: B) -> std::result::Result<Rules, peg::error::ParseError<usize>> {
-    parser::parse(buf.as_ref()).map(Rules)
+pub fn parse<B: AsRef<[u8]>>(buf: B) -> std::result::Result<Vec<Statement>, Error> {
+    parse_with_aliases(buf, &mut Aliases::default())
+}
+
+/// Like [`parse`], but aliases defined by previously parsed files are visible
+/// and the ones defined by `buf` are added to `aliases`.
+#[inline]
+pub fn parse_with_aliases<B: AsRef<[u8]>>(
+    buf: B,
+    aliases: &mut Aliases,
+) -> std::result::Result<Vec<Statement>, Error> {
+    let buf = buf.as_ref();
+    parse_as(buf, Syntax::from_header(buf).unwrap_or_default(), aliases)
+}
+
+/// Parses `buf` in the given syntax, doas files cannot use aliases.
+pub fn parse_as<B: AsRef<[u8]>>(
+    buf: B,
+    syntax: Syntax,
+    aliases: &mut Aliases,
+) -> std::result::Result<Vec<Statement>, Error> {
+    match syntax {
+        Syntax::Pezzo => parser::parse(buf.as_ref(), aliases),
+        Syntax::Doas => doas::parse(buf.as_ref()),
+    }
+}
+
+#[cfg(test)]
+impl Rules {
+    /// The policy made of already parsed statements, which cannot include
+    /// other files.
+    pub(crate) fn from_statements(statements: Vec<Statement>) -> Self {
+        let mut policy = Self::new(Vec::new(), Vec::new(), None);
+        for statement in statements {
+            match statement {
+                Statement::Rule(rule) => policy.rules.push(*rule),
+                Statement::Defaults(defaults) => policy.defaults.push(*defaults),
+                Statement::Timezone(tz) => policy.timezone = Some(tz),
+                Statement::Include(_) | Statement::IncludeDir(_) => unreachable!(),
+            }
+        }
+        policy
+    }
+}
+
+/// The rules of `conf`.
+#[cfg(test)]
+pub(crate) fn rules(conf: &str) -> Vec<Rule> {
+    Rules::from_statements(parse(conf).unwrap()).rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults() {
+        let conf = r#"defaults { timeout = 30; max_retries = 5; secure_path = "/usr/bin:/bin"; }
+            defaults { origin = :wheel; askpass = false; prompt_timeout = 60; }
+            rule { origin = alice; timeout = 0; }"#;
+        let policy = Rules::from_statements(parse(conf).unwrap());
+        let (defaults, rules) = (policy.defaults(), policy.rules());
+        assert_eq!(defaults.len(), 2);
+        assert!(defaults[0].origin.is_none());
+        assert!(defaults[1].origin.is_some());
+
+        let mut settings = Settings::default();
+        settings.apply_defaults(&defaults[0]);
+        assert_eq!(settings.timeout, 30);
+        assert_eq!(settings.max_retries, 5);
+        assert_eq!(settings.secure_path.as_c_str(), c"/usr/bin:/bin");
+        assert!(settings.askpass);
+        settings.apply_defaults(&defaults[1]);
+        settings.apply_rule(&rules[0]);
+        assert_eq!(settings.timeout, 0);
+        assert_eq!(settings.prompt_timeout, 60);
+        assert!(!settings.askpass);
+
+        let text = policy.to_string();
+        assert!(text.starts_with("defaults {\n    timeout = 30;\n"));
+        let reparsed = parse(&text).unwrap();
+        assert_eq!(reparsed.len(), 3);
+        assert!(policy.json().to_string().contains(
+            r#""defaults":[{"origin":null,"timeout":30,"askpass":null,"keepenv":null,"use_pty":null,"prompt_timeout":null,"max_retries":5,"secure_path":"/usr/bin:/bin","prompt":null,"env_check":null,"password_from":null}"#
+        ));
+
+        for bad in [
+            "defaults { max_retries = 0; }",
+            "defaults { secure_path = \"bin:/usr/bin\"; }",
+            "defaults { timeout = 1; timeout = 2; }",
+            "defaults { exe = /usr/bin/id; }",
+        ] {
+            assert!(parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn secure_path() {
+        let mut settings = Settings::default();
+        assert_eq!(
+            settings.secure_path.as_c_str(),
+            c"/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"
+        );
+        let rules = rules(r#"rule { origin = alice; secure_path = "/opt/tools/bin"; }"#);
+        settings.apply_rule(&rules[0]);
+        assert_eq!(settings.secure_path.as_c_str(), c"/opt/tools/bin");
+        assert!(rules[0]
+            .to_string()
+            .contains("    secure_path = \"/opt/tools/bin\";\n"));
+        assert!(parse(r#"rule { origin = alice; secure_path = "/bin:."; }"#).is_err());
+    }
+
+    #[test]
+    fn password_from() {
+        let conf = r#"defaults { password_from = tty | askpass; }
+            rule { origin = alice; password_from = stdin; }"#;
+        let policy = Rules::from_statements(parse(conf).unwrap());
+        let (defaults, rules) = (policy.defaults(), policy.rules());
+
+        let mut settings = Settings::default();
+        assert!(settings.allows_password_from(PasswordFrom::Stdin));
+        settings.apply_defaults(&defaults[0]);
+        assert!(settings.allows_password_from(PasswordFrom::Tty));
+        assert!(settings.allows_password_from(PasswordFrom::Askpass));
+        assert!(!settings.allows_password_from(PasswordFrom::Stdin));
+        settings.apply_rule(&rules[0]);
+        assert!(!settings.allows_password_from(PasswordFrom::Tty));
+        assert!(settings.allows_password_from(PasswordFrom::Stdin));
+
+        let text = policy.to_string();
+        assert!(text.contains("    password_from = tty | askpass;\n"));
+        assert!(text.contains("    password_from = stdin;\n"));
+        assert_eq!(parse(&text).unwrap().len(), 2);
+        assert!(policy
+            .json()
+            .to_string()
+            .contains(r#""password_from":["tty","askpass"]}"#));
+
+        for bad in [
+            "defaults { password_from = ; }",
+            "defaults { password_from = pipe; }",
+            "rule { origin = alice; password_from = tty; password_from = stdin; }",
+        ] {
+            assert!(parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn use_pty() {
+        let conf = r#"defaults { use_pty = true; }
+            rule { origin = alice; use_pty = false; }"#;
+        let policy = Rules::from_statements(parse(conf).unwrap());
+        let (defaults, rules) = (policy.defaults(), policy.rules());
+
+        let mut settings = Settings::default();
+        assert!(!settings.use_pty);
+        settings.apply_defaults(&defaults[0]);
+        assert!(settings.use_pty);
+        settings.apply_rule(&rules[0]);
+        assert!(!settings.use_pty);
+
+        let text = policy.to_string();
+        assert!(text.contains("    use_pty = true;\n"));
+        assert!(text.contains("    use_pty = false;\n"));
+        assert_eq!(parse(&text).unwrap().len(), 2);
+        assert!(policy.json().to_string().contains(r#""use_pty":true"#));
+
+        assert!(parse("rule { origin = alice; use_pty = true; use_pty = true; }").is_err());
+    }
 }
@@ -1,24 +1,130 @@
-use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use super::Error;
+use globset::{Glob, GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
 use std::{
-    ffi::{CString, OsStr, OsString},
+    cell::RefCell,
+    collections::HashMap,
+    ffi::{CStr, CString, OsStr, OsString},
+    fmt,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::Path,
     rc::Rc,
 };
 
+/// A user or a group, either by name or by numeric ID (`#1001`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Ident {
+    Name(CString),
+    Id(u32),
+}
+
 #[derive(Debug, Clone)]
 pub enum Origin {
-    User(Vec<CString>),
-    Group(Vec<CString>),
+    User(Vec<Ident>),
+    Group(Vec<Ident>),
 }
 
 #[derive(Debug, Clone)]
 pub enum Target {
-    User(Vec<CString>),
-    UserGroup(Vec<CString>, Vec<CString>),
+    User(Vec<Ident>),
+    UserGroup(Vec<Ident>, Vec<Ident>),
+}
+
+/// Writes `buf` escaping `special` characters and backslashes.
+/// Bytes that are not valid UTF-8 are written as `\xNN`.
+pub(super) fn write_escaped(f: &mut fmt::Formatter<'_>, buf: &[u8], special: &[u8]) -> fmt::Result {
+    for chunk in buf.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if c == '\\' || (c.is_ascii() && special.contains(&(c as u8))) {
+                f.write_str("\\")?;
+            }
+            write!(f, "{}", c)?;
+        }
+        for b in chunk.invalid() {
+            write!(f, "\\x{:02x}", b)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_list<T: fmt::Display>(f: &mut fmt::Formatter<'_>, items: &[T]) -> fmt::Result {
+    if let [item] = items {
+        return item.fmt(f);
+    }
+
+    f.write_str("(")?;
+    write_joined(f, items, " | ")?;
+    f.write_str(")")
+}
+
+fn write_joined<T: fmt::Display>(
+    f: &mut fmt::Formatter<'_>,
+    items: &[T],
+    sep: &str,
+) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i != 0 {
+            f.write_str(sep)?;
+        }
+        item.fmt(f)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Ident {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Id(id) => return write!(f, "#{}", id),
+            Self::Name(name) => name.to_bytes(),
+        };
+
+        let portable = |c: &u8| c.is_ascii_alphanumeric() || matches!(c, b'_' | b'.' | b'-');
+        let body = name.strip_suffix(b"$").unwrap_or(name);
+        let alias_like = name
+            .iter()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || *c == b'_');
+        if !body.is_empty() && body[0] != b'-' && body.iter().all(portable) && !alias_like {
+            f.write_str(&String::from_utf8_lossy(name))
+        } else {
+            f.write_str("\"")?;
+            write_escaped(f, name, b"\"")?;
+            f.write_str("\"")
+        }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::User(users) => write_list(f, users),
+            Self::Group(groups) => {
+                f.write_str(":")?;
+                write_list(f, groups)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::User(users) => write_list(f, users),
+            Self::UserGroup(users, groups) => {
+                write_list(f, users)?;
+                f.write_str(":")?;
+                write_list(f, groups)
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum EnvTemplatePart {
+    /// `$VAR` or `${VAR}`, nothing if unset.
     Var(Box<OsStr>),
+    /// `${VAR:-default}`, `default` if unset or empty.
+    VarOr(Box<OsStr>, Box<OsStr>),
+    /// `${VAR:?}`, an error if unset or empty.
+    Required(Box<OsStr>),
     Str(Box<OsStr>),
 }
 
@@ -26,14 +132,48 @@ pub enum EnvTemplatePart {
 pub struct EnvTemplate(Rc<Box<[EnvTemplatePart]>>);
 
 impl EnvTemplate {
-    pub fn format(&self) -> Box<OsStr> {
+    #[inline]
+    pub fn new(parts: Vec<EnvTemplatePart>) -> Self {
+        Self(Rc::new(parts.into_boxed_slice()))
+    }
+
+    #[inline]
+    pub fn parts(&self) -> &[EnvTemplatePart] {
+        &self.0
+    }
+
+    /// Whether the template is `""`, which sets the variable to the empty
+    /// string.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Expands the template, `lookup` gives the value of a variable. Fails
+    /// with the name of a `${VAR:?}` variable that is unset or empty.
+    pub fn format<F>(&self, mut lookup: F) -> Result<OsString, &OsStr>
+    where
+        F: FnMut(&OsStr) -> Option<OsString>,
+    {
         let mut buf = OsString::new();
 
         for p in &**self.0 {
             match p {
                 EnvTemplatePart::Var(ref name) => {
-                    if let Ok(txt) = std::env::var(name) {
-                        buf.push(txt);
+                    if let Some(value) = lookup(name) {
+                        buf.push(value);
+                    }
+                }
+                EnvTemplatePart::VarOr(ref name, ref default) => {
+                    match lookup(name).filter(|value| !value.is_empty()) {
+                        Some(value) => buf.push(value),
+                        None => buf.push(default),
+                    }
+                }
+                EnvTemplatePart::Required(ref name) => {
+                    match lookup(name).filter(|value| !value.is_empty()) {
+                        Some(value) => buf.push(value),
+                        None => return Err(name),
                     }
                 }
                 EnvTemplatePart::Str(ref txt) => {
@@ -42,36 +182,875 @@ impl EnvTemplate {
             }
         }
 
-        buf.into_boxed_os_str()
+        Ok(buf)
+    }
+}
+
+impl fmt::Display for EnvTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for p in &**self.0 {
+            match p {
+                EnvTemplatePart::Var(name) => write!(f, "${{{}}}", name.to_string_lossy())?,
+                EnvTemplatePart::VarOr(name, default) => {
+                    write!(f, "${{{}:-", name.to_string_lossy())?;
+                    write_escaped(f, default.as_bytes(), b"$\"}")?;
+                    f.write_str("}")?;
+                }
+                EnvTemplatePart::Required(name) => write!(f, "${{{}:?}}", name.to_string_lossy())?,
+                EnvTemplatePart::Str(txt) => write_escaped(f, txt.as_bytes(), b"$\"")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A variable name, possibly with `*` and `?` wildcards (`LC_*`).
+#[derive(Debug, Clone)]
+pub struct VarPattern {
+    name: Rc<Box<OsStr>>,
+    matcher: Option<GlobMatcher>,
+}
+
+impl VarPattern {
+    pub fn new(name: Rc<Box<OsStr>>) -> Result<Self, globset::Error> {
+        let matcher = if name.as_bytes().contains(&b'*') || name.as_bytes().contains(&b'?') {
+            Some(Glob::new(&name.to_string_lossy())?.compile_matcher())
+        } else {
+            None
+        };
+        Ok(Self { name, matcher })
+    }
+
+    #[inline]
+    pub fn literal(name: Rc<Box<OsStr>>) -> Self {
+        Self {
+            name,
+            matcher: None,
+        }
+    }
+
+    #[inline]
+    pub fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    #[inline]
+    pub fn is_glob(&self) -> bool {
+        self.matcher.is_some()
+    }
+
+    pub fn is_match<S: AsRef<OsStr>>(&self, name: S) -> bool {
+        match self.matcher {
+            Some(ref matcher) => matcher.is_match(name.as_ref()),
+            None => **self.name == *name.as_ref(),
+        }
+    }
+}
+
+impl PartialEq for VarPattern {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for VarPattern {}
+
+impl fmt::Display for VarPattern {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name.to_string_lossy())
     }
 }
 
 #[derive(Debug, Clone)]
 pub enum Env {
-    Unset(Rc<Box<OsStr>>),
-    Copy(Rc<Box<OsStr>>),
+    Unset(VarPattern),
+    Copy(VarPattern),
     Set(Rc<Box<OsStr>>, EnvTemplate),
 }
 
+impl fmt::Display for Env {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unset(pattern) => write!(f, "-{}", pattern),
+            Self::Copy(pattern) => write!(f, "{}", pattern),
+            Self::Set(name, template) => write!(f, "{}=\"{}\"", name.to_string_lossy(), template),
+        }
+    }
+}
+
+/// A compiled [`GlobSet`] that keeps the patterns it was built from.
+#[derive(Debug, Clone)]
+pub struct Patterns {
+    globs: Box<[Glob]>,
+    set: GlobSet,
+}
+
+impl Patterns {
+    pub fn new(globs: Vec<Glob>) -> Result<Self, globset::Error> {
+        let mut builder = GlobSetBuilder::new();
+        for glob in &globs {
+            builder.add(glob.clone());
+        }
+        Ok(Self {
+            set: builder.build()?,
+            globs: globs.into_boxed_slice(),
+        })
+    }
+
+    #[inline]
+    pub fn is_match<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.set.is_match(path)
+    }
+
+    #[inline]
+    pub fn globs(&self) -> &[Glob] {
+        &self.globs
+    }
+}
+
+impl fmt::Display for Patterns {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, glob) in self.globs.iter().enumerate() {
+            if i != 0 {
+                f.write_str(" | ")?;
+            }
+            write_escaped(f, glob.glob().as_bytes(), b" |;:")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ArgPattern {
+    Exact(Box<OsStr>),
+    Glob(GlobMatcher),
+}
+
+impl ArgPattern {
+    #[inline]
+    pub fn is_match<S: AsRef<OsStr>>(&self, arg: S) -> bool {
+        match self {
+            Self::Exact(exact) => &**exact == arg.as_ref(),
+            Self::Glob(glob) => glob.is_match(arg.as_ref()),
+        }
+    }
+}
+
+impl fmt::Display for ArgPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exact(exact) => {
+                f.write_str("\"")?;
+                write_escaped(f, exact.as_bytes(), b"\"")?;
+                f.write_str("\"")
+            }
+            Self::Glob(glob) => write_escaped(f, glob.glob().glob().as_bytes(), b" |;]\""),
+        }
+    }
+}
+
+/// Every pattern matches exactly one argument, the whole argument vector must
+/// be matched unless `variadic` is set, in which case any number of arguments
+/// can follow the patterns.
+#[derive(Debug, Clone)]
+pub struct Args {
+    pub patterns: Box<[ArgPattern]>,
+    pub variadic: bool,
+}
+
+impl Args {
+    pub fn is_match<S: AsRef<OsStr>>(&self, args: &[S]) -> bool {
+        if args.len() < self.patterns.len() || (!self.variadic && args.len() != self.patterns.len())
+        {
+            return false;
+        }
+
+        self.patterns
+            .iter()
+            .zip(args)
+            .all(|(pattern, arg)| pattern.is_match(arg))
+    }
+}
+
+impl fmt::Display for Args {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[")?;
+        for (i, pattern) in self.patterns.iter().enumerate() {
+            if i != 0 {
+                f.write_str(" ")?;
+            }
+            pattern.fmt(f)?;
+        }
+        match (self.variadic, self.patterns.is_empty()) {
+            (true, true) => f.write_str("...]"),
+            (true, false) => f.write_str(" ...]"),
+            (false, _) => f.write_str("]"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Action {
+    #[default]
+    Permit,
+    Deny,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Permit => "permit",
+            Self::Deny => "deny",
+        })
+    }
+}
+
+/// Where the password can be read from: the terminal, the standard input
+/// with `pezzo -S` or the askpass program with `pezzo -A`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordFrom {
+    Tty,
+    Stdin,
+    Askpass,
+}
+
+impl fmt::Display for PasswordFrom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Tty => "tty",
+            Self::Stdin => "stdin",
+            Self::Askpass => "askpass",
+        })
+    }
+}
+
+/// A calendar date and a time of day in minutes since midnight. `24:00` is
+/// allowed and marks the end of the day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub minutes: u16,
+}
+
+impl DateTime {
+    /// Days since Sunday, in the proleptic Gregorian calendar.
+    pub fn weekday(&self) -> u8 {
+        const OFFSETS: [u16; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+
+        let year = if self.month < 3 {
+            self.year.saturating_sub(1)
+        } else {
+            self.year
+        } as u32;
+        let days = year + year / 4 - year / 100
+            + year / 400
+            + u32::from(OFFSETS[usize::from(self.month - 1)])
+            + u32::from(self.day);
+        (days % 7) as u8
+    }
+
+    /// Days in `month` of `year`, in the proleptic Gregorian calendar.
+    pub fn days_in_month(year: u16, month: u8) -> u8 {
+        match month {
+            2 if year.is_multiple_of(4)
+                && (!year.is_multiple_of(100) || year.is_multiple_of(400)) =>
+            {
+                29
+            }
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)?;
+        if self.minutes != 0 && self.minutes != 24 * 60 {
+            write!(f, " {:02}:{:02}", self.minutes / 60, self.minutes % 60)?;
+        }
+        Ok(())
+    }
+}
+
+/// Local wall-clock time, `weekday` counts from Sunday (0).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+    pub datetime: DateTime,
+    pub weekday: u8,
+}
+
+/// A time window repeating on `days` (a bitmask indexed by weekday). When
+/// `end` is not after `start` the window spans midnight and belongs to the day
+/// it starts on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hours {
+    pub days: u8,
+    pub start: u16,
+    pub end: u16,
+}
+
+impl Hours {
+    pub fn is_match(&self, now: &LocalTime) -> bool {
+        let today = self.days & (1 << now.weekday) != 0;
+        let minutes = now.datetime.minutes;
+
+        if self.start < self.end {
+            today && (self.start..self.end).contains(&minutes)
+        } else {
+            let yesterday = self.days & (1 << ((now.weekday + 6) % 7)) != 0;
+            (today && minutes >= self.start) || (yesterday && minutes < self.end)
+        }
+    }
+}
+
+impl fmt::Display for Hours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const NAMES: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+        if self.days & 0x7f != 0x7f {
+            let mut first = true;
+            let mut day = 0;
+            while day < 7 {
+                if self.days & (1 << day) == 0 {
+                    day += 1;
+                    continue;
+                }
+                let start = day;
+                while day < 6 && self.days & (1 << (day + 1)) != 0 {
+                    day += 1;
+                }
+                if !first {
+                    f.write_str(",")?;
+                }
+                first = false;
+                f.write_str(NAMES[start])?;
+                if day != start {
+                    write!(f, "-{}", NAMES[day])?;
+                }
+                day += 1;
+            }
+            f.write_str(" ")?;
+        }
+
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+fn digits(buf: &[u8]) -> u16 {
+    buf.iter().fold(0, |acc, &c| acc * 10 + u16::from(c - b'0'))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AliasKind {
+    Users,
+    Groups,
+    Hosts,
+    Commands,
+}
+
+impl fmt::Display for AliasKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Users => "users",
+            Self::Groups => "groups",
+            Self::Hosts => "hosts",
+            Self::Commands => "commands",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum AliasItem {
+    Ident(Ident),
+    Glob(Glob),
+    Ref(String, usize),
+}
+
+#[derive(Debug, Clone)]
+struct AliasDef {
+    kind: AliasKind,
+    name: String,
+    location: usize,
+    items: Vec<AliasItem>,
+}
+
+#[derive(Debug, Clone)]
+struct Alias {
+    kind: AliasKind,
+    idents: Vec<Ident>,
+    globs: Vec<Glob>,
+}
+
+/// Named lists of users, groups, hosts or commands, shared by every file of
+/// a configuration. Aliases must be defined in the file that uses them or in
+/// a file loaded before it.
+#[derive(Debug, Default)]
+pub struct Aliases(HashMap<String, Alias>);
+
+impl Aliases {
+    fn define(&mut self, defs: Vec<AliasDef>) -> Result<(), Error> {
+        let mut pending = HashMap::with_capacity(defs.len());
+        for def in &defs {
+            if self.0.contains_key(&def.name) || pending.contains_key(def.name.as_str()) {
+                return Err(Error::DuplicateAlias {
+                    location: def.location,
+                    name: def.name.clone(),
+                });
+            }
+            pending.insert(def.name.as_str(), def);
+        }
+
+        let mut stack = Vec::new();
+        for def in &defs {
+            self.resolve(def, &pending, &mut stack)?;
+        }
+
+        Ok(())
+    }
+
+    fn resolve<'a>(
+        &mut self,
+        def: &'a AliasDef,
+        pending: &HashMap<&str, &'a AliasDef>,
+        stack: &mut Vec<&'a str>,
+    ) -> Result<(), Error> {
+        if self.0.contains_key(&def.name) {
+            return Ok(());
+        }
+
+        stack.push(&def.name);
+        let mut alias = Alias {
+            kind: def.kind,
+            idents: Vec::new(),
+            globs: Vec::new(),
+        };
+        for item in &def.items {
+            match item {
+                AliasItem::Ident(ident) => alias.idents.push(ident.clone()),
+                AliasItem::Glob(glob) => alias.globs.push(glob.clone()),
+                AliasItem::Ref(name, location) => {
+                    if stack.contains(&name.as_str()) {
+                        return Err(Error::RecursiveAlias {
+                            location: *location,
+                            name: name.clone(),
+                        });
+                    }
+                    if !self.0.contains_key(name) {
+                        match pending.get(name.as_str()) {
+                            Some(other) => self.resolve(other, pending, stack)?,
+                            None => {
+                                return Err(Error::UndefinedAlias {
+                                    location: *location,
+                                    name: name.clone(),
+                                })
+                            }
+                        }
+                    }
+
+                    let other = &self.0[name];
+                    if other.kind != def.kind {
+                        return Err(Error::AliasKind {
+                            location: *location,
+                            name: name.clone(),
+                            expected: def.kind,
+                        });
+                    }
+                    alias.idents.extend_from_slice(&other.idents);
+                    alias.globs.extend_from_slice(&other.globs);
+                }
+            }
+        }
+        stack.pop();
+        self.0.insert(def.name.clone(), alias);
+
+        Ok(())
+    }
+}
+
+/// The configuration is parsed twice: the first pass only collects alias
+/// definitions, so that rules can reference aliases defined further down the
+/// file, the second one expands references and builds the rules.
+struct State<'a> {
+    aliases: &'a Aliases,
+    collect: bool,
+    definitions: RefCell<Vec<AliasDef>>,
+    errors: RefCell<Vec<Error>>,
+}
+
+impl<'a> State<'a> {
+    fn new(aliases: &'a Aliases, collect: bool) -> Self {
+        Self {
+            aliases,
+            collect,
+            definitions: RefCell::new(Vec::new()),
+            errors: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn define(&self, kind: AliasKind, name: &[u8], location: usize, items: Vec<AliasItem>) {
+        if self.collect {
+            self.definitions.borrow_mut().push(AliasDef {
+                kind,
+                name: String::from_utf8_lossy(name).into_owned(),
+                location,
+                items,
+            });
+        }
+    }
+
+    fn lookup(&self, kind: AliasKind, name: &[u8], location: usize) -> Option<&'a Alias> {
+        if self.collect {
+            return None;
+        }
+
+        let name = String::from_utf8_lossy(name).into_owned();
+        let error = match self.aliases.0.get(&name) {
+            Some(alias) if alias.kind == kind => return Some(alias),
+            Some(_) => Error::AliasKind {
+                location,
+                name,
+                expected: kind,
+            },
+            None => Error::UndefinedAlias { location, name },
+        };
+        self.errors.borrow_mut().push(error);
+        None
+    }
+
+    fn idents(&self, kind: AliasKind, name: &[u8], location: usize) -> Vec<Ident> {
+        self.lookup(kind, name, location)
+            .map(|alias| alias.idents.clone())
+            .unwrap_or_default()
+    }
+
+    fn globs(&self, kind: AliasKind, name: &[u8], location: usize) -> Vec<Glob> {
+        self.lookup(kind, name, location)
+            .map(|alias| alias.globs.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Parses a configuration file, resolving alias references against `aliases`
+/// and adding the aliases the file defines to it.
+pub fn parse(buf: &[u8], aliases: &mut Aliases) -> Result<Vec<Statement>, Error> {
+    let state = State::new(aliases, true);
+    config::parse(buf, &state).map_err(Error::Syntax)?;
+    let definitions = state.definitions.into_inner();
+    aliases.define(definitions)?;
+
+    let state = State::new(aliases, false);
+    let statements = config::parse(buf, &state).map_err(Error::Syntax)?;
+    match state
+        .errors
+        .into_inner()
+        .into_iter()
+        .min_by_key(Error::location)
+    {
+        Some(err) => Err(err),
+        None => Ok(statements),
+    }
+}
+
+#[derive(Default)]
 struct Builder {
+    action: Option<Action>,
     origin: Option<Vec<Origin>>,
     target: Option<Vec<Target>>,
-    exe: Option<GlobSet>,
+    host: Option<Patterns>,
+    exe: Option<Patterns>,
+    args: Option<Vec<Args>>,
+    edit: Option<Patterns>,
     timeout: Option<u64>,
     askpass: Option<bool>,
     keepenv: Option<bool>,
+    use_pty: Option<bool>,
     setenv: Option<Box<[Env]>>,
+    valid_from: Option<DateTime>,
+    valid_until: Option<DateTime>,
+    hours: Option<Vec<Hours>>,
+    prompt_timeout: Option<u32>,
+    max_retries: Option<usize>,
+    secure_path: Option<CString>,
+    prompt: Option<CString>,
+    env_check: Option<Vec<VarPattern>>,
+    envfile: Option<CString>,
+    password_from: Option<Vec<PasswordFrom>>,
 }
 
 #[derive(Debug, Clone)]
+pub enum Statement {
+    Rule(Box<Rule>),
+    Defaults(Box<Defaults>),
+    Include(CString),
+    IncludeDir(CString),
+    Timezone(CString),
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct Rule {
+    pub action: Action,
     pub origin: Vec<Origin>,
     pub target: Option<Vec<Target>>,
+    pub host: Option<Patterns>,
     pub timeout: Option<u64>,
     pub askpass: Option<bool>,
-    pub exe: Option<GlobSet>,
+    pub exe: Option<Patterns>,
+    pub args: Option<Vec<Args>>,
+    /// Files that can be edited with `pezzo -e`. Rules with `edit` only
+    /// match edit requests. Permit rules without it only match commands,
+    /// deny rules without it match both.
+    pub edit: Option<Patterns>,
     pub keepenv: Option<bool>,
+    /// Run the command on a new pseudo-terminal, see
+    /// [`crate::unix::pty::run`].
+    pub use_pty: Option<bool>,
     pub setenv: Option<Box<[Env]>>,
+    pub valid_from: Option<DateTime>,
+    pub valid_until: Option<DateTime>,
+    pub hours: Option<Vec<Hours>>,
+    pub prompt_timeout: Option<u32>,
+    pub max_retries: Option<usize>,
+    /// Password prompt, with the escapes of
+    /// [`crate::unix::pam::expand_prompt`].
+    pub prompt: Option<CString>,
+    /// PATH of the command. The command itself is looked up in the one of
+    /// the `defaults` blocks, before any rule is matched.
+    pub secure_path: Option<CString>,
+    /// Variables dropped when their value, taken from the invoking user,
+    /// contains `/` or `%`.
+    pub env_check: Option<Vec<VarPattern>>,
+    /// Root-owned file of `KEY=VALUE` lines added to the environment after
+    /// `keepenv` and `setenv`, see [`crate::env::parse_envfile`].
+    pub envfile: Option<CString>,
+    /// Where the password can be read from, anywhere if `None`.
+    pub password_from: Option<Vec<PasswordFrom>>,
+}
+
+impl Rule {
+    /// Whether `now` falls in the validity period and in one of the time
+    /// windows of the rule.
+    pub fn is_active(&self, now: &LocalTime) -> bool {
+        self.valid_from.is_none_or(|from| now.datetime >= from)
+            && !self.is_expired(now)
+            && self
+                .hours
+                .as_ref()
+                .is_none_or(|hours| hours.iter().any(|h| h.is_match(now)))
+    }
+
+    #[inline]
+    pub fn is_expired(&self, now: &LocalTime) -> bool {
+        self.valid_until.is_some_and(|until| now.datetime >= until)
+    }
+}
+
+/// A date written with its time unless it is `default`, the time the parser
+/// assumes when none is given.
+struct Bound(DateTime, u16);
+
+impl fmt::Display for Bound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self(datetime, default) = *self;
+        write!(
+            f,
+            "{:04}-{:02}-{:02}",
+            datetime.year, datetime.month, datetime.day
+        )?;
+        if datetime.minutes != default {
+            write!(
+                f,
+                " {:02}:{:02}",
+                datetime.minutes / 60,
+                datetime.minutes % 60
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The canonical form of the rule, which parses back into the same rule.
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "rule {{")?;
+        writeln!(f, "    action = {};", self.action)?;
+        f.write_str("    origin = ")?;
+        write_joined(f, &self.origin, " | ")?;
+        writeln!(f, ";")?;
+        if let Some(ref target) = self.target {
+            f.write_str("    target = ")?;
+            write_joined(f, target, " | ")?;
+            writeln!(f, ";")?;
+        }
+        if let Some(ref host) = self.host {
+            writeln!(f, "    host = {};", host)?;
+        }
+        if let Some(ref exe) = self.exe {
+            writeln!(f, "    exe = {};", exe)?;
+        }
+        if let Some(ref args) = self.args {
+            f.write_str("    args = ")?;
+            write_joined(f, args, " | ")?;
+            writeln!(f, ";")?;
+        }
+        if let Some(ref edit) = self.edit {
+            writeln!(f, "    edit = {};", edit)?;
+        }
+        if let Some(timeout) = self.timeout {
+            writeln!(f, "    timeout = {};", timeout)?;
+        }
+        if let Some(askpass) = self.askpass {
+            writeln!(f, "    askpass = {};", askpass)?;
+        }
+        if let Some(keepenv) = self.keepenv {
+            writeln!(f, "    keepenv = {};", keepenv)?;
+        }
+        if let Some(use_pty) = self.use_pty {
+            writeln!(f, "    use_pty = {};", use_pty)?;
+        }
+        if let Some(ref setenv) = self.setenv {
+            f.write_str("    setenv = { ")?;
+            write_joined(f, setenv, ", ")?;
+            writeln!(f, " }};")?;
+        }
+        if let Some(from) = self.valid_from {
+            writeln!(f, "    valid_from = {};", Bound(from, 0))?;
+        }
+        if let Some(until) = self.valid_until {
+            writeln!(f, "    valid_until = {};", Bound(until, 24 * 60))?;
+        }
+        if let Some(ref hours) = self.hours {
+            f.write_str("    hours = ")?;
+            write_joined(f, hours, " | ")?;
+            writeln!(f, ";")?;
+        }
+        write_settings(
+            f,
+            self.prompt_timeout,
+            self.max_retries,
+            self.prompt.as_deref(),
+            self.secure_path.as_deref(),
+            self.env_check.as_deref(),
+            self.password_from.as_deref(),
+        )?;
+        if let Some(ref envfile) = self.envfile {
+            f.write_str("    envfile = \"")?;
+            write_escaped(f, envfile.to_bytes(), b"\"")?;
+            writeln!(f, "\";")?;
+        }
+        f.write_str("}")
+    }
+}
+
+/// Settings both rules and `defaults` blocks have.
+fn write_settings(
+    f: &mut fmt::Formatter<'_>,
+    prompt_timeout: Option<u32>,
+    max_retries: Option<usize>,
+    prompt: Option<&CStr>,
+    secure_path: Option<&CStr>,
+    env_check: Option<&[VarPattern]>,
+    password_from: Option<&[PasswordFrom]>,
+) -> fmt::Result {
+    if let Some(prompt_timeout) = prompt_timeout {
+        writeln!(f, "    prompt_timeout = {};", prompt_timeout)?;
+    }
+    if let Some(max_retries) = max_retries {
+        writeln!(f, "    max_retries = {};", max_retries)?;
+    }
+    if let Some(prompt) = prompt {
+        f.write_str("    prompt = \"")?;
+        write_escaped(f, prompt.to_bytes(), b"\"")?;
+        writeln!(f, "\";")?;
+    }
+    if let Some(secure_path) = secure_path {
+        f.write_str("    secure_path = \"")?;
+        write_escaped(f, secure_path.to_bytes(), b"\"")?;
+        writeln!(f, "\";")?;
+    }
+    if let Some(env_check) = env_check {
+        f.write_str("    env_check = { ")?;
+        write_joined(f, env_check, ", ")?;
+        writeln!(f, " }};")?;
+    }
+    if let Some(password_from) = password_from {
+        f.write_str("    password_from = ")?;
+        write_joined(f, password_from, " | ")?;
+        writeln!(f, ";")?;
+    }
+    Ok(())
+}
+
+/// Policy-wide values from a `defaults` block, for everyone or only for
+/// `origin`. Rules override them.
+#[derive(Debug, Clone, Default)]
+pub struct Defaults {
+    pub origin: Option<Vec<Origin>>,
+    pub timeout: Option<u64>,
+    pub askpass: Option<bool>,
+    pub keepenv: Option<bool>,
+    pub use_pty: Option<bool>,
+    pub prompt_timeout: Option<u32>,
+    pub max_retries: Option<usize>,
+    pub secure_path: Option<CString>,
+    pub prompt: Option<CString>,
+    pub env_check: Option<Vec<VarPattern>>,
+    pub password_from: Option<Vec<PasswordFrom>>,
+}
+
+impl fmt::Display for Defaults {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "defaults {{")?;
+        if let Some(ref origin) = self.origin {
+            f.write_str("    origin = ")?;
+            write_joined(f, origin, " | ")?;
+            writeln!(f, ";")?;
+        }
+        if let Some(timeout) = self.timeout {
+            writeln!(f, "    timeout = {};", timeout)?;
+        }
+        if let Some(askpass) = self.askpass {
+            writeln!(f, "    askpass = {};", askpass)?;
+        }
+        if let Some(keepenv) = self.keepenv {
+            writeln!(f, "    keepenv = {};", keepenv)?;
+        }
+        if let Some(use_pty) = self.use_pty {
+            writeln!(f, "    use_pty = {};", use_pty)?;
+        }
+        write_settings(
+            f,
+            self.prompt_timeout,
+            self.max_retries,
+            self.prompt.as_deref(),
+            self.secure_path.as_deref(),
+            self.env_check.as_deref(),
+            self.password_from.as_deref(),
+        )?;
+        f.write_str("}")
+    }
+}
+
+impl From<Action> for Builder {
+    #[inline]
+    fn from(action: Action) -> Self {
+        Self {
+            action: Some(action),
+            ..Default::default()
+        }
+    }
 }
 
 impl From<Vec<Origin>> for Builder {
@@ -79,12 +1058,7 @@ impl From<Vec<Origin>> for Builder {
     fn from(origin: Vec<Origin>) -> Self {
         Self {
             origin: Some(origin),
-            target: None,
-            exe: None,
-            timeout: None,
-            askpass: None,
-            keepenv: None,
-            setenv: None,
+            ..Default::default()
         }
     }
 }
@@ -93,28 +1067,28 @@ impl From<Vec<Target>> for Builder {
     #[inline]
     fn from(target: Vec<Target>) -> Self {
         Self {
-            origin: None,
             target: Some(target),
-            exe: None,
-            timeout: None,
-            askpass: None,
-            keepenv: None,
-            setenv: None,
+            ..Default::default()
         }
     }
 }
 
-impl From<GlobSet> for Builder {
+impl From<Patterns> for Builder {
     #[inline]
-    fn from(exe: GlobSet) -> Self {
+    fn from(exe: Patterns) -> Self {
         Self {
-            origin: None,
-            target: None,
             exe: Some(exe),
-            timeout: None,
-            askpass: None,
-            keepenv: None,
-            setenv: None,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Vec<Args>> for Builder {
+    #[inline]
+    fn from(args: Vec<Args>) -> Self {
+        Self {
+            args: Some(args),
+            ..Default::default()
         }
     }
 }
@@ -123,13 +1097,8 @@ impl From<Box<[Env]>> for Builder {
     #[inline]
     fn from(value: Box<[Env]>) -> Self {
         Self {
-            origin: None,
-            target: None,
-            exe: None,
-            timeout: None,
-            askpass: None,
-            keepenv: None,
             setenv: Some(value),
+            ..Default::default()
         }
     }
 }
@@ -138,15 +1107,36 @@ impl Builder {
     pub fn merge(
         &mut self,
         Self {
+            action,
             origin,
             target,
+            host,
             exe,
+            args,
+            edit,
             timeout,
             askpass,
             keepenv,
+            use_pty,
             setenv,
+            valid_from,
+            valid_until,
+            hours,
+            prompt_timeout,
+            max_retries,
+            secure_path,
+            prompt,
+            env_check,
+            envfile,
+            password_from,
         }: Self,
     ) -> Result<(), &'static str> {
+        if let Some(action) = action {
+            if self.action.is_some() {
+                return Err("action has already been defined");
+            }
+            self.action = Some(action);
+        }
         if let Some(origin) = origin {
             if self.origin.is_some() {
                 return Err("origin has already been defined");
@@ -159,11 +1149,29 @@ impl Builder {
             }
             self.target = Some(target);
         }
+        if let Some(host) = host {
+            if self.host.is_some() {
+                return Err("host has already been defined");
+            }
+            self.host = Some(host);
+        }
         if let Some(exe) = exe {
             if self.exe.is_some() {
                 return Err("exe has already been defined");
             }
-            self.exe = Some(exe);
+            self.exe = Some(exe);
+        }
+        if let Some(args) = args {
+            if self.args.is_some() {
+                return Err("args has already been defined");
+            }
+            self.args = Some(args);
+        }
+        if let Some(edit) = edit {
+            if self.edit.is_some() {
+                return Err("edit has already been defined");
+            }
+            self.edit = Some(edit);
         }
         if let Some(timeout) = timeout {
             if self.timeout.is_some() {
@@ -179,78 +1187,274 @@ impl Builder {
         }
         if let Some(keepenv) = keepenv {
             if self.keepenv.is_some() {
-                return Err("askpass has already been defined");
+                return Err("keepenv has already been defined");
             }
             self.keepenv = Some(keepenv);
         }
+        if let Some(use_pty) = use_pty {
+            if self.use_pty.is_some() {
+                return Err("use_pty has already been defined");
+            }
+            self.use_pty = Some(use_pty);
+        }
         if let Some(setenv) = setenv {
             if self.setenv.is_some() {
-                return Err("askpass has already been defined");
+                return Err("setenv has already been defined");
             }
             self.setenv = Some(setenv);
         }
+        if let Some(valid_from) = valid_from {
+            if self.valid_from.is_some() {
+                return Err("valid_from has already been defined");
+            }
+            self.valid_from = Some(valid_from);
+        }
+        if let Some(valid_until) = valid_until {
+            if self.valid_until.is_some() {
+                return Err("valid_until has already been defined");
+            }
+            self.valid_until = Some(valid_until);
+        }
+        if let Some(hours) = hours {
+            if self.hours.is_some() {
+                return Err("hours has already been defined");
+            }
+            self.hours = Some(hours);
+        }
+        if let Some(prompt_timeout) = prompt_timeout {
+            if self.prompt_timeout.is_some() {
+                return Err("prompt_timeout has already been defined");
+            }
+            self.prompt_timeout = Some(prompt_timeout);
+        }
+        if let Some(max_retries) = max_retries {
+            if self.max_retries.is_some() {
+                return Err("max_retries has already been defined");
+            }
+            self.max_retries = Some(max_retries);
+        }
+        if let Some(secure_path) = secure_path {
+            if self.secure_path.is_some() {
+                return Err("secure_path has already been defined");
+            }
+            self.secure_path = Some(secure_path);
+        }
+        if let Some(prompt) = prompt {
+            if self.prompt.is_some() {
+                return Err("prompt has already been defined");
+            }
+            self.prompt = Some(prompt);
+        }
+        if let Some(env_check) = env_check {
+            if self.env_check.is_some() {
+                return Err("env_check has already been defined");
+            }
+            self.env_check = Some(env_check);
+        }
+        if let Some(envfile) = envfile {
+            if self.envfile.is_some() {
+                return Err("envfile has already been defined");
+            }
+            self.envfile = Some(envfile);
+        }
+        if let Some(password_from) = password_from {
+            if self.password_from.is_some() {
+                return Err("password_from has already been defined");
+            }
+            self.password_from = Some(password_from);
+        }
         Ok(())
     }
 
     #[inline]
     pub fn build(self) -> Result<Rule, &'static str> {
+        if let (Some(from), Some(until)) = (self.valid_from, self.valid_until) {
+            if from >= until {
+                return Err("valid_from before valid_until");
+            }
+        }
+        if self.edit.is_some() && (self.exe.is_some() || self.args.is_some()) {
+            return Err("edit without exe and args");
+        }
+
         if let Some(origin) = self.origin {
             Ok(Rule {
+                action: self.action.unwrap_or_default(),
                 origin,
                 target: self.target,
+                host: self.host,
                 timeout: self.timeout,
                 askpass: self.askpass,
                 exe: self.exe,
+                args: self.args,
+                edit: self.edit,
                 keepenv: self.keepenv,
+                use_pty: self.use_pty,
                 setenv: self.setenv,
+                valid_from: self.valid_from,
+                valid_until: self.valid_until,
+                hours: self.hours,
+                prompt_timeout: self.prompt_timeout,
+                max_retries: self.max_retries,
+                prompt: self.prompt,
+                secure_path: self.secure_path,
+                env_check: self.env_check,
+                envfile: self.envfile,
+                password_from: self.password_from,
             })
         } else {
             Err("origin not defined in rule")
         }
     }
 
+    #[inline]
+    pub fn build_defaults(self) -> Defaults {
+        Defaults {
+            origin: self.origin,
+            timeout: self.timeout,
+            askpass: self.askpass,
+            keepenv: self.keepenv,
+            use_pty: self.use_pty,
+            prompt_timeout: self.prompt_timeout,
+            max_retries: self.max_retries,
+            secure_path: self.secure_path,
+            prompt: self.prompt,
+            env_check: self.env_check,
+            password_from: self.password_from,
+        }
+    }
+
+    #[inline]
+    pub fn with_host(host: Patterns) -> Self {
+        Self {
+            host: Some(host),
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn with_edit(edit: Patterns) -> Self {
+        Self {
+            edit: Some(edit),
+            ..Default::default()
+        }
+    }
+
     #[inline]
     pub fn with_timeout(timeout: u64) -> Self {
         Self {
-            origin: None,
-            target: None,
-            exe: None,
-            askpass: None,
             timeout: Some(timeout),
-            keepenv: None,
-            setenv: None,
+            ..Default::default()
         }
     }
 
     #[inline]
     pub fn with_askpass(ask: bool) -> Self {
         Self {
-            origin: None,
-            target: None,
-            exe: None,
-            timeout: None,
             askpass: Some(ask),
-            keepenv: None,
-            setenv: None,
+            ..Default::default()
         }
     }
 
     #[inline]
     pub fn with_keepenv(keepenv: bool) -> Self {
         Self {
-            origin: None,
-            target: None,
-            exe: None,
-            timeout: None,
-            askpass: None,
             keepenv: Some(keepenv),
-            setenv: None,
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn with_use_pty(use_pty: bool) -> Self {
+        Self {
+            use_pty: Some(use_pty),
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn with_valid_from(valid_from: DateTime) -> Self {
+        Self {
+            valid_from: Some(valid_from),
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn with_valid_until(valid_until: DateTime) -> Self {
+        Self {
+            valid_until: Some(valid_until),
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn with_hours(hours: Vec<Hours>) -> Self {
+        Self {
+            hours: Some(hours),
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn with_prompt_timeout(prompt_timeout: u32) -> Self {
+        Self {
+            prompt_timeout: Some(prompt_timeout),
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn with_max_retries(max_retries: usize) -> Self {
+        Self {
+            max_retries: Some(max_retries),
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn with_secure_path(secure_path: CString) -> Self {
+        Self {
+            secure_path: Some(secure_path),
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn with_prompt(prompt: CString) -> Self {
+        Self {
+            prompt: Some(prompt),
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn with_envfile(envfile: CString) -> Self {
+        Self {
+            envfile: Some(envfile),
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn with_env_check(env_check: Vec<VarPattern>) -> Self {
+        Self {
+            env_check: Some(env_check),
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn with_password_from(password_from: Vec<PasswordFrom>) -> Self {
+        Self {
+            password_from: Some(password_from),
+            ..Default::default()
         }
     }
 }
 
 peg::parser! {
-    grammar config() for [u8] {
+    grammar config(state: &State<'_>) for [u8] {
         use std::ffi::CString;
 
         rule ws() = quiet!{[b' ' | b'\n' | b'\t']+}
@@ -259,13 +1463,109 @@ peg::parser! {
         rule comment() = quiet!{[b'#'] [^ b'\n']* (eol() / eof())}
         rule ignored() = quiet!{ws()/comment()}
         rule _ = quiet!{ignored()*}
+        // Like `_`, before a user or a group: `#1001` there is an ID, not a
+        // comment.
+        rule __ = quiet!{(ws() / !id_literal() comment())*}
+
+        pub rule parse() -> Vec<Statement>
+            = statements:parse_statement()* { statements.into_iter().flatten().collect() }
+
+        rule parse_statement() -> Option<Statement>
+            = r:parse_rule() { Some(Statement::Rule(Box::new(r))) }
+            / d:parse_defaults() { Some(Statement::Defaults(Box::new(d))) }
+            / i:parse_include() { Some(i) }
+            / t:parse_timezone() { Some(t) }
+            / parse_alias() { None }
+
+        rule parse_timezone() -> Statement
+            = _ "timezone" _ t:path_literal() _ ";" _ { Statement::Timezone(t) }
+
+        rule parse_alias()
+            = _ "alias" ws() "users" ws() p:position!() n:alias_name() _ "=" __ i:(alias_ident() ++ (_ [b'|'] __)) _ ";" _ {
+                state.define(AliasKind::Users, n, p, i)
+            }
+            / _ "alias" ws() "groups" ws() p:position!() n:alias_name() _ "=" __ i:(alias_ident() ++ (_ [b'|'] __)) _ ";" _ {
+                state.define(AliasKind::Groups, n, p, i)
+            }
+            / _ "alias" ws() "hosts" ws() p:position!() n:alias_name() _ "=" _ i:(alias_host() ++ (_ [b'|'] _)) _ ";" _ {
+                state.define(AliasKind::Hosts, n, p, i)
+            }
+            / _ "alias" ws() "commands" ws() p:position!() n:alias_name() _ "=" _ i:(alias_exe() ++ (_ [b'|'] _)) _ ";" _ {
+                state.define(AliasKind::Commands, n, p, i)
+            }
+
+        // Alias names are upper case so that they cannot be mistaken for
+        // users, groups, hosts or commands; quote a name to use it literally.
+        rule alias_name() -> &'input [u8]
+            = $([b'A'..=b'Z'][b'A'..=b'Z' | b'0'..=b'9' | b'_']*)
+
+        rule alias_ref() -> (usize, &'input [u8])
+            = p:position!() n:alias_name() &([b' ' | b'\t' | b'\n' | b'|' | b';' | b':' | b')'] / eof()) { (p, n) }
+
+        rule alias_ident() -> AliasItem
+            = r:alias_ref() { AliasItem::Ref(String::from_utf8_lossy(r.1).into_owned(), r.0) }
+            / i:ident() { AliasItem::Ident(i) }
+
+        rule alias_host() -> AliasItem
+            = r:alias_ref() { AliasItem::Ref(String::from_utf8_lossy(r.1).into_owned(), r.0) }
+            / h:host() { AliasItem::Glob(h) }
 
-        pub rule parse() -> Vec<Rule>
-            = rules:parse_rule()* { rules }
+        rule alias_exe() -> AliasItem
+            = r:alias_ref() { AliasItem::Ref(String::from_utf8_lossy(r.1).into_owned(), r.0) }
+            / e:exe() { AliasItem::Glob(e) }
+
+        rule parse_include() -> Statement
+            = _ "includedir" _ p:path_literal() _ ";" _ { Statement::IncludeDir(p) }
+            / _ "include" _ p:path_literal() _ ";" _ { Statement::Include(p) }
+
+        // The byte NN, for values that are not UTF-8.
+        rule hex_escape() -> u8
+            = "\\x" h:$([b'0'..=b'9' | b'a'..=b'f' | b'A'..=b'F']*<2>) {?
+                match h.iter().fold(0, |n, c| n * 16 + (*c as char).to_digit(16).unwrap() as u8) {
+                    0 => Err("non-NUL byte"),
+                    b => Ok(b),
+                }
+            }
+
+        rule path_char() -> u8
+            = [b'\\'] c:[b'"' | b'\\'] { c }
+            / hex_escape()
+            / c:[^ b'\0' | b'"' | b'\\' | b'\n'] { c }
+
+        rule path_literal() -> CString
+            = [b'"'] p:path_char()* [b'"'] {?
+                if p.is_empty() {
+                    Err("non-empty path")
+                } else {
+                    Ok(unsafe { CString::from_vec_unchecked(p) })
+                }
+            }
 
         rule parse_rule() -> Rule
             = _ "rule" _ "{" _ r:rule_statements() _ "}" _ { r }
 
+        rule parse_defaults() -> Defaults
+            = _ "defaults" _ "{" _ d:(defaults_statement() ** _) _ "}" _ {?
+                let mut acc = Builder::default();
+                for d in d {
+                    acc.merge(d)?;
+                }
+                Ok(acc.build_defaults())
+            }
+
+        rule defaults_statement() -> Builder
+            = o:origin_statement() { o }
+            / t:timeout_statement() { t }
+            / a:askpass_statement() { a }
+            / k:keepenv_statement() { k }
+            / u:use_pty_statement() { u }
+            / p:prompt_timeout_statement() { p }
+            / m:max_retries_statement() { m }
+            / s:secure_path_statement() { s }
+            / p:prompt_statement() { p }
+            / e:env_check_statement() { e }
+            / p:password_from_statement() { p }
+
         rule _rule_statements() -> Vec<Builder>
             = lh:rule_statement() _ rh:_rule_statements() { let mut rh = rh; rh.insert(0, lh); rh }
             / rul:rule_statement() { vec![rul] }
@@ -285,35 +1585,197 @@ peg::parser! {
             }
 
         rule rule_statement() -> Builder
-            = o:origin_statement() { o }
+            = a:action_statement() { a }
+            / o:origin_statement() { o }
             / t:target_statement() { t }
+            / h:host_statement() { h }
             / e:exe_statement() { e }
+            / a:args_statement() { a }
+            / e:edit_statement() { e }
             / t:timeout_statement() { t }
             / a:askpass_statement() { a }
             / k:keepenv_statement() { k }
+            / u:use_pty_statement() { u }
             / e:setenv_statement() { e }
+            / v:valid_from_statement() { v }
+            / v:valid_until_statement() { v }
+            / h:hours_statement() { h }
+            / p:prompt_timeout_statement() { p }
+            / m:max_retries_statement() { m }
+            / p:prompt_statement() { p }
+            / s:secure_path_statement() { s }
+            / e:env_check_statement() { e }
+            / e:envfile_statement() { e }
+            / p:password_from_statement() { p }
+
+        rule action_statement() -> Builder
+            = "action" _ "=" _ a:action_literal() _ ";" { a.into() }
 
         rule origin_statement() -> Builder
-            = "origin" _ "=" _ o:origin_exp() _ ";" { o.into() }
+            = "origin" _ "=" __ o:origin_exp() _ ";" { o.into() }
 
         rule target_statement() -> Builder
-            = "target" _ "=" _ t:target_exp() _ ";" { t.into() }
+            = "target" _ "=" __ t:target_exp() _ ";" { t.into() }
+
+        rule host_statement() -> Builder
+            = "host" _ "=" _ h:host_expr() _ ";" { Builder::with_host(h) }
 
         rule exe_statement() -> Builder
             = "exe" _ "=" _ e:exe_expr() _ ";" { e.into() }
 
+        rule args_statement() -> Builder
+            = "args" _ "=" _ a:args_expr() _ ";" { a.into() }
+
+        rule edit_statement() -> Builder
+            = "edit" _ "=" _ e:exe_expr() _ ";" { Builder::with_edit(e) }
+
         rule timeout_statement() -> Builder
             = "timeout" _ "=" _ i:u64_literal() _ ";" { Builder::with_timeout(i) }
 
+        rule prompt_timeout_statement() -> Builder
+            = "prompt_timeout" _ "=" _ i:u64_literal() _ ";" {?
+                u32::try_from(i).map(Builder::with_prompt_timeout).map_err(|_| "prompt_timeout too large")
+            }
+
+        rule max_retries_statement() -> Builder
+            = "max_retries" _ "=" _ i:u64_literal() _ ";" {?
+                match usize::try_from(i) {
+                    Ok(0) => Err("max_retries greater than 0"),
+                    Ok(i) => Ok(Builder::with_max_retries(i)),
+                    Err(_) => Err("max_retries too large"),
+                }
+            }
+
+        // Only absolute directories, so that the working directory of the
+        // invoking user never ends up in the PATH.
+        rule secure_path_statement() -> Builder
+            = "secure_path" _ "=" _ p:path_literal() _ ";" {?
+                if p.to_bytes().split(|&c| c == b':').all(|dir| dir.first() == Some(&b'/')) {
+                    Ok(Builder::with_secure_path(p))
+                } else {
+                    Err("absolute directories in secure_path")
+                }
+            }
+
+        rule prompt_statement() -> Builder
+            = "prompt" _ "=" _ p:path_literal() _ ";" { Builder::with_prompt(p) }
+
         rule askpass_statement() -> Builder
             = "askpass" _ "=" _ b:bool_literal() _ ";" { Builder::with_askpass(b) }
 
         rule keepenv_statement() -> Builder
             = "keepenv" _ "=" _ b:bool_literal() _ ";" { Builder::with_keepenv(b) }
 
+        rule use_pty_statement() -> Builder
+            = "use_pty" _ "=" _ b:bool_literal() _ ";" { Builder::with_use_pty(b) }
+
         rule setenv_statement() -> Builder
             = "setenv" _ "=" _ "{" _ e:env_expr() _ [b',']? _ "}" _ ";" { e.into() }
 
+        rule bare_path() -> CString
+            = p:$([b'/'] [^ b'\0' | b' ' | b'\t' | b'\n' | b';' | b'"']*) {
+                unsafe { CString::from_vec_unchecked(p.to_vec()) }
+            }
+
+        rule envfile_statement() -> Builder
+            = "envfile" _ "=" _ p:(path_literal() / bare_path()) _ ";" {?
+                if p.to_bytes().first() == Some(&b'/') {
+                    Ok(Builder::with_envfile(p))
+                } else {
+                    Err("absolute envfile path")
+                }
+            }
+
+        rule env_check_statement() -> Builder
+            = "env_check" _ "=" _ "{" _ p:(var_pattern() ** (_ "," _)) _ [b',']? _ "}" _ ";" {
+                Builder::with_env_check(p)
+            }
+
+        rule password_from_statement() -> Builder
+            = "password_from" _ "=" _ p:(password_from_literal() ++ (_ [b'|'] _)) _ ";" {
+                Builder::with_password_from(p)
+            }
+
+        rule valid_from_statement() -> Builder
+            = "valid_from" _ "=" _ d:datetime_literal(0) _ ";" { Builder::with_valid_from(d) }
+
+        rule valid_until_statement() -> Builder
+            = "valid_until" _ "=" _ d:datetime_literal(24 * 60) _ ";" { Builder::with_valid_until(d) }
+
+        rule hours_statement() -> Builder
+            = "hours" _ "=" _ h:(hours() ++ (_ [b'|'] _)) _ ";" { Builder::with_hours(h) }
+
+        rule date_literal() -> (u16, u8, u8)
+            = y:$([b'0'..=b'9']*<4>) "-" m:$([b'0'..=b'9']*<2>) "-" d:$([b'0'..=b'9']*<2>) {?
+                let (year, month, day) = (digits(y), digits(m) as u8, digits(d) as u8);
+                if !(1..=12).contains(&month) || day == 0 || day > DateTime::days_in_month(year, month) {
+                    Err("valid date")
+                } else {
+                    Ok((year, month, day))
+                }
+            }
+
+        rule time_literal() -> u16
+            = h:$([b'0'..=b'9']*<2>) ":" m:$([b'0'..=b'9']*<2>) {?
+                let (hour, minute) = (digits(h), digits(m));
+                if minute >= 60 || hour > 24 || (hour == 24 && minute != 0) {
+                    Err("valid time")
+                } else {
+                    Ok(hour * 60 + minute)
+                }
+            }
+
+        // Without a time of day `valid_from` starts at the beginning of the day
+        // and `valid_until` ends at the end of it.
+        rule datetime_literal(minutes: u16) -> DateTime
+            = d:date_literal() t:(([b'T'] / [b' ' | b'\t']+) t:time_literal() { t })? {
+                DateTime {
+                    year: d.0,
+                    month: d.1,
+                    day: d.2,
+                    minutes: t.unwrap_or(minutes),
+                }
+            }
+
+        rule weekday() -> u8
+            = "Sun" { 0 }
+            / "Mon" { 1 }
+            / "Tue" { 2 }
+            / "Wed" { 3 }
+            / "Thu" { 4 }
+            / "Fri" { 5 }
+            / "Sat" { 6 }
+
+        rule weekdays_item() -> u8
+            = from:weekday() "-" to:weekday() {
+                let mut days = 0u8;
+                let mut day = from;
+                loop {
+                    days |= 1 << day;
+                    if day == to {
+                        break days;
+                    }
+                    day = (day + 1) % 7;
+                }
+            }
+            / day:weekday() { 1 << day }
+
+        rule weekdays() -> u8
+            = d:(weekdays_item() ++ ",") { d.into_iter().fold(0, |acc, d| acc | d) }
+
+        rule time_range() -> (u16, u16)
+            = start:time_literal() "-" end:time_literal() {?
+                if start == 24 * 60 || start == end {
+                    Err("non-empty time range")
+                } else {
+                    Ok((start, end))
+                }
+            }
+
+        rule hours() -> Hours
+            = days:weekdays() ws() r:time_range() { Hours { days, start: r.0, end: r.1 } }
+            / r:time_range() { Hours { days: 0x7f, start: r.0, end: r.1 } }
+
         rule var_name_() -> OsString
             = name:$([b'A'..=b'Z' | b'a'..=b'z' | b'_'][b'A'..=b'Z' | b'a'..=b'z' | b'_' | b'0'..=b'9']*) {
                 OsString::from(unsafe { std::str::from_utf8_unchecked(name) }.to_string())
@@ -321,11 +1783,16 @@ peg::parser! {
         rule var_name() -> Rc<Box<OsStr>>
             = name:var_name_() { Rc::new(name.into_boxed_os_str()) }
 
+        rule var_pattern() -> VarPattern
+            = name:$([b'A'..=b'Z' | b'a'..=b'z' | b'_' | b'*' | b'?'][b'A'..=b'Z' | b'a'..=b'z' | b'_' | b'0'..=b'9' | b'*' | b'?']*) {?
+                VarPattern::new(Rc::new(OsStr::from_bytes(name).into())).map_err(|_| "variable name pattern")
+            }
+
         rule env_unset() -> Env
-            = "-" n:var_name() { Env::Unset(n) }
+            = "-" p:var_pattern() { Env::Unset(p) }
 
         rule env_copy() -> Env
-            = n:var_name() { Env::Copy(n) }
+            = p:var_pattern() { Env::Copy(p) }
 
         rule env_set() -> Env
             = n:var_name() "=\"" t:var_template() [b'"'] { Env::Set(n, t) }
@@ -335,13 +1802,37 @@ peg::parser! {
 
         rule var_template_part_str_char() -> u8
             = [b'\\'] c:[b'$' | b'"' | b'\\'] { c }
+            / hex_escape()
             / c:[^ b'\0' | b'$' | b'"'] { c }
 
+        rule var_default_char() -> u8
+            = [b'\\'] c:[b'$' | b'"' | b'\\' | b'}'] { c }
+            / hex_escape()
+            / c:[^ b'\0' | b'$' | b'"' | b'}'] { c }
+
+        // Variables named PEZZO_* are the built-in ones, never taken from
+        // the environment of the invoking user.
+        rule template_var() -> Box<OsStr>
+            = n:var_name_() {?
+                let name = n.into_boxed_os_str();
+                if name.as_bytes().starts_with(b"PEZZO_")
+                    && !crate::env::BUILTINS.iter().any(|b| b.as_bytes() == name.as_bytes())
+                {
+                    Err("a known PEZZO_* variable")
+                } else {
+                    Ok(name)
+                }
+            }
+
         rule var_template_part() -> EnvTemplatePart
-            = [b'$'] n:var_name_() { EnvTemplatePart::Var(n.into_boxed_os_str()) }
-            / "${" n:var_name_() [b'}'] { EnvTemplatePart::Var(n.into_boxed_os_str()) }
-            / s:var_template_part_str_char()+ {?
-                Ok(EnvTemplatePart::Str(OsString::from(String::from_utf8(s).map_err(|_| "invalid utf8")?).into_boxed_os_str()))
+            = [b'$'] n:template_var() { EnvTemplatePart::Var(n) }
+            / "${" n:template_var() [b'}'] { EnvTemplatePart::Var(n) }
+            / "${" n:template_var() ":-" d:var_default_char()* [b'}'] {
+                EnvTemplatePart::VarOr(n, OsString::from_vec(d).into_boxed_os_str())
+            }
+            / "${" n:template_var() ":?}" { EnvTemplatePart::Required(n) }
+            / s:var_template_part_str_char()+ {
+                EnvTemplatePart::Str(OsString::from_vec(s).into_boxed_os_str())
             }
 
         rule env() -> Env
@@ -362,6 +1853,15 @@ peg::parser! {
                 vec![lh].into_boxed_slice()
             }
 
+        rule password_from_literal() -> PasswordFrom
+            = "tty" { PasswordFrom::Tty }
+            / "stdin" { PasswordFrom::Stdin }
+            / "askpass" { PasswordFrom::Askpass }
+
+        rule action_literal() -> Action
+            = "permit" { Action::Permit }
+            / "deny" { Action::Deny }
+
         rule bool_literal() -> bool
             = "true" { true }
             / "false" { false }
@@ -388,57 +1888,139 @@ peg::parser! {
                     .map_err(|_| "invalid glob")
             }
 
-        rule exe_expr_cont() -> Glob
-            = [b'|'] _ e:exe() _ { e }
+        rule exe_item() -> Vec<Glob>
+            = r:alias_ref() { state.globs(AliasKind::Commands, r.1, r.0) }
+            / e:exe() { vec![e] }
+
+        rule exe_expr_cont() -> Vec<Glob>
+            = [b'|'] _ e:exe_item() _ { e }
+
+        rule exe_expr() -> Patterns
+            = lh:exe_item() _ rh:exe_expr_cont()* {?
+                Patterns::new(lh.into_iter().chain(rh.into_iter().flatten()).collect())
+                    .map_err(|_| "invalid exe glob")
+            }
+
+        rule host() -> Glob
+            = name:$([^ b'\0' | b' ' | b'\t' | b'\n' | b'|' | b';']+) {?
+                GlobBuilder::new(std::str::from_utf8(name).map_err(|_| "invalid utf8")?)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|_| "invalid glob")
+            }
+
+        rule host_item() -> Vec<Glob>
+            = r:alias_ref() { state.globs(AliasKind::Hosts, r.1, r.0) }
+            / h:host() { vec![h] }
+
+        rule host_expr_cont() -> Vec<Glob>
+            = [b'|'] _ h:host_item() _ { h }
+
+        rule host_expr() -> Patterns
+            = lh:host_item() _ rh:host_expr_cont()* {?
+                Patterns::new(lh.into_iter().chain(rh.into_iter().flatten()).collect())
+                    .map_err(|_| "invalid host glob")
+            }
+
+        rule arg_char() -> u8
+            = [b'\\'] c:[b' ' | b'|' | b';' | b']' | b'"' | b'\\'] { c }
+            / c:[^ b'\0' | b' ' | b'\t' | b'\n' | b'|' | b';' | b']' | b'"' | b'\\'] { c }
+
+        rule arg_quoted_char() -> u8
+            = [b'\\'] c:[b'"' | b'\\'] { c }
+            / hex_escape()
+            / c:[^ b'\0' | b'"' | b'\\'] { c }
+
+        rule arg_pattern() -> ArgPattern
+            = [b'"'] s:arg_quoted_char()* [b'"'] {
+                ArgPattern::Exact(OsString::from_vec(s).into_boxed_os_str())
+            }
+            / !("..." (ws() / "]")) s:arg_char()+ {?
+                GlobBuilder::new(std::str::from_utf8(s.as_slice()).map_err(|_| "invalid utf8")?)
+                    .literal_separator(true)
+                    .build()
+                    .map(|g| ArgPattern::Glob(g.compile_matcher()))
+                    .map_err(|_| "invalid glob")
+            }
 
-        rule exe_expr() -> GlobSet
-            = lh:exe() _ rh:exe_expr_cont()* {?
-                let mut builder = GlobSetBuilder::new();
-                builder.add(lh);
-                for g in rh {
-                    builder.add(g);
+        rule args_vector() -> Args
+            = "[" _ patterns:(arg_pattern() ** ws()) _ variadic:"..."? _ "]" {
+                Args {
+                    patterns: patterns.into_boxed_slice(),
+                    variadic: variadic.is_some(),
                 }
-                builder.build().map_err(|_| "invalid exe glob")
             }
-            / name:exe() {? GlobSetBuilder::new().add(name).build().map_err(|_| "invalid exe glob") }
 
-        rule user() -> CString
-            = name:$([b'A'..=b'Z'|b'a'..=b'z'|b'_'][b'A'..=b'Z'|b'a'..=b'z'|b'0'..=b'9'|b'_']+) { unsafe { CString::from_vec_unchecked(name.to_vec()) } }
+        rule args_expr_cont() -> Args
+            = [b'|'] _ a:args_vector() _ { a }
+
+        rule args_expr() -> Vec<Args>
+            = lh:args_vector() _ rh:args_expr_cont()* { let mut rh = rh; rh.insert(0, lh); rh }
+
+        rule name_char() -> u8
+            = [b'\\'] c:[b'"' | b'\\'] { c }
+            / hex_escape()
+            / c:[^ b'\0' | b'"' | b'\\'] { c }
+
+        rule id_literal() -> u32
+            = [b'#'] i:$([b'0'..=b'9']+) {?
+                std::str::from_utf8(i)
+                    .map_err(|_| "invalid id")?
+                    .parse::<u32>()
+                    .map_err(|_| "invalid id")
+            }
+
+        // POSIX portable user name character set, plus the trailing `$` used
+        // by machine accounts.
+        rule name() -> CString
+            = [b'"'] name:name_char()+ [b'"'] { unsafe { CString::from_vec_unchecked(name) } }
+            / name:$([b'A'..=b'Z'|b'a'..=b'z'|b'0'..=b'9'|b'_'|b'.'][b'A'..=b'Z'|b'a'..=b'z'|b'0'..=b'9'|b'_'|b'.'|b'-']*[b'$']?) {
+                unsafe { CString::from_vec_unchecked(name.to_vec()) }
+            }
+
+        rule ident() -> Ident
+            = id:id_literal() { Ident::Id(id) }
+            / name:name() { Ident::Name(name) }
 
-        rule group() -> CString
-            = name:$([b'A'..=b'Z'|b'a'..=b'z'|b'_'][b'A'..=b'Z'|b'a'..=b'z'|b'0'..=b'9'|b'_']+) { unsafe { CString::from_vec_unchecked(name.to_vec()) } }
+        rule user() -> Vec<Ident>
+            = r:alias_ref() { state.idents(AliasKind::Users, r.1, r.0) }
+            / i:ident() { vec![i] }
 
-        rule user_exp_cont() -> CString
-            = [b'|'] _ user:user() _ { user }
+        rule group() -> Vec<Ident>
+            = r:alias_ref() { state.idents(AliasKind::Groups, r.1, r.0) }
+            / i:ident() { vec![i] }
 
-        rule user_exp() -> Vec<CString>
-            = [b'('] _ lh:user() _ rh:user_exp_cont()* [b')'] { let mut rh = rh; rh.insert(0, lh); rh }
-            / user:user() { vec![user] }
+        rule user_exp_cont() -> Vec<Ident>
+            = [b'|'] __ user:user() _ { user }
 
-        rule group_exp_cont() -> CString
-            = [b'|'] _ group:group() _ { group }
+        rule user_exp() -> Vec<Ident>
+            = [b'('] __ lh:user() _ rh:user_exp_cont()* [b')'] { lh.into_iter().chain(rh.into_iter().flatten()).collect() }
+            / user:user() { user }
 
-        rule group_exp() -> Vec<CString>
-            = [b'('] _ lh:group() _ rh:group_exp_cont()* [b')'] { let mut rh = rh; rh.insert(0, lh); rh }
-            / group:group() { vec![group] }
+        rule group_exp_cont() -> Vec<Ident>
+            = [b'|'] __ group:group() _ { group }
+
+        rule group_exp() -> Vec<Ident>
+            = [b'('] __ lh:group() _ rh:group_exp_cont()* [b')'] { lh.into_iter().chain(rh.into_iter().flatten()).collect() }
+            / group:group() { group }
 
         rule origin() -> Origin
-            = [b':'] _ group:group_exp() { Origin::Group(group) }
+            = [b':'] __ group:group_exp() { Origin::Group(group) }
             / user:user_exp() { Origin::User(user) }
 
         rule origin_exp_cont() -> Origin
-            = [b'|'] _ o:origin() _ { o }
+            = [b'|'] __ o:origin() _ { o }
 
         rule origin_exp() -> Vec<Origin>
             = lh:origin() _ rh:origin_exp_cont()* { let mut rh = rh; rh.insert(0, lh); rh }
             / o:origin() { vec![o] }
 
         rule target() -> Target
-            = users:user_exp() _ [b':'] _ groups:group_exp() { Target::UserGroup(users, groups) }
+            = users:user_exp() _ [b':'] __ groups:group_exp() { Target::UserGroup(users, groups) }
             / users:user_exp() { Target::User(users) }
 
         rule target_exp_cont() -> Target
-            = [b'|'] _ t:target() _ { t }
+            = [b'|'] __ t:target() _ { t }
 
         rule target_exp() -> Vec<Target>
             = lh:target() _ rh:target_exp_cont()* { let mut rh = rh; rh.insert(0, lh); rh }
@@ -446,4 +2028,258 @@ peg::parser! {
     }
 }
 
-pub use config::parse;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::rules;
+
+    fn name(s: &str) -> Ident {
+        Ident::Name(CString::new(s).unwrap())
+    }
+
+    #[test]
+    fn numeric_ids() {
+        let rules = rules(
+            "alias users OPS = #1002 | alice;
+            rule { origin = #1000 | (#1001|bob) | :#10 | OPS; target = #0:#0; }",
+        );
+        match &rules[0].origin[..] {
+            [Origin::User(a), Origin::User(b), Origin::Group(c), Origin::User(d)] => {
+                assert_eq!(a, &[Ident::Id(1000)]);
+                assert_eq!(b, &[Ident::Id(1001), name("bob")]);
+                assert_eq!(c, &[Ident::Id(10)]);
+                assert_eq!(d, &[Ident::Id(1002), name("alice")]);
+            }
+            origin => panic!("unexpected origin {:?}", origin),
+        }
+        match rules[0].target.as_deref() {
+            Some([Target::UserGroup(users, groups)]) => {
+                assert_eq!(users, &[Ident::Id(0)]);
+                assert_eq!(groups, &[Ident::Id(0)]);
+            }
+            target => panic!("unexpected target {:?}", target),
+        }
+    }
+
+    #[test]
+    fn upper_case_names() {
+        let err = |conf: &[u8]| parse(conf, &mut Aliases::default()).unwrap_err();
+
+        assert!(matches!(
+            err(b"alias users ADMINS = alice; rule { origin = ADMNS; }"),
+            Error::UndefinedAlias { location: 44, ref name } if name == "ADMNS"
+        ));
+        assert!(matches!(
+            err(b"alias hosts DB = db*; rule { origin = DB; }"),
+            Error::AliasKind { location: 38, .. }
+        ));
+
+        let rules = rules(r#"rule { origin = "BUILD" | :"WHEEL"; target = #1001; }"#);
+        match &rules[0].origin[..] {
+            [Origin::User(users), Origin::Group(groups)] => {
+                assert_eq!(users, &[name("BUILD")]);
+                assert_eq!(groups, &[name("WHEEL")]);
+            }
+            origin => panic!("unexpected origin {:?}", origin),
+        }
+    }
+
+    #[test]
+    fn comments_starting_with_digits() {
+        let rules = rules(
+            "#1 allow the admins
+            #2024 note
+            rule { origin = alice; #3 exe below
+                exe = /usr/bin/id; } #4",
+        );
+        assert_eq!(rules.len(), 1);
+        assert!(rules[0].exe.is_some());
+    }
+
+    #[test]
+    fn edit_patterns() {
+        let rule = &rules("rule { origin = alice; edit = /etc/nginx/** | /etc/hosts; }")[0];
+        let edit = rule.edit.as_ref().unwrap();
+        assert!(edit.is_match("/etc/nginx/sites/default.conf"));
+        assert!(edit.is_match("/etc/hosts"));
+        assert!(!edit.is_match("/etc/passwd"));
+        assert!(parse(
+            b"rule { origin = alice; edit = /etc/hosts; exe = /bin/vi; }",
+            &mut Aliases::default()
+        )
+        .is_err());
+
+        let text = "rule {\n    action = permit;\n    origin = alice;\n    edit = /etc/nginx/** | /etc/hosts;\n}";
+        assert_eq!(rules(text)[0].to_string(), text);
+    }
+
+    #[test]
+    fn args_matching() {
+        let rules = rules(
+            "rule {
+                origin = :wheel;
+                args = [restart nginx] | [status ...] | [\"-h\" *.conf];
+            }
+            rule { origin = :wheel; args = []; }",
+        );
+        let matches = |i: usize, args: &[&str]| {
+            rules[i]
+                .args
+                .as_ref()
+                .unwrap()
+                .iter()
+                .any(|a| a.is_match(args))
+        };
+
+        assert!(matches(0, &["restart", "nginx"]));
+        assert!(!matches(0, &["restart", "nginx", "sshd"]));
+        assert!(!matches(0, &["restart"]));
+        assert!(matches(0, &["status"]));
+        assert!(matches(0, &["status", "nginx", "sshd"]));
+        assert!(matches(0, &["-h", "a.conf"]));
+        assert!(!matches(0, &["-h", "/etc/a.conf"]));
+        assert!(matches(1, &[]));
+        assert!(!matches(1, &["-h"]));
+    }
+
+    #[test]
+    fn aliases() {
+        let rules = rules(
+            "rule { origin = (ADMINS | carol) | :\"OPS\"; target = DBA:DBA_GROUPS; exe = NET; }
+            alias commands NET = /usr/sbin/ip | SS;
+            alias commands SS = /usr/bin/ss;
+            alias users ADMINS = alice | bob;
+            alias users DBA = postgres | #1001;
+            alias groups DBA_GROUPS = postgres;",
+        );
+        let exe = rules[0].exe.as_ref().unwrap();
+        assert!(exe.is_match("/usr/sbin/ip"));
+        assert!(exe.is_match("/usr/bin/ss"));
+        assert!(!exe.is_match("/usr/bin/id"));
+
+        match &rules[0].origin[..] {
+            [Origin::User(users), Origin::Group(groups)] => {
+                assert_eq!(users, &[name("alice"), name("bob"), name("carol")]);
+                assert_eq!(groups, &[name("OPS")]);
+            }
+            origin => panic!("unexpected origin {:?}", origin),
+        }
+        match &rules[0].target.as_deref() {
+            Some([Target::UserGroup(users, groups)]) => {
+                assert_eq!(users, &[name("postgres"), Ident::Id(1001)]);
+                assert_eq!(groups, &[name("postgres")]);
+            }
+            target => panic!("unexpected target {:?}", target),
+        }
+    }
+
+    #[test]
+    fn time_constraints() {
+        let rules = rules(
+            "rule {
+                origin = :wheel;
+                valid_from = 2026-01-01;
+                valid_until = 2026-12-31;
+                hours = Mon-Fri 08:00-18:00 | Sat,Sun 22:00-02:00;
+            }
+            rule { origin = :wheel; valid_until = 2026-06-30T12:30; }",
+        );
+        let at = |month, day, hour: u16, minute: u16, weekday| LocalTime {
+            datetime: DateTime {
+                year: 2026,
+                month,
+                day,
+                minutes: hour * 60 + minute,
+            },
+            weekday,
+        };
+
+        assert!(rules[0].is_active(&at(3, 2, 8, 0, 1)));
+        assert!(!rules[0].is_active(&at(3, 2, 18, 0, 1)));
+        assert!(!rules[0].is_active(&at(3, 7, 12, 0, 6)));
+        assert!(rules[0].is_active(&at(3, 7, 23, 0, 6)));
+        assert!(rules[0].is_active(&at(3, 9, 1, 59, 1)));
+        assert!(!rules[0].is_active(&at(3, 10, 1, 0, 2)));
+        assert!(rules[0].is_active(&at(12, 31, 9, 0, 4)));
+        assert!(rules[0].is_expired(&at(12, 31, 24, 0, 4)));
+
+        assert!(rules[1].is_active(&at(6, 30, 12, 29, 2)));
+        assert!(!rules[1].is_active(&at(6, 30, 12, 30, 2)));
+        assert_eq!(
+            rules[1].valid_until.unwrap().to_string(),
+            "2026-06-30 12:30"
+        );
+
+        assert!(crate::conf::parse("rule { origin = :wheel; valid_until = 2026-02-29; }").is_err());
+        assert!(crate::conf::parse(
+            "rule { origin = :wheel; valid_from = 2026-02-01; valid_until = 2026-01-01; }"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn display_round_trip() {
+        fn render(rule: &Rule) -> String {
+            let join = |items: Vec<String>, sep| items.join(sep);
+            format!(
+                "rule {{ action = {}; origin = {}; target = {}; exe = {}; args = {}; setenv = {{ {} }}; hours = {}; }}",
+                rule.action,
+                join(rule.origin.iter().map(|o| o.to_string()).collect(), " | "),
+                join(rule.target.as_ref().unwrap().iter().map(|t| t.to_string()).collect(), " | "),
+                rule.exe.as_ref().unwrap(),
+                join(rule.args.as_ref().unwrap().iter().map(|a| a.to_string()).collect(), " | "),
+                join(rule.setenv.as_ref().unwrap().iter().map(|e| e.to_string()).collect(), ", "),
+                join(rule.hours.as_ref().unwrap().iter().map(|h| h.to_string()).collect(), " | "),
+            )
+        }
+
+        let rule = &rules(
+            r#"rule {
+                action = deny;
+                origin = (alice | "ROOT" | "we ird" | #1001) | :wheel;
+                target = root | (postgres | #5):"dba";
+                exe = /usr/bin/a\ b | /usr/bin/*;
+                args = ["-h" *.conf ...] | [] | [...];
+                setenv = { -FOO, BAR, BAZ="x${HOME}\$y\"z" };
+                hours = Sat,Sun,Tue-Thu 22:00-02:00 | 08:00-09:00;
+            }"#,
+        )[0];
+        let text = render(rule);
+        assert_eq!(render(&rules(&text)[0]), text);
+        assert!(text.contains(r#"origin = (alice | "ROOT" | "we ird" | #1001) | :wheel;"#));
+        assert!(text.contains(r#"exe = /usr/bin/a\ b | /usr/bin/*;"#));
+        assert!(text.contains(r#"hours = Sun,Tue-Thu,Sat 22:00-02:00 | 08:00-09:00;"#));
+
+        let rule = &rules(
+            r#"rule { origin = "caf\xe9"; exe = /bin/ls; args = ["\xff" "\xC3\xa9\\x"]; setenv = { A="\xfe${B:-\x80}" }; hours = 08:00-09:00; target = root; }"#,
+        )[0];
+        let text = render(rule);
+        assert_eq!(render(&rules(&text)[0]), text);
+        assert!(text.contains(r#"origin = "caf\xe9";"#));
+        assert!(text.contains(r#"args = ["\xff" "é\\x"];"#));
+        assert!(text.contains(r#"setenv = { A="\xfe${B:-\x80}" };"#));
+        assert!(crate::conf::parse(r#"rule { origin = "a\x00"; }"#).is_err());
+    }
+
+    #[test]
+    fn alias_errors() {
+        let err = |conf: &str| crate::conf::parse(conf).unwrap_err();
+
+        assert!(matches!(
+            err("rule { origin = ADMINS; }"),
+            Error::UndefinedAlias { location: 16, .. }
+        ));
+        assert!(matches!(
+            err("alias users A = B; alias users B = alice | A;"),
+            Error::RecursiveAlias { .. }
+        ));
+        assert!(matches!(
+            err("alias users A = alice; alias users A = bob;"),
+            Error::DuplicateAlias { location: 35, .. }
+        ));
+        assert!(matches!(
+            err("alias groups G = wheel; rule { origin = G; }"),
+            Error::AliasKind { location: 40, .. }
+        ));
+    }
+}
@@ -0,0 +1,511 @@
+//! Environment of the commands run.
+
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    fmt,
+    os::unix::ffi::OsStrExt,
+};
+
+use crate::conf::{Env, Settings, VarPattern};
+
+/// Variables that change how programs load code or start up. They are
+/// never taken from the invoking user, even with `keepenv`, unless a rule
+/// names them. A trailing `*` matches any suffix.
+pub const BLACKLIST: &[&str] = &[
+    "LD_*",
+    "DYLD_*",
+    "_RLD*",
+    "LIBPATH",
+    "SHLIB_PATH",
+    "GCONV_PATH",
+    "LOCPATH",
+    "NLSPATH",
+    "HOSTALIASES",
+    "RES_OPTIONS",
+    "LOCALDOMAIN",
+    "BASH_ENV",
+    "ENV",
+    "BASH_FUNC_*",
+    "SHELLOPTS",
+    "BASHOPTS",
+    "PS4",
+    "IFS",
+    "CDPATH",
+    "GLOBIGNORE",
+    "PYTHONPATH",
+    "PYTHONHOME",
+    "PYTHONSTARTUP",
+    "PYTHONINSPECT",
+    "PYTHONUSERBASE",
+    "PERL5LIB",
+    "PERLLIB",
+    "PERL5OPT",
+    "PERL5DB",
+    "RUBYLIB",
+    "RUBYOPT",
+    "NODE_OPTIONS",
+    "NODE_PATH",
+    "JAVA_TOOL_OPTIONS",
+    "_JAVA_OPTIONS",
+    "TCLLIBPATH",
+    "LUA_PATH*",
+    "LUA_CPATH*",
+    "LUA_INIT*",
+    "PHPRC",
+];
+
+/// Variables checked when `env_check` is not set, as sudo does.
+pub const DEFAULT_ENV_CHECK: &[&str] = &[
+    "COLORTERM",
+    "LANG",
+    "LANGUAGE",
+    "LC_*",
+    "LINGUAS",
+    "TERM",
+    "TZ",
+];
+
+/// Variables `setenv` templates can use besides the ones of the invoking
+/// user, see [`Builtins`].
+pub const BUILTINS: &[&str] = &[
+    "PEZZO_USER",
+    "PEZZO_UID",
+    "PEZZO_GID",
+    "PEZZO_TARGET_USER",
+    "PEZZO_TARGET_UID",
+    "PEZZO_TARGET_GID",
+    "PEZZO_TARGET_HOME",
+    "PEZZO_TTY",
+    "PEZZO_COMMAND",
+];
+
+/// Values of the [`BUILTINS`] for a request.
+#[derive(Debug, Clone, Default)]
+pub struct Builtins {
+    pub user: OsString,
+    pub uid: u32,
+    pub gid: u32,
+    pub target_user: OsString,
+    pub target_uid: u32,
+    pub target_gid: u32,
+    pub target_home: OsString,
+    pub tty: OsString,
+    pub command: OsString,
+}
+
+impl Builtins {
+    pub fn get(&self, name: &OsStr) -> Option<OsString> {
+        Some(match name.as_bytes() {
+            b"PEZZO_USER" => self.user.clone(),
+            b"PEZZO_UID" => self.uid.to_string().into(),
+            b"PEZZO_GID" => self.gid.to_string().into(),
+            b"PEZZO_TARGET_USER" => self.target_user.clone(),
+            b"PEZZO_TARGET_UID" => self.target_uid.to_string().into(),
+            b"PEZZO_TARGET_GID" => self.target_gid.to_string().into(),
+            b"PEZZO_TARGET_HOME" => self.target_home.clone(),
+            b"PEZZO_TTY" => self.tty.clone(),
+            b"PEZZO_COMMAND" => self.command.clone(),
+            _ => return None,
+        })
+    }
+}
+
+/// A `${VAR:?}` variable of a `setenv` template is unset or empty.
+#[derive(Debug)]
+pub struct MissingVar(pub OsString);
+
+impl fmt::Display for MissingVar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is required by setenv but not set",
+            self.0.to_string_lossy()
+        )
+    }
+}
+
+impl std::error::Error for MissingVar {}
+
+#[inline]
+fn list_matches(list: &[&str], name: &OsStr) -> bool {
+    list.iter().any(|entry| match entry.strip_suffix('*') {
+        Some(prefix) => name.as_bytes().starts_with(prefix.as_bytes()),
+        None => name.as_bytes() == entry.as_bytes(),
+    })
+}
+
+#[inline]
+pub fn is_blacklisted(name: &OsStr) -> bool {
+    list_matches(BLACKLIST, name)
+}
+
+/// Whether `value` can be taken from the invoking user for a variable in
+/// the `env_check` list, the built-in one if `None`.
+pub fn is_safe(name: &OsStr, value: &OsStr, env_check: Option<&[VarPattern]>) -> bool {
+    let checked = match env_check {
+        Some(patterns) => patterns.iter().any(|p| p.is_match(name)),
+        None => list_matches(DEFAULT_ENV_CHECK, name),
+    };
+    !checked || !value.as_bytes().iter().any(|&c| c == b'/' || c == b'%')
+}
+
+/// The environment of the command, given `vars`, the one of the invoking
+/// user.
+///
+/// With `keepenv` every variable is kept but the blacklisted ones. `PATH` is
+/// always `secure_path`. Then `setenv` applies in order: `-VAR` removes
+/// the matching variables, `VAR` copies them from `vars` (a glob skips the
+/// blacklisted ones) and `VAR="..."` sets them. Values copied from `vars`
+/// are dropped if they fail `env_check`.
+///
+/// Templates take `PEZZO_*` variables from `builtins` and the others from
+/// `vars`, unchecked. A template expanding to nothing leaves the variable
+/// unset, unless it is `""`.
+pub fn build<I>(
+    vars: I,
+    settings: &Settings,
+    setenv: &[Env],
+    builtins: &Builtins,
+) -> Result<BTreeMap<OsString, OsString>, MissingVar>
+where
+    I: IntoIterator<Item = (OsString, OsString)>,
+{
+    let all = vars.into_iter().collect::<Vec<_>>();
+    let lookup = |name: &OsStr| {
+        if name.as_bytes().starts_with(b"PEZZO_") {
+            builtins.get(name)
+        } else {
+            all.iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, value)| value.clone())
+        }
+    };
+    let vars = all
+        .iter()
+        .filter(|(name, value)| is_safe(name, value, settings.env_check.as_deref()))
+        .collect::<Vec<_>>();
+
+    let mut env = BTreeMap::new();
+    if settings.keepenv {
+        env.extend(
+            vars.iter()
+                .filter(|(name, _)| !is_blacklisted(name))
+                .map(|&var| var.clone()),
+        );
+    }
+    env.insert(
+        OsString::from("PATH"),
+        OsStr::from_bytes(settings.secure_path.to_bytes()).to_owned(),
+    );
+
+    for e in setenv {
+        match e {
+            Env::Unset(pattern) => env.retain(|name, _| !pattern.is_match(name)),
+            Env::Copy(pattern) => env.extend(
+                vars.iter()
+                    .filter(|(name, _)| {
+                        pattern.is_match(name) && !(pattern.is_glob() && is_blacklisted(name))
+                    })
+                    .map(|&var| var.clone()),
+            ),
+            Env::Set(name, template) => {
+                let value = template
+                    .format(&lookup)
+                    .map_err(|name| MissingVar(name.to_owned()))?;
+                if !value.is_empty() || template.is_empty() {
+                    env.insert(OsString::from(&***name), value);
+                }
+            }
+        }
+    }
+
+    Ok(env)
+}
+
+/// A line of an env file that cannot be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvFileError {
+    pub line: usize,
+    pub msg: &'static str,
+}
+
+impl fmt::Display for EnvFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl std::error::Error for EnvFileError {}
+
+#[inline]
+fn is_var_name(name: &[u8]) -> bool {
+    name.first()
+        .is_some_and(|c| c.is_ascii_alphabetic() || *c == b'_')
+        && name.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'_')
+}
+
+fn parse_value(value: &[u8]) -> Result<Vec<u8>, &'static str> {
+    match value.first() {
+        Some(b'\'') => match value[1..].iter().position(|&c| c == b'\'') {
+            Some(end) if value[end + 2..].trim_ascii().is_empty() => Ok(value[1..end + 1].to_vec()),
+            Some(_) => Err("text after the closing quote"),
+            None => Err("unterminated single quote"),
+        },
+        Some(b'"') => {
+            let mut res = Vec::new();
+            let mut it = value[1..].iter();
+            while let Some(&c) = it.next() {
+                match c {
+                    b'"' => {
+                        return if it.as_slice().trim_ascii().is_empty() {
+                            Ok(res)
+                        } else {
+                            Err("text after the closing quote")
+                        }
+                    }
+                    b'\\' => match it.next().copied() {
+                        Some(b'n') => res.push(b'\n'),
+                        Some(b't') => res.push(b'\t'),
+                        Some(c @ (b'"' | b'\\' | b'$')) => res.push(c),
+                        _ => return Err("unknown escape"),
+                    },
+                    c => res.push(c),
+                }
+            }
+            Err("unterminated double quote")
+        }
+        _ => Ok(value.trim_ascii_end().to_vec()),
+    }
+}
+
+/// Parses an env file: `KEY=VALUE` lines, optionally prefixed by `export`.
+/// Empty lines and lines starting with `#` are skipped. Values are taken as
+/// they are, without expanding variables: unquoted up to the end of the
+/// line, `'...'` literally or `"..."` with the `\n`, `\t`, `\"`, `\\` and
+/// `\$` escapes.
+pub fn parse_envfile(buf: &[u8]) -> Result<Vec<(OsString, OsString)>, EnvFileError> {
+    let mut vars = Vec::new();
+
+    for (i, line) in buf.split(|&c| c == b'\n').enumerate() {
+        let err = |msg| EnvFileError { line: i + 1, msg };
+
+        let line = line.trim_ascii_start();
+        if line.is_empty() || line[0] == b'#' {
+            continue;
+        }
+        let line = line
+            .strip_prefix(b"export")
+            .filter(|rest| rest.first().is_some_and(u8::is_ascii_whitespace))
+            .map_or(line, <[u8]>::trim_ascii_start);
+
+        let pos = memchr::memchr(b'=', line).ok_or_else(|| err("missing ="))?;
+        let name = line[..pos].trim_ascii_end();
+        if !is_var_name(name) {
+            return Err(err("invalid variable name"));
+        }
+        let value = parse_value(line[pos + 1..].trim_ascii_start()).map_err(err)?;
+        if value.contains(&0) {
+            return Err(err("NUL byte in value"));
+        }
+        vars.push((
+            OsStr::from_bytes(name).to_owned(),
+            OsStr::from_bytes(&value).to_owned(),
+        ));
+    }
+
+    Ok(vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::ffi::OsStringExt;
+
+    use super::*;
+    use crate::conf::rules;
+
+    #[test]
+    fn environment() {
+        use crate::conf::Settings;
+
+        let vars = || {
+            [
+                ("HOME", "/home/alice"),
+                ("PATH", "/home/alice/bin:/usr/bin"),
+                ("LD_PRELOAD", "/tmp/evil.so"),
+                ("LD_LIBRARY_PATH", "/tmp"),
+                ("LC_ALL", "en_US.UTF-8"),
+                ("LC_MESSAGES", "../../tmp/x"),
+                ("TZ", "Europe/Rome"),
+                ("EDITOR", "/usr/bin/vim"),
+            ]
+            .map(|(k, v)| (OsString::from(k), OsString::from(v)))
+        };
+        let build = |conf: &str| {
+            let rule = &rules(conf)[0];
+            let mut settings = Settings::default();
+            settings.apply_rule(rule);
+            crate::env::build(
+                vars(),
+                &settings,
+                rule.setenv.as_deref().unwrap_or_default(),
+                &Default::default(),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|(k, v)| format!("{}={}", k.to_string_lossy(), v.to_string_lossy()))
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            build("rule { origin = alice; keepenv = true; setenv = { -LC_* }; }"),
+            [
+                "EDITOR=/usr/bin/vim",
+                "HOME=/home/alice",
+                "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin",
+            ]
+        );
+        assert_eq!(
+            build("rule { origin = alice; setenv = { LC_*, LD_*, EDITOR }; }"),
+            [
+                "EDITOR=/usr/bin/vim",
+                "LC_ALL=en_US.UTF-8",
+                "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"
+            ]
+        );
+        assert_eq!(
+            build(
+                r#"rule { origin = alice; env_check = { EDITOR }; secure_path = "/bin"; setenv = { LD_LIBRARY_PATH, TZ, EDITOR, -PATH, X="y" }; }"#
+            ),
+            ["LD_LIBRARY_PATH=/tmp", "TZ=Europe/Rome", "X=y"]
+        );
+
+        let text = "rule {\n    action = permit;\n    origin = alice;\n    setenv = { LC_?, -LD_* };\n    env_check = { TZ, LC_* };\n}";
+        assert_eq!(rules(text)[0].to_string(), text);
+        assert!(crate::conf::parse("rule { origin = alice; setenv = { LC_*=\"x\" }; }").is_err());
+    }
+
+    #[test]
+    fn env_templates() {
+        use crate::{conf::Settings, env::Builtins};
+
+        let vars = || {
+            [
+                ("HOME", b"/home/alice".as_slice()),
+                ("EMPTY", b""),
+                ("RAW", b"caf\xe9"),
+            ]
+            .map(|(k, v)| (OsString::from(k), OsString::from_vec(v.to_vec())))
+        };
+        let builtins = Builtins {
+            user: "alice".into(),
+            uid: 1000,
+            target_user: "root".into(),
+            target_home: "/root".into(),
+            tty: "/dev/pts/3".into(),
+            command: "/usr/bin/id".into(),
+            ..Default::default()
+        };
+        let build = |setenv: &str| {
+            let rule = &rules(&format!(
+                "rule {{ origin = alice; setenv = {{ {} }}; }}",
+                setenv
+            ))[0];
+            crate::env::build(
+                vars(),
+                &Settings::default(),
+                rule.setenv.as_deref().unwrap(),
+                &builtins,
+            )
+            .map(|env| {
+                env.into_iter()
+                    .filter(|(k, _)| k != "PATH")
+                    .map(|(k, v)| [k.into_vec(), b"=".to_vec(), v.into_vec()].concat())
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        assert_eq!(
+            build(r#"A="${EMPTY:-none}", B="${UNSET:-x\}y}", C="$RAW", D="", E="$UNSET""#).unwrap(),
+            [
+                b"A=none".to_vec(),
+                b"B=x}y".to_vec(),
+                b"C=caf\xe9".to_vec(),
+                b"D=".to_vec()
+            ]
+        );
+        assert_eq!(
+            build(r#"WHO="${PEZZO_USER}:${PEZZO_UID}->$PEZZO_TARGET_USER", H="${PEZZO_TARGET_HOME}", T="${PEZZO_TTY} ${PEZZO_COMMAND}""#)
+                .unwrap(),
+            [
+                b"H=/root".to_vec(),
+                b"T=/dev/pts/3 /usr/bin/id".to_vec(),
+                b"WHO=alice:1000->root".to_vec()
+            ]
+        );
+        assert_eq!(
+            build(r#"X="${HOME:?}""#).unwrap(),
+            [b"X=/home/alice".to_vec()]
+        );
+        assert_eq!(
+            build(r#"X="${EMPTY:?}""#).unwrap_err().to_string(),
+            "EMPTY is required by setenv but not set"
+        );
+
+        let text = r#"rule {
+    action = permit;
+    origin = alice;
+    setenv = { A="${B:-c\}\$}", D="${E:?}", F="" };
+}"#;
+        assert_eq!(rules(text)[0].to_string(), text);
+        assert!(
+            crate::conf::parse(r#"rule { origin = alice; setenv = { A="${PEZZO_NOPE}" }; }"#)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn envfile() {
+        use crate::env::{parse_envfile, EnvFileError};
+
+        let vars = parse_envfile(
+            b"# backup credentials\n\
+              \n\
+              DB_USER=backup\n\
+              export DB_PASSWORD='s3cr$t \"x\"'  \n\
+              DB_HOST = \"db\\n\\$1\"\n\
+              EMPTY=\n\
+              RAW=caf\xe9 \n",
+        )
+        .unwrap();
+        assert_eq!(
+            vars.iter()
+                .map(|(k, v)| [k.as_bytes(), b"=", v.as_bytes()].concat())
+                .collect::<Vec<_>>(),
+            [
+                b"DB_USER=backup".to_vec(),
+                b"DB_PASSWORD=s3cr$t \"x\"".to_vec(),
+                b"DB_HOST=db\n$1".to_vec(),
+                b"EMPTY=".to_vec(),
+                b"RAW=caf\xe9".to_vec(),
+            ]
+        );
+
+        for (buf, line, msg) in [
+            (b"A=1\nB\n".as_slice(), 2, "missing ="),
+            (b"1A=x", 1, "invalid variable name"),
+            (b"A=\"x", 1, "unterminated double quote"),
+            (b"A='x' y", 1, "text after the closing quote"),
+            (b"A=\"\\q\"", 1, "unknown escape"),
+        ] {
+            assert_eq!(parse_envfile(buf), Err(EnvFileError { line, msg }));
+        }
+
+        let rule = &rules("rule { origin = alice; envfile = /etc/pezzo/env/backup.env; }")[0];
+        assert_eq!(rule.envfile.as_deref(), Some(c"/etc/pezzo/env/backup.env"));
+        assert!(rule
+            .to_string()
+            .contains("    envfile = \"/etc/pezzo/env/backup.env\";\n"));
+        assert!(crate::conf::parse(r#"rule { origin = alice; envfile = "backup.env"; }"#).is_err());
+    }
+}
@@ -2,15 +2,10 @@ use std::ffi::CString;
 
 use tty_info::CStr;
 
+// The faccessat syscall has no flags, F_OK with the effective IDs is a stat.
 #[cfg(target_os = "linux")]
 fn is_file_accessible(file: &CStr) -> bool {
-    use linux_raw_sys::general::{AT_EACCESS, F_OK};
-    use linux_stat::CURRENT_DIRECTORY;
-    use linux_syscalls::{syscall, Sysno};
-
-    unsafe {
-        syscall!([ro] Sysno::faccessat, CURRENT_DIRECTORY, file.as_ptr(), AT_EACCESS | F_OK).is_ok()
-    }
+    linux_stat::stat_cstr(file).is_ok()
 }
 
 #[cfg(not(target_os = "linux"))]
@@ -269,7 +264,7 @@ pub fn realpath<P: AsRef<CStr>>(name: P) -> std::io::Result<CString> {
                 {
                     state.rname.reserve_exact(1);
                     let len = state.rname.len();
-                    *state.rname.get_unchecked_mut(len) = 0;
+                    *state.rname.as_mut_ptr().add(len) = 0;
                 }
                 {
                     let path = CStr::from_ptr(state.rname.as_ptr().cast());
@@ -281,19 +276,18 @@ pub fn realpath<P: AsRef<CStr>>(name: P) -> std::io::Result<CString> {
                             &mut state.extra
                         },
                     ) {
-                        let error = if suffix_requires_dir_check(end) {
-                            !dir_check(&mut state.rname)
+                        // Not a symbolic link, move on to the next component.
+                        let is_dir_or_file = if suffix_requires_dir_check(end) {
+                            dir_check(&mut state.rname)
                         } else {
-                            if err.kind() == std::io::ErrorKind::InvalidInput {
-                                start = end;
-                                continue;
-                            }
-                            true
+                            err.kind() == std::io::ErrorKind::InvalidInput
                         };
 
-                        if error {
+                        if !is_dir_or_file {
                             return Err(err);
                         }
+                        start = end;
+                        continue;
                     }
                 }
 
@@ -6,6 +6,10 @@ pub trait FileExt {
     fn lock_shared(&mut self) -> std::io::Result<()>;
 
     fn lock_exclusive(&mut self) -> std::io::Result<()>;
+
+    /// Like [`FileExt::lock_exclusive`], but returns `false` instead of
+    /// waiting if the lock is held by someone else.
+    fn try_lock_exclusive(&mut self) -> std::io::Result<bool>;
 }
 
 cfg_if::cfg_if! {
@@ -18,8 +22,8 @@ cfg_if::cfg_if! {
         use linux_stat::CURRENT_DIRECTORY;
         use super::AsRawFd;
         use linux_raw_sys::general::{
-            LOCK_EX, LOCK_SH, O_APPEND, O_CLOEXEC, O_CREAT, O_EXCL, O_RDONLY, O_RDWR, O_TRUNC,
-            O_WRONLY, SEEK_SET, SEEK_CUR, SEEK_END
+            LOCK_EX, LOCK_NB, LOCK_SH, O_APPEND, O_CLOEXEC, O_CREAT, O_EXCL, O_NOFOLLOW, O_RDONLY,
+            O_RDWR, O_TRUNC, O_WRONLY, SEEK_SET, SEEK_CUR, SEEK_END
         };
 
         pub struct File {
@@ -105,6 +109,17 @@ cfg_if::cfg_if! {
                     }
                 }
             }
+
+            fn try_lock_exclusive(&mut self) -> std::io::Result<bool> {
+                loop {
+                    match unsafe { syscall!([ro] Sysno::flock, self.as_raw_fd(), LOCK_EX | LOCK_NB) } {
+                        Err(Errno::EINTR) => (),
+                        Err(Errno::EAGAIN) => return Ok(false),
+                        Err(err) => return Err(err.into()),
+                        Ok(_) => return Ok(true),
+                    }
+                }
+            }
         }
 
         impl Seek for File {
@@ -158,6 +173,7 @@ cfg_if::cfg_if! {
         const O_TRUNC: u32 = libc::O_TRUNC as u32;
         const O_EXCL: u32 = libc::O_EXCL as u32;
         const O_CLOEXEC: u32 = libc::O_CLOEXEC as u32;
+        const O_NOFOLLOW: u32 = libc::O_NOFOLLOW as u32;
 
         pub fn remove_file<P: AsRef<CStr>>(path: P) -> std::io::Result<()> {
             loop {
@@ -188,6 +204,17 @@ cfg_if::cfg_if! {
                     Ok(())
                 }
             }
+
+            fn try_lock_exclusive(&mut self) -> std::io::Result<bool> {
+                if unsafe { libc::flock(self.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == -1 {
+                    match std::io::Error::last_os_error() {
+                        err if err.kind() == std::io::ErrorKind::WouldBlock => Ok(false),
+                        err => Err(err),
+                    }
+                } else {
+                    Ok(true)
+                }
+            }
         }
     }
 }
@@ -201,6 +228,7 @@ cfg_if::cfg_if! {
             truncate: bool,
             create: bool,
             create_new: bool,
+            nofollow: bool,
             mode: u16,
         }
 
@@ -221,6 +249,7 @@ cfg_if::cfg_if! {
                     truncate: false,
                     create: false,
                     create_new: false,
+                    nofollow: false,
                     mode: 0o666,
                 }
             }
@@ -255,6 +284,13 @@ cfg_if::cfg_if! {
                 self
             }
 
+            /// Fails with `ELOOP` if the last component of the path is a
+            /// symbolic link.
+            pub fn nofollow(&mut self, nofollow: bool) -> &mut Self {
+                self.nofollow = nofollow;
+                self
+            }
+
             pub fn mode(&mut self, mode: u16) -> &mut Self {
                 self.mode = mode;
                 self
@@ -298,7 +334,10 @@ cfg_if::cfg_if! {
             pub fn open_cstr<P: AsRef<CStr>>(&self, path: P) -> std::io::Result<File> {
                 let path = path.as_ref();
 
-                let flags = O_CLOEXEC | self.get_access_mode()? | self.get_creation_mode()?;
+                let mut flags = O_CLOEXEC | self.get_access_mode()? | self.get_creation_mode()?;
+                if self.nofollow {
+                    flags |= O_NOFOLLOW;
+                }
                 #[cfg(not(target_os = "linux"))]
                 {
                     loop {
@@ -329,3 +368,39 @@ cfg_if::cfg_if! {
         }
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::ffi::CString;
+
+    use super::*;
+
+    #[test]
+    fn try_lock_exclusive() {
+        let path = CString::new(format!(
+            "{}/pezzo-lock-{}",
+            std::env::temp_dir().display(),
+            std::process::id()
+        ))
+        .unwrap();
+        let open = || {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .mode(0o600)
+                .open_cstr(&path)
+                .unwrap()
+        };
+
+        let mut first = open();
+        let mut second = open();
+        assert!(first.try_lock_exclusive().unwrap());
+        assert!(!second.try_lock_exclusive().unwrap());
+        drop(first);
+        assert!(second.try_lock_exclusive().unwrap());
+
+        drop(second);
+        remove_file(&path).unwrap();
+    }
+}
@@ -2,7 +2,11 @@ use std::ffi::CStr;
 
 pub mod conf;
 pub mod database;
+pub mod env;
 pub mod io;
+pub mod lint;
+#[cfg(unix)]
+pub mod policy;
 #[cfg(unix)]
 pub mod unix;
 pub mod util;
@@ -13,5 +17,9 @@ pub const DEFAULT_PROMPT_TIMEOUT: u32 = 300;
 pub const PEZZO_NAME_CSTR: &CStr = unsafe { CStr::from_bytes_with_nul_unchecked(b"pezzo\0") };
 pub const DEFAULT_SESSION_TIMEOUT: u64 = 600;
 pub const DEFAULT_MAX_RETRIES: usize = 3;
+/// PATH commands are looked up in and run with, unless `secure_path` says
+/// otherwise.
+pub const DEFAULT_SECURE_PATH: &CStr =
+    c"/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
 
 include!(concat!(env!("OUT_DIR"), "/paths.rs"));
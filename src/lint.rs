@@ -0,0 +1,310 @@
+//! Checks for rules that are valid but most likely not what was meant.
+//!
+//! Coverage is decided conservatively: a rule is reported as shadowed or
+//! unreachable only when the other rule certainly matches every request it
+//! does, e.g. `/usr/bin/*` is known to cover `/usr/bin/id` but not
+//! `/usr/bin/i?`.
+
+use std::{
+    ffi::{CStr, CString},
+    fmt, io,
+};
+
+use globset::Glob;
+
+use crate::conf::{Action, Args, Ident, Origin, Patterns, Rule, Target};
+
+/// Programs that give a shell to whoever can run them.
+const INTERPRETERS: &[&str] = &[
+    "sh", "ash", "bash", "dash", "ksh", "mksh", "zsh", "csh", "tcsh", "fish", "busybox", "env",
+    "python", "python3", "perl", "ruby", "node", "php", "lua", "tclsh",
+];
+
+const BIN_DIRS: &[&str] = &["/bin", "/usr/bin", "/usr/local/bin"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lint {
+    /// A later `permit` matches everything the rule does and wins over it.
+    Shadowed { by: usize },
+    /// A `deny` matches everything the rule does.
+    Unreachable { by: usize },
+    /// An `exe` glob lets the rule run a shell or an interpreter.
+    Interpreter { glob: String, path: String },
+    /// The environment is kept without asking for a password.
+    KeepenvWithoutAskpass,
+    /// A target user is not in the database.
+    UnknownTarget(CString),
+    /// An `exe` glob can only match relative paths, commands never are.
+    RelativeExe(String),
+}
+
+/// A [`Lint`] found on the rule at index `rule`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub rule: usize,
+    pub lint: Lint,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rule #{}: ", self.rule + 1)?;
+        match self.lint {
+            Lint::Shadowed { by } => write!(f, "always overridden by rule #{}", by + 1),
+            Lint::Unreachable { by } => write!(f, "always denied by rule #{}", by + 1),
+            Lint::Interpreter { ref glob, ref path } => {
+                write!(f, "exe {} allows running {}", glob, path)
+            }
+            Lint::KeepenvWithoutAskpass => {
+                f.write_str("keepenv = true without asking for a password")
+            }
+            Lint::UnknownTarget(ref name) => {
+                write!(f, "target user {} does not exist", name.to_string_lossy())
+            }
+            Lint::RelativeExe(ref glob) => {
+                write!(f, "exe {} never matches an absolute path", glob)
+            }
+        }
+    }
+}
+
+#[inline]
+fn is_literal(glob: &Glob) -> bool {
+    !glob.glob().contains(['*', '?', '[', ']', '{', '}', '\\'])
+}
+
+fn glob_covers(outer: &Glob, inner: &Glob) -> bool {
+    outer.glob() == inner.glob()
+        || (is_literal(inner) && outer.compile_matcher().is_match(inner.glob()))
+}
+
+/// `None` matches anything.
+fn patterns_cover(outer: Option<&Patterns>, inner: Option<&Patterns>) -> bool {
+    match (outer, inner) {
+        (None, _) => true,
+        (Some(_), None) => false,
+        (Some(outer), Some(inner)) => inner
+            .globs()
+            .iter()
+            .all(|i| outer.globs().iter().any(|o| glob_covers(o, i))),
+    }
+}
+
+fn args_cover(outer: Option<&[Args]>, inner: Option<&[Args]>) -> bool {
+    let any = |a: &Args| a.variadic && a.patterns.is_empty();
+    match (outer, inner) {
+        (None, _) => true,
+        (Some(outer), None) => outer.iter().any(any),
+        (Some(outer), Some(inner)) => inner.iter().all(|i| {
+            outer
+                .iter()
+                .any(|o| any(o) || o.to_string() == i.to_string())
+        }),
+    }
+}
+
+fn origin_covers(outer: &[Origin], inner: &[Origin]) -> bool {
+    let covered = |id: &Ident, group: bool| {
+        outer.iter().any(|o| match o {
+            Origin::User(users) => !group && users.contains(id),
+            Origin::Group(groups) => group && groups.contains(id),
+        })
+    };
+
+    inner.iter().all(|i| match i {
+        Origin::User(idents) => idents.iter().all(|id| covered(id, false)),
+        Origin::Group(idents) => idents.iter().all(|id| covered(id, true)),
+    })
+}
+
+#[inline]
+fn subset(inner: &[Ident], outer: &[Ident]) -> bool {
+    inner.iter().all(|i| outer.contains(i))
+}
+
+fn target_idents(target: &Target) -> impl Iterator<Item = &Ident> {
+    let (users, groups): (&[Ident], &[Ident]) = match target {
+        Target::User(users) => (users, &[]),
+        Target::UserGroup(users, groups) => (users, groups),
+    };
+    users.iter().chain(groups)
+}
+
+/// Rules without a target match only users and groups in the database, so
+/// do the ones naming their targets.
+fn target_covers(outer: Option<&[Target]>, inner: Option<&[Target]>) -> bool {
+    match (outer, inner) {
+        (None, None) => true,
+        (None, Some(inner)) => inner
+            .iter()
+            .flat_map(target_idents)
+            .all(|i| matches!(i, Ident::Name(_))),
+        (Some(_), None) => false,
+        (Some(outer), Some(inner)) => inner.iter().all(|i| {
+            outer.iter().any(|o| match (o, i) {
+                (Target::User(o), Target::User(i)) => subset(i, o),
+                (Target::UserGroup(ou, og), Target::UserGroup(iu, ig)) => {
+                    subset(iu, ou) && subset(ig, og)
+                }
+                _ => false,
+            })
+        }),
+    }
+}
+
+fn time_covers(outer: &Rule, inner: &Rule) -> bool {
+    outer
+        .valid_from
+        .is_none_or(|o| inner.valid_from.is_some_and(|i| i >= o))
+        && outer
+            .valid_until
+            .is_none_or(|o| inner.valid_until.is_some_and(|i| i <= o))
+        && (outer.hours.is_none() || outer.hours == inner.hours)
+}
+
+/// Whether `outer` matches every request `inner` matches.
+pub fn covers(outer: &Rule, inner: &Rule) -> bool {
+    origin_covers(&outer.origin, &inner.origin)
+        && target_covers(outer.target.as_deref(), inner.target.as_deref())
+        && patterns_cover(outer.host.as_ref(), inner.host.as_ref())
+        && patterns_cover(outer.exe.as_ref(), inner.exe.as_ref())
+        && args_cover(outer.args.as_deref(), inner.args.as_deref())
+        && outer.edit.is_some() == inner.edit.is_some()
+        && patterns_cover(outer.edit.as_ref(), inner.edit.as_ref())
+        && time_covers(outer, inner)
+}
+
+/// With `literal_separator` only these can match the leading `/`.
+#[inline]
+fn can_match_absolute(glob: &Glob) -> bool {
+    glob.glob().starts_with(['/', '[', '{', '\\']) || glob.glob().starts_with("**")
+}
+
+fn interpreter(glob: &Glob) -> Option<String> {
+    let matcher = glob.compile_matcher();
+    BIN_DIRS
+        .iter()
+        .flat_map(|dir| {
+            INTERPRETERS
+                .iter()
+                .map(move |name| format!("{}/{}", dir, name))
+        })
+        .find(|path| matcher.is_match(path))
+}
+
+/// Lints `rules`, `user_exists` tells whether a target user is in the
+/// database.
+pub fn lint<F>(rules: &[Rule], mut user_exists: F) -> io::Result<Vec<Warning>>
+where
+    F: FnMut(&CStr) -> io::Result<bool>,
+{
+    let mut warnings = Vec::new();
+
+    for (i, rule) in rules.iter().enumerate() {
+        let mut warn = |lint| warnings.push(Warning { rule: i, lint });
+
+        if rule.action == Action::Permit {
+            let denied = rules
+                .iter()
+                .enumerate()
+                .find(|&(j, other)| j != i && other.action == Action::Deny && covers(other, rule));
+            let overridden = rules
+                .iter()
+                .enumerate()
+                .skip(i + 1)
+                .find(|&(_, other)| other.action == Action::Permit && covers(other, rule));
+            if let Some((by, _)) = denied {
+                warn(Lint::Unreachable { by });
+            } else if let Some((by, _)) = overridden {
+                warn(Lint::Shadowed { by });
+            }
+
+            if let Some(ref exe) = rule.exe {
+                for glob in exe.globs() {
+                    if let Some(path) = interpreter(glob) {
+                        warn(Lint::Interpreter {
+                            glob: glob.glob().to_string(),
+                            path,
+                        });
+                    }
+                }
+            }
+
+            if rule.keepenv == Some(true) && rule.askpass == Some(false) {
+                warn(Lint::KeepenvWithoutAskpass);
+            }
+        }
+
+        if let Some(ref exe) = rule.exe {
+            for glob in exe.globs().iter().filter(|g| !can_match_absolute(g)) {
+                warn(Lint::RelativeExe(glob.glob().to_string()));
+            }
+        }
+
+        let mut seen = Vec::new();
+        for target in rule.target.iter().flatten() {
+            let users = match target {
+                Target::User(users) | Target::UserGroup(users, _) => users,
+            };
+            for user in users {
+                if let Ident::Name(name) = user {
+                    if !seen.contains(&name) && !user_exists(name)? {
+                        warn(Lint::UnknownTarget(name.clone()));
+                    }
+                    seen.push(name);
+                }
+            }
+        }
+    }
+
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::rules;
+
+    #[test]
+    fn lints() {
+        let rules = rules(
+            r#"rule { origin = alice; exe = /usr/bin/id; }
+            rule { origin = alice | bob; exe = /usr/bin/*; }
+            rule { origin = :wheel; exe = /usr/bin/passwd; }
+            rule { action = deny; origin = :wheel | carol; }
+            rule { origin = :wheel; exe = /usr/bin/i?; }
+            rule { origin = bob; exe = usr/bin/vi | /usr/bin/vi; keepenv = true; askpass = false; }
+            rule { origin = bob; target = postgres | nobody:nogroup; exe = /usr/*/*sh; }"#,
+        );
+        let warnings = lint(&rules, |name| Ok(name.to_bytes() != b"nobody"))
+            .unwrap()
+            .into_iter()
+            .map(|w| (w.rule, w.lint))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            warnings,
+            [
+                (0, Lint::Shadowed { by: 1 }),
+                (
+                    1,
+                    Lint::Interpreter {
+                        glob: "/usr/bin/*".into(),
+                        path: "/usr/bin/sh".into(),
+                    }
+                ),
+                (2, Lint::Unreachable { by: 3 }),
+                (4, Lint::Unreachable { by: 3 }),
+                (5, Lint::KeepenvWithoutAskpass),
+                (5, Lint::RelativeExe("usr/bin/vi".into())),
+                (
+                    6,
+                    Lint::Interpreter {
+                        glob: "/usr/*/*sh".into(),
+                        path: "/usr/bin/sh".into(),
+                    }
+                ),
+                (6, Lint::UnknownTarget(c"nobody".into())),
+            ]
+        );
+    }
+}
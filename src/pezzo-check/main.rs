@@ -0,0 +1,496 @@
+use std::{
+    ffi::{CStr, CString, OsString},
+    io,
+    os::unix::prelude::OsStrExt,
+    str::FromStr,
+};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use pezzo::{
+    conf::{DateTime, LocalTime, Rules},
+    policy::{self, Subject, Verdict},
+    unix::{Group, User},
+};
+
+extern crate pezzo;
+
+/// A user or a group given as `NAME`, `#ID` or `NAME#ID`. Without a name the
+/// identity is not in the database, without an ID it can only match by name.
+#[derive(Debug, Clone)]
+pub struct Identity {
+    name: Option<CString>,
+    id: Option<u32>,
+}
+
+impl FromStr for Identity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, id) = match s.split_once('#') {
+            Some((name, id)) => (
+                name,
+                Some(id.parse().map_err(|_| format!("invalid ID {:?}", id))?),
+            ),
+            None => (s, None),
+        };
+        let name = if name.is_empty() {
+            None
+        } else {
+            Some(CString::new(name).map_err(|_| "invalid name".to_string())?)
+        };
+        if name.is_none() && id.is_none() {
+            return Err("empty name".to_string());
+        }
+
+        Ok(Self { name, id })
+    }
+}
+
+impl Identity {
+    #[inline]
+    fn is_known(&self) -> bool {
+        self.name.is_some()
+    }
+
+    fn name(&self) -> Box<CStr> {
+        match (&self.name, self.id) {
+            (Some(name), _) => name.clone().into_boxed_c_str(),
+            (None, id) => CString::new(format!("#{}", id.unwrap_or(u32::MAX)))
+                .unwrap()
+                .into_boxed_c_str(),
+        }
+    }
+
+    /// IDs nobody can have are used when only a name is given.
+    #[inline]
+    fn id(&self) -> u32 {
+        self.id.unwrap_or(u32::MAX)
+    }
+
+    #[inline]
+    fn user(&self) -> User {
+        User::new(self.id(), self.name())
+    }
+
+    #[inline]
+    fn group(&self) -> Group {
+        Group::new(self.id(), self.name())
+    }
+}
+
+fn parse_c_string(input: &str) -> Result<CString, &'static str> {
+    CString::new(input).map_err(|_| "Invalid string")
+}
+
+fn parse_datetime(input: &str) -> Result<DateTime, String> {
+    let invalid = || format!("invalid date {:?}, expected YYYY-MM-DD[THH:MM]", input);
+    let (date, time) = match input.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (input, None),
+    };
+
+    let mut date = date.splitn(3, '-').map(|p| p.parse::<u16>());
+    let (year, month, day) = match (date.next(), date.next(), date.next()) {
+        (Some(Ok(y)), Some(Ok(m)), Some(Ok(d)))
+            if (1..=12).contains(&m)
+                && d != 0
+                && d <= u16::from(DateTime::days_in_month(y, m as u8)) =>
+        {
+            (y, m as u8, d as u8)
+        }
+        _ => return Err(invalid()),
+    };
+    let minutes = match time.map(|t| t.split_once(':')) {
+        None => 0,
+        Some(Some((h, m))) => match (h.parse::<u16>(), m.parse::<u16>()) {
+            (Ok(h), Ok(m)) if h < 24 && m < 60 => h * 60 + m,
+            _ => return Err(invalid()),
+        },
+        Some(None) => return Err(invalid()),
+    };
+
+    Ok(DateTime {
+        year,
+        month,
+        day,
+        minutes,
+    })
+}
+
+/// Checks a pezzo configuration and, given a user and a command, tells
+/// whether the command would be allowed and why each rule does or does not
+/// match. No privileges are needed and the user database is never read.
+///
+/// Exits with 0 if the configuration is valid and the command, if any, is
+/// allowed, 1 if the command is not allowed and 2 on errors.
+#[derive(Debug, Parser)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    #[arg(short, long, value_parser = parse_c_string, value_name = "FILE", help("configuration file, the installed one by default"))]
+    pub file: Option<CString>,
+    #[arg(
+        short,
+        long,
+        value_name = "USER",
+        help("invoking user, as NAME, #ID or NAME#ID")
+    )]
+    pub user: Option<Identity>,
+    #[arg(
+        short,
+        long,
+        value_name = "GROUP",
+        help("primary group of the invoking user, same as the user by default")
+    )]
+    pub group: Option<Identity>,
+    #[arg(
+        short = 'G',
+        long,
+        value_name = "GROUP",
+        value_delimiter = ',',
+        help("supplementary groups of the invoking user")
+    )]
+    pub groups: Vec<Identity>,
+    #[arg(
+        short,
+        long,
+        value_name = "USER",
+        default_value = "root#0",
+        help("target user")
+    )]
+    pub target: Identity,
+    #[arg(
+        short = 'T',
+        long,
+        value_name = "GROUP",
+        help("target group, same as the target user by default")
+    )]
+    pub target_group: Option<Identity>,
+    #[arg(
+        long,
+        value_name = "GROUP",
+        value_delimiter = ',',
+        help("other groups the target user belongs to")
+    )]
+    pub target_groups: Vec<Identity>,
+    #[arg(long, value_parser = parse_c_string, value_name = "NAME", default_value = "root", help("name of the user with ID 0"))]
+    pub root: CString,
+    #[arg(short = 'H', long, value_parser = parse_c_string, help("host name, the local one by default"))]
+    pub host: Option<CString>,
+    #[arg(long, value_parser = parse_c_string, help("fully qualified domain name of the host"))]
+    pub fqdn: Option<CString>,
+    #[arg(long, value_parser = parse_datetime, value_name = "DATE", help("evaluate at YYYY-MM-DD[THH:MM] instead of now"))]
+    pub at: Option<DateTime>,
+    #[arg(
+        short,
+        long,
+        requires("user"),
+        help("check editing the files given as COMMAND with pezzo -e")
+    )]
+    pub edit: bool,
+    #[arg(trailing_var_arg(true), requires("user"))]
+    pub command: Vec<OsString>,
+}
+
+struct Simulation {
+    user: User,
+    group: Group,
+    groups: Vec<Group>,
+    target_user: User,
+    target_group: Group,
+    target_user_known: bool,
+    target_group_known: bool,
+    target_user_groups: Vec<Box<CStr>>,
+    root: CString,
+    hostname: CString,
+    fqdn: Option<CString>,
+    command: CString,
+    arguments: Vec<OsString>,
+    edit: bool,
+}
+
+impl Subject for Simulation {
+    #[inline]
+    fn user(&self) -> &User {
+        &self.user
+    }
+
+    #[inline]
+    fn group(&self) -> &Group {
+        &self.group
+    }
+
+    #[inline]
+    fn groups(&self) -> &[Group] {
+        &self.groups
+    }
+
+    #[inline]
+    fn target_user(&self) -> &User {
+        &self.target_user
+    }
+
+    #[inline]
+    fn target_group(&self) -> &Group {
+        &self.target_group
+    }
+
+    #[inline]
+    fn target_user_known(&self) -> bool {
+        self.target_user_known
+    }
+
+    #[inline]
+    fn target_group_known(&self) -> bool {
+        self.target_group_known
+    }
+
+    #[inline]
+    fn root_name(&self) -> io::Result<&CStr> {
+        Ok(&self.root)
+    }
+
+    #[inline]
+    fn target_user_groups(&self) -> io::Result<&[Box<CStr>]> {
+        Ok(&self.target_user_groups)
+    }
+
+    #[inline]
+    fn hostname(&self) -> &CStr {
+        &self.hostname
+    }
+
+    #[inline]
+    fn fqdn(&self) -> Option<&CStr> {
+        self.fqdn.as_deref()
+    }
+
+    #[inline]
+    fn command(&self) -> &CStr {
+        &self.command
+    }
+
+    #[inline]
+    fn arguments(&self) -> &[OsString] {
+        &self.arguments
+    }
+
+    #[inline]
+    fn edited(&self) -> Option<&[OsString]> {
+        self.edit.then_some(self.arguments.as_slice())
+    }
+}
+
+fn run(cli: Cli) -> Result<bool> {
+    let path = match cli.file {
+        Some(ref file) => file.as_c_str(),
+        None => unsafe { CStr::from_ptr(pezzo::CONFIG_PATH.as_ptr().cast()) },
+    };
+    let rules = pezzo::conf::load(path, |_, _| Ok(()))?;
+
+    let now = match cli.at {
+        Some(datetime) => LocalTime {
+            datetime,
+            weekday: datetime.weekday(),
+        },
+        None => policy::local_time(rules.timezone()).context("Cannot get local time")?,
+    };
+
+    if cli.user.is_none() {
+        for (i, rule) in rules.rules().iter().enumerate() {
+            if let Some(until) = rule.valid_until.filter(|_| rule.is_expired(&now)) {
+                eprintln!("warning: rule #{} expired (valid_until = {})", i + 1, until);
+            }
+        }
+        // Target users are not looked up, the database may not be the one
+        // of the host the configuration is for.
+        for warning in pezzo::lint::lint(rules.rules(), |_| Ok(true))? {
+            eprintln!("warning: {}", warning);
+        }
+        println!("{}: parsed OK", path.to_string_lossy());
+        return Ok(true);
+    }
+
+    simulate(cli, &rules, &now)
+}
+
+/// Evaluates `rules` at `now` for the user and the command of `cli`.
+fn simulate(cli: Cli, rules: &Rules, now: &LocalTime) -> Result<bool> {
+    let Cli {
+        user,
+        group,
+        groups,
+        target,
+        target_group,
+        target_groups,
+        root,
+        host,
+        fqdn,
+        edit,
+        mut command,
+        ..
+    } = cli;
+
+    let user = user.context("No user given")?;
+    if command.is_empty() {
+        bail!("No command given");
+    }
+    let arg0 = if edit {
+        // Files are matched as given, they are not canonicalized.
+        if let Some(file) = command.iter().find(|f| f.as_bytes().first() != Some(&b'/')) {
+            bail!("{:?}: file must be an absolute path", file);
+        }
+        OsString::new()
+    } else {
+        let arg0 = command.remove(0);
+        if arg0.as_bytes().first() != Some(&b'/') {
+            bail!("{:?}: command must be an absolute path", arg0);
+        }
+        arg0
+    };
+
+    let target_group = target_group.unwrap_or_else(|| target.clone());
+    let mut target_user_groups: Vec<Box<CStr>> = target_groups.iter().map(Identity::name).collect();
+    target_user_groups.push(target_group.name());
+
+    let sim = Simulation {
+        group: group.unwrap_or_else(|| user.clone()).group(),
+        user: user.user(),
+        groups: groups.iter().map(Identity::group).collect(),
+        target_user: target.user(),
+        target_group: target_group.group(),
+        target_user_known: target.is_known(),
+        target_group_known: target_group.is_known(),
+        target_user_groups,
+        root,
+        hostname: host.unwrap_or_else(pezzo::unix::hostname),
+        fqdn,
+        command: CString::new(arg0.into_encoded_bytes()).context("Invalid command")?,
+        arguments: command,
+        edit,
+    };
+
+    for (i, rule) in rules.rules().iter().enumerate() {
+        match policy::check(&sim, rule, now).context("Cannot match rule")? {
+            Some(mismatch) => println!("rule #{}: {}", i + 1, mismatch),
+            None => println!("rule #{}: matches ({})", i + 1, rule.action),
+        }
+    }
+
+    let verdict = policy::verdict(&sim, rules.rules(), now).context("Cannot match rule")?;
+    Ok(match verdict {
+        Some(Verdict::Permit(i)) => {
+            println!("permitted by rule #{}", i + 1);
+            true
+        }
+        Some(Verdict::Deny(i)) => {
+            println!("denied by rule #{}", i + 1);
+            false
+        }
+        None => {
+            println!("no rule matches");
+            false
+        }
+    })
+}
+
+/// See [`Cli`].
+fn exit_code(res: &Result<bool>) -> i32 {
+    match res {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(_) => 2,
+    }
+}
+
+fn main() {
+    #[cfg(target_os = "linux")]
+    linux_syscalls::init();
+
+    let res = run(Cli::parse());
+    if let Err(ref err) = res {
+        eprintln!("{:?}", err);
+    }
+    std::process::exit(exit_code(&res));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pezzo::conf::Statement;
+
+    fn simulate(conf: &str, args: &[&str]) -> Result<bool> {
+        let rules = pezzo::conf::parse(conf)
+            .unwrap()
+            .into_iter()
+            .filter_map(|s| match s {
+                Statement::Rule(rule) => Some(*rule),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut cli = Cli::try_parse_from(["pezzo-check"].iter().chain(args)).unwrap();
+        cli.host.get_or_insert_with(|| c"web1".into());
+        let datetime = parse_datetime("2024-01-01T12:00").unwrap();
+        let now = LocalTime {
+            datetime,
+            weekday: datetime.weekday(),
+        };
+        super::simulate(cli, &Rules::from(rules), &now)
+    }
+
+    #[test]
+    fn verdicts() {
+        let conf = "rule { origin = alice; }
+            rule { action = deny; origin = alice; exe = /usr/bin/passwd; }
+            rule { origin = :ops; target = postgres; host = db*; }
+            rule { origin = carol; edit = /etc/nginx/**; }";
+        let code = |args: &[&str]| exit_code(&simulate(conf, args));
+
+        assert_eq!(code(&["-u", "alice", "/usr/bin/id"]), 0);
+        assert_eq!(code(&["-u", "alice", "/usr/bin/passwd"]), 1);
+        assert_eq!(code(&["-u", "bob", "/usr/bin/id"]), 1);
+        assert_eq!(code(&["-u", "alice", "id"]), 2);
+        assert_eq!(
+            code(&["-u", "bob", "-G", "ops", "-t", "postgres", "/usr/bin/id"]),
+            1
+        );
+        assert_eq!(
+            code(&[
+                "-u",
+                "bob",
+                "-G",
+                "ops",
+                "-t",
+                "postgres",
+                "-H",
+                "db1",
+                "/usr/bin/id"
+            ]),
+            0
+        );
+        assert_eq!(code(&["-u", "carol", "-e", "/etc/nginx/nginx.conf"]), 0);
+        assert_eq!(code(&["-u", "carol", "-e", "/etc/shadow"]), 1);
+        assert_eq!(code(&["-u", "carol", "-e", "nginx.conf"]), 2);
+    }
+
+    #[test]
+    fn dates() {
+        let date = |s: &str| parse_datetime(s).map(|d| d.to_string());
+
+        assert_eq!(date("2024-02-29").unwrap(), "2024-02-29");
+        assert_eq!(date("2000-02-29T23:59").unwrap(), "2000-02-29 23:59");
+        assert_eq!(date("2024-12-31 08:05").unwrap(), "2024-12-31 08:05");
+        for invalid in [
+            "2024-02-31",
+            "2023-02-29",
+            "1900-02-29",
+            "2024-04-31",
+            "2024-13-01",
+            "2024-01-00",
+            "2024-01-01T24:00",
+            "2024-01-01T12",
+        ] {
+            assert!(date(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,978 @@
+mod sudoers;
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::CString,
+    fmt::Write as _,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use globset::GlobBuilder;
+use pezzo::conf::{ArgPattern, Args, Ident, Patterns};
+use sudoers::{AliasKind, Command, Entry, Member, Operator, Privilege, Runas, Setting};
+
+extern crate pezzo;
+
+/// Deeper alias chains are taken for cycles.
+const MAX_ALIAS_DEPTH: usize = 64;
+
+/// Converts a sudoers file into a pezzo configuration. Constructs that cannot
+/// be translated are reported on stderr, rules that would grant more than the
+/// original ones are left out.
+///
+/// Exits with 0 if everything was translated, 1 if something was left out and
+/// 2 on errors.
+#[derive(Debug, Parser)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    #[arg(
+        short,
+        long,
+        value_name = "FILE",
+        help("write the configuration to FILE instead of stdout")
+    )]
+    pub output: Option<PathBuf>,
+    #[arg(
+        value_name = "SUDOERS",
+        default_value = "/etc/sudoers",
+        help("sudoers file to convert")
+    )]
+    pub file: PathBuf,
+}
+
+/// How a sudoers alias is translated: as a pezzo alias of some kind, or by
+/// expanding its members wherever it is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Translation {
+    Users,
+    Groups,
+    Hosts,
+    Commands,
+    Inline,
+}
+
+struct Alias {
+    location: String,
+    kind: AliasKind,
+    members: Vec<Member>,
+    translation: Translation,
+}
+
+struct Globals {
+    timeout: Option<u64>,
+    authenticate: bool,
+    keepenv: bool,
+    env_keep: Vec<String>,
+}
+
+impl Default for Globals {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            authenticate: true,
+            keepenv: false,
+            env_keep: Vec::new(),
+        }
+    }
+}
+
+/// A command of a user spec, `exe` is empty for any command.
+struct Cmd {
+    negated: bool,
+    exe: Vec<String>,
+    args: Option<String>,
+}
+
+#[derive(PartialEq, Eq)]
+struct RuleOut {
+    location: String,
+    negated: bool,
+    origin: Vec<String>,
+    target: Option<String>,
+    host: Option<Vec<String>>,
+    exe: Vec<String>,
+    args: Option<String>,
+    askpass: Option<bool>,
+    keepenv: bool,
+}
+
+impl RuleOut {
+    /// Commands without arguments that share everything else go in a single
+    /// rule.
+    fn merge(&mut self, other: RuleOut) -> Option<RuleOut> {
+        let mergeable = self.args.is_none()
+            && other.args.is_none()
+            && !self.exe.is_empty()
+            && !other.exe.is_empty()
+            && self.location == other.location
+            && self.negated == other.negated
+            && self.origin == other.origin
+            && self.target == other.target
+            && self.host == other.host
+            && self.askpass == other.askpass
+            && self.keepenv == other.keepenv;
+        if !mergeable {
+            return Some(other);
+        }
+
+        for exe in other.exe {
+            if !self.exe.contains(&exe) {
+                self.exe.push(exe);
+            }
+        }
+        None
+    }
+}
+
+#[derive(Default)]
+struct Importer {
+    stack: Vec<PathBuf>,
+    entries: Vec<(String, Entry)>,
+    aliases: HashMap<String, Alias>,
+    alias_order: Vec<String>,
+    globals: Globals,
+    notes: RefCell<Vec<String>>,
+}
+
+#[inline]
+fn paren(items: &[String]) -> String {
+    if let [item] = items {
+        item.clone()
+    } else {
+        format!("({})", items.join(" | "))
+    }
+}
+
+fn ident(name: &str) -> Result<String, String> {
+    let ident = match name.strip_prefix('#').map(str::parse::<u32>) {
+        Some(Ok(id)) => Ident::Id(id),
+        _ => Ident::Name(CString::new(name).map_err(|_| format!("invalid name {:?}", name))?),
+    };
+    Ok(ident.to_string())
+}
+
+fn glob(pattern: &str, case_insensitive: bool) -> Result<globset::Glob, String> {
+    GlobBuilder::new(pattern)
+        .literal_separator(!case_insensitive)
+        .case_insensitive(case_insensitive)
+        .build()
+        .map_err(|_| format!("invalid pattern {:?}", pattern))
+}
+
+/// A single exe or host pattern as written in the pezzo configuration.
+fn pattern(pattern: &str, case_insensitive: bool) -> Result<String, String> {
+    Patterns::new(vec![glob(pattern, case_insensitive)?])
+        .map(|p| p.to_string())
+        .map_err(|_| format!("invalid pattern {:?}", pattern))
+}
+
+/// Hosts match regardless of case, and an upper case host name would be read
+/// back as an alias.
+fn host_pattern(host: &str) -> Result<String, String> {
+    pattern(&host.to_ascii_lowercase(), true)
+}
+
+/// A directory stands for all the commands in it.
+fn exe_pattern(exe: &str) -> Result<String, String> {
+    if exe.ends_with('/') {
+        pattern(&format!("{}*", exe), false)
+    } else {
+        pattern(exe, false)
+    }
+}
+
+fn is_address(host: &str) -> bool {
+    host.contains('/') || host.parse::<std::net::IpAddr>().is_ok()
+}
+
+/// A variable name, `*` and `?` are wildcards in pezzo too.
+fn is_env_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || matches!(c, '_' | '*' | '?'))
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '*' | '?'))
+}
+
+/// sudoers ignores files in an `#includedir` whose name ends with `~` or
+/// contains a dot.
+fn list_include_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("Cannot read directory {:?}", dir))?
+    {
+        let entry = entry.with_context(|| format!("Cannot read directory {:?}", dir))?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.ends_with('~') || name.contains('.') {
+            continue;
+        }
+        paths.push(entry.path());
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+impl Importer {
+    fn note<S: Into<String>>(&self, location: &str, message: S) {
+        self.notes
+            .borrow_mut()
+            .push(format!("{}: {}", location, message.into()));
+    }
+
+    fn read(&mut self, path: &Path) -> Result<()> {
+        let canonical =
+            std::fs::canonicalize(path).with_context(|| format!("Cannot resolve {:?}", path))?;
+        if self.stack.contains(&canonical) {
+            bail!("{}: include cycle detected", path.display());
+        }
+        let content = std::fs::read(path).with_context(|| format!("Cannot read {:?}", path))?;
+
+        self.stack.push(canonical);
+        self.add(path, &String::from_utf8_lossy(&content))?;
+        self.stack.pop();
+        Ok(())
+    }
+
+    fn add(&mut self, path: &Path, content: &str) -> Result<()> {
+        for (number, line) in sudoers::logical_lines(content) {
+            let location = format!("{}:{}", path.display(), number);
+            let entries = match sudoers::parse_line(&line) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    self.note(&location, format!("{}, line skipped", err));
+                    continue;
+                }
+            };
+
+            for entry in entries {
+                match entry {
+                    Entry::Include(ref include) | Entry::IncludeDir(ref include)
+                        if include.contains('%') =>
+                    {
+                        self.note(&location, "include with escapes not supported, skipped");
+                    }
+                    Entry::Include(include) => {
+                        let include = path.parent().unwrap_or(Path::new("/")).join(include);
+                        self.read(&include)
+                            .with_context(|| format!("included from {}", location))?;
+                    }
+                    Entry::IncludeDir(dir) => {
+                        let dir = path.parent().unwrap_or(Path::new("/")).join(dir);
+                        if !dir.is_dir() {
+                            self.note(&location, format!("{:?} not found, skipped", dir));
+                            continue;
+                        }
+                        for include in list_include_dir(&dir)? {
+                            self.read(&include)
+                                .with_context(|| format!("included from {}", location))?;
+                        }
+                    }
+                    entry => self.entries.push((location.clone(), entry)),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn alias(&self, name: &str, kind: AliasKind) -> Option<&Alias> {
+        self.aliases.get(name).filter(|a| a.kind == kind)
+    }
+
+    /// What a single alias member would make of a pezzo alias, `None` if it
+    /// cannot be part of one.
+    fn classify_member(
+        &self,
+        kind: AliasKind,
+        member: &Member,
+        depth: usize,
+    ) -> Option<Translation> {
+        let name = member.name.as_str();
+        if member.negated || name == "ALL" || depth > MAX_ALIAS_DEPTH {
+            return None;
+        }
+        if let Some(alias) = self.alias(name, kind) {
+            return match self.classify(alias, depth + 1) {
+                Translation::Inline => None,
+                t => Some(t),
+            };
+        }
+
+        match kind {
+            AliasKind::User if name.starts_with('+') || name.starts_with("%:") => None,
+            AliasKind::User if name.starts_with('%') => Some(Translation::Groups),
+            AliasKind::User => Some(Translation::Users),
+            AliasKind::Runas if name.starts_with('+') || name.starts_with('%') => None,
+            AliasKind::Runas => Some(Translation::Users),
+            AliasKind::Host if name.starts_with('+') || is_address(name) => None,
+            AliasKind::Host => Some(Translation::Hosts),
+            AliasKind::Cmnd => match sudoers::split_command(name).as_slice() {
+                [exe] if exe.starts_with('/') => Some(Translation::Commands),
+                _ => None,
+            },
+        }
+    }
+
+    fn classify(&self, alias: &Alias, depth: usize) -> Translation {
+        let mut translation = None;
+        for member in &alias.members {
+            match self.classify_member(alias.kind, member, depth) {
+                Some(t) if translation.is_none_or(|x| x == t) => translation = Some(t),
+                _ => return Translation::Inline,
+            }
+        }
+        translation.unwrap_or(Translation::Inline)
+    }
+
+    fn users(&self, members: &[Member], out: &mut Vec<String>, depth: usize) -> Result<(), String> {
+        if depth > MAX_ALIAS_DEPTH {
+            return Err("recursive alias".to_string());
+        }
+
+        for member in members {
+            let name = member.name.as_str();
+            if member.negated {
+                return Err(format!("negated user {:?} not supported", name));
+            }
+            if let Some(alias) = self.alias(name, AliasKind::User) {
+                match alias.translation {
+                    Translation::Users => out.push(name.to_string()),
+                    Translation::Groups => out.push(format!(":{}", name)),
+                    _ => self.users(&alias.members, out, depth + 1)?,
+                }
+            } else if name == "ALL" {
+                return Err("ALL as user not supported".to_string());
+            } else if name.starts_with('+') {
+                return Err(format!("netgroup {:?} not supported", name));
+            } else if name.starts_with("%:") {
+                return Err(format!("non-Unix group {:?} not supported", name));
+            } else if let Some(group) = name.strip_prefix('%') {
+                out.push(format!(":{}", ident(group)?));
+            } else {
+                out.push(ident(name)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run-as users or groups, `None` if `ALL` is among them.
+    fn runas_list(
+        &self,
+        members: &[Member],
+        groups: bool,
+        depth: usize,
+    ) -> Result<Option<Vec<String>>, String> {
+        if depth > MAX_ALIAS_DEPTH {
+            return Err("recursive alias".to_string());
+        }
+
+        let mut out = Vec::new();
+        for member in members {
+            let name = member.name.as_str();
+            if member.negated {
+                return Err(format!("negated run-as {:?} not supported", name));
+            }
+            if let Some(alias) = self.alias(name, AliasKind::Runas) {
+                if alias.translation == Translation::Users && !groups {
+                    out.push(name.to_string());
+                } else {
+                    match self.runas_list(&alias.members, groups, depth + 1)? {
+                        Some(members) => out.extend(members),
+                        None => return Ok(None),
+                    }
+                }
+            } else if name == "ALL" {
+                return Ok(None);
+            } else if name.starts_with('%') || name.starts_with('+') {
+                return Err(format!("run-as {:?} not supported", name));
+            } else {
+                out.push(ident(name)?);
+            }
+        }
+
+        Ok(Some(out))
+    }
+
+    fn target(&self, location: &str, runas: Option<&Runas>) -> Result<Option<String>, String> {
+        let runas = match runas {
+            Some(runas) if runas.users.is_some() || runas.groups.is_some() => runas,
+            _ => return Ok(Some(ident("root")?)),
+        };
+        let users = match runas.users {
+            Some(ref users) => self.runas_list(users, false, 0)?,
+            None => return Err("run-as group without user not supported".to_string()),
+        };
+        let groups = match runas.groups {
+            Some(ref groups) => self.runas_list(groups, true, 0)?,
+            None => return Ok(users.map(|users| users.join(" | "))),
+        };
+
+        match (users, groups) {
+            (None, None) => Ok(None),
+            (None, Some(_)) => Err("any run-as user with given groups not supported".to_string()),
+            (Some(users), None) => {
+                self.note(
+                    location,
+                    "run-as group ALL restricted to the groups of the target user",
+                );
+                Ok(Some(users.join(" | ")))
+            }
+            (Some(users), Some(groups)) => {
+                Ok(Some(format!("{}:{}", paren(&users), paren(&groups))))
+            }
+        }
+    }
+
+    /// Hosts, `None` if any.
+    fn hosts(&self, members: &[Member], depth: usize) -> Result<Option<Vec<String>>, String> {
+        if depth > MAX_ALIAS_DEPTH {
+            return Err("recursive alias".to_string());
+        }
+
+        let mut out = Vec::new();
+        for member in members {
+            let name = member.name.as_str();
+            if member.negated {
+                return Err(format!("negated host {:?} not supported", name));
+            }
+            if let Some(alias) = self.alias(name, AliasKind::Host) {
+                if alias.translation == Translation::Hosts {
+                    out.push(name.to_string());
+                } else {
+                    match self.hosts(&alias.members, depth + 1)? {
+                        Some(hosts) => out.extend(hosts),
+                        None => return Ok(None),
+                    }
+                }
+            } else if name == "ALL" {
+                return Ok(None);
+            } else if name.starts_with('+') {
+                return Err(format!("netgroup {:?} not supported", name));
+            } else if is_address(name) {
+                return Err(format!("host address {:?} not supported", name));
+            } else {
+                out.push(host_pattern(name)?);
+            }
+        }
+
+        Ok(Some(out))
+    }
+
+    fn commands(&self, member: &Member, out: &mut Vec<Cmd>, depth: usize) -> Result<(), String> {
+        if depth > MAX_ALIAS_DEPTH {
+            return Err("recursive alias".to_string());
+        }
+
+        let name = member.name.as_str();
+        if let Some(alias) = self.alias(name, AliasKind::Cmnd) {
+            if alias.translation == Translation::Commands {
+                out.push(Cmd {
+                    negated: member.negated,
+                    exe: vec![name.to_string()],
+                    args: None,
+                });
+            } else {
+                for m in &alias.members {
+                    let m = Member {
+                        negated: m.negated != member.negated,
+                        name: m.name.clone(),
+                    };
+                    self.commands(&m, out, depth + 1)?;
+                }
+            }
+            return Ok(());
+        }
+        if name == "ALL" {
+            out.push(Cmd {
+                negated: member.negated,
+                exe: Vec::new(),
+                args: None,
+            });
+            return Ok(());
+        }
+
+        let words = sudoers::split_command(name);
+        let exe = words.first().map_or("", String::as_str);
+        if exe == "sudoedit" {
+            return Err("sudoedit not supported".to_string());
+        }
+        if !exe.starts_with('/') {
+            return Err(format!("command {:?} is not an absolute path", exe));
+        }
+        let exe = exe_pattern(exe)?;
+
+        let args = match &words[1..] {
+            [] => None,
+            [empty] if empty == "\"\"" => Some(Args {
+                patterns: Box::new([]),
+                variadic: false,
+            }),
+            [args @ .., last] => {
+                let (args, variadic) = if last == "*" {
+                    (args, true)
+                } else {
+                    (&words[1..], false)
+                };
+                let patterns = args
+                    .iter()
+                    .map(|a| glob(a, false).map(|g| ArgPattern::Glob(g.compile_matcher())))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Some(Args {
+                    patterns: patterns.into_boxed_slice(),
+                    variadic,
+                })
+            }
+        };
+
+        out.push(Cmd {
+            negated: member.negated,
+            exe: vec![exe],
+            args: args.map(|a| a.to_string()),
+        });
+        Ok(())
+    }
+
+    fn command_rules(
+        &self,
+        location: &str,
+        origin: &[String],
+        host: &Option<Vec<String>>,
+        command: &Command,
+        out: &mut Vec<RuleOut>,
+    ) -> Result<(), String> {
+        if let Some(option) = command.options.first() {
+            return Err(format!("{} not supported", option));
+        }
+
+        let mut askpass = (!self.globals.authenticate).then_some(false);
+        let mut keepenv = self.globals.keepenv;
+        for tag in &command.tags {
+            match *tag {
+                "NOPASSWD" => askpass = Some(false),
+                "PASSWD" => askpass = (!self.globals.authenticate).then_some(true),
+                "SETENV" => {
+                    self.note(
+                        location,
+                        "SETENV converted to keepenv = true, which keeps the whole environment",
+                    );
+                    keepenv = true
+                }
+                "NOEXEC" | "INTERCEPT" => return Err(format!("{} not supported", tag)),
+                "LOG_INPUT" | "LOG_OUTPUT" | "MAIL" | "FOLLOW" => {
+                    self.note(location, format!("{} ignored", tag))
+                }
+                _ => (),
+            }
+        }
+
+        let target = self.target(location, command.runas.as_ref())?;
+        let mut cmds = Vec::new();
+        self.commands(&command.command, &mut cmds, 0)?;
+
+        for cmd in cmds {
+            let rule = RuleOut {
+                location: location.to_string(),
+                negated: cmd.negated,
+                origin: origin.to_vec(),
+                target: target.clone(),
+                host: host.clone(),
+                exe: cmd.exe,
+                args: cmd.args,
+                askpass,
+                keepenv,
+            };
+            let rule = match out.last_mut() {
+                Some(last) => last.merge(rule),
+                None => Some(rule),
+            };
+            out.extend(rule);
+        }
+
+        Ok(())
+    }
+
+    fn privilege_rules(
+        &self,
+        location: &str,
+        users: &[Member],
+        privilege: &Privilege,
+    ) -> Vec<RuleOut> {
+        let mut origin = Vec::new();
+        let host = self
+            .users(users, &mut origin, 0)
+            .and_then(|_| self.hosts(&privilege.hosts, 0));
+        let host = match host {
+            Ok(host) => host,
+            Err(err) => {
+                self.note(location, format!("{}, rule skipped", err));
+                return Vec::new();
+            }
+        };
+
+        let mut rules = Vec::new();
+        for command in &privilege.commands {
+            let mut out = Vec::new();
+            match self.command_rules(location, &origin, &host, command, &mut out) {
+                Ok(()) => {
+                    for rule in out {
+                        let rule = match rules.last_mut() {
+                            Some(last) => RuleOut::merge(last, rule),
+                            None => Some(rule),
+                        };
+                        rules.extend(rule);
+                    }
+                }
+                Err(err) => self.note(
+                    location,
+                    format!("{}, rule for {:?} skipped", err, command.command.name),
+                ),
+            }
+        }
+        rules
+    }
+
+    fn defaults(&mut self, location: &str, scope: Option<&str>, settings: Vec<Setting>) {
+        if let Some(scope) = scope {
+            self.note(
+                location,
+                format!("Defaults{} not supported, skipped", scope),
+            );
+            return;
+        }
+
+        for setting in settings {
+            match setting {
+                Setting::Value(name, Operator::Set, value) if name == "timestamp_timeout" => {
+                    match value.parse::<f64>() {
+                        Ok(minutes) if minutes >= 0.0 => {
+                            self.globals.timeout = Some((minutes * 60.0).round() as u64)
+                        }
+                        _ => self.note(
+                            location,
+                            format!("timestamp_timeout={} not supported", value),
+                        ),
+                    }
+                }
+                Setting::Value(name, op, value) if name == "env_keep" => {
+                    let vars = value.split_whitespace().filter(|v| {
+                        is_env_name(v) || {
+                            self.note(location, format!("env_keep {:?} not supported", v));
+                            false
+                        }
+                    });
+                    let vars = vars.map(str::to_string).collect::<Vec<_>>();
+                    match op {
+                        Operator::Set => self.globals.env_keep = vars,
+                        Operator::Add => {
+                            for var in vars {
+                                if !self.globals.env_keep.contains(&var) {
+                                    self.globals.env_keep.push(var);
+                                }
+                            }
+                        }
+                        Operator::Remove => self.globals.env_keep.retain(|v| !vars.contains(v)),
+                    }
+                }
+                Setting::Flag(value, name) if name == "authenticate" => {
+                    self.globals.authenticate = value
+                }
+                Setting::Flag(value, name) if name == "env_reset" => self.globals.keepenv = !value,
+                Setting::Flag(_, name) | Setting::Value(name, _, _) => {
+                    self.note(location, format!("Defaults {} not supported", name))
+                }
+            }
+        }
+    }
+
+    fn alias_definition(&self, name: &str, alias: &Alias) -> Result<String, String> {
+        let items = alias
+            .members
+            .iter()
+            .map(|m| {
+                let n = m.name.as_str();
+                match alias.translation {
+                    _ if self.alias(n, alias.kind).is_some() => Ok(n.to_string()),
+                    Translation::Groups => ident(&n[1..]),
+                    Translation::Hosts => host_pattern(n),
+                    Translation::Commands => exe_pattern(&sudoers::split_command(n)[0]),
+                    _ => ident(n),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let kind = match alias.translation {
+            Translation::Users => "users",
+            Translation::Groups => "groups",
+            Translation::Hosts => "hosts",
+            _ => "commands",
+        };
+        Ok(format!("alias {} {} = {};", kind, name, items.join(" | ")))
+    }
+
+    fn write_rule(&self, out: &mut String, rule: &RuleOut) {
+        _ = writeln!(out, "\n# {}", rule.location);
+        _ = writeln!(out, "rule {{");
+        if rule.negated {
+            _ = writeln!(out, "    action = deny;");
+        }
+        _ = writeln!(out, "    origin = {};", rule.origin.join(" | "));
+        if let Some(ref target) = rule.target {
+            _ = writeln!(out, "    target = {};", target);
+        }
+        if let Some(ref host) = rule.host {
+            _ = writeln!(out, "    host = {};", host.join(" | "));
+        }
+        if !rule.exe.is_empty() {
+            _ = writeln!(out, "    exe = {};", rule.exe.join(" | "));
+        }
+        if let Some(ref args) = rule.args {
+            _ = writeln!(out, "    args = {};", args);
+        }
+        if let Some(askpass) = rule.askpass {
+            _ = writeln!(out, "    askpass = {};", askpass);
+        }
+        if rule.keepenv {
+            _ = writeln!(out, "    keepenv = true;");
+        } else if !self.globals.env_keep.is_empty() {
+            _ = writeln!(
+                out,
+                "    setenv = {{ {} }};",
+                self.globals.env_keep.join(", ")
+            );
+        }
+        if let Some(timeout) = self.globals.timeout {
+            _ = writeln!(out, "    timeout = {};", timeout);
+        }
+        _ = writeln!(out, "}}");
+    }
+
+    /// Translates everything read so far, returns the configuration and the
+    /// report of what could not be translated.
+    fn convert(mut self, source: &Path) -> (String, Vec<String>) {
+        let entries = std::mem::take(&mut self.entries);
+
+        let mut specs = Vec::new();
+        for (location, entry) in entries {
+            match entry {
+                Entry::Alias {
+                    kind,
+                    name,
+                    members,
+                } => {
+                    if self.aliases.contains_key(&name) {
+                        self.note(&location, format!("alias {} redefined, skipped", name));
+                        continue;
+                    }
+                    self.alias_order.push(name.clone());
+                    self.aliases.insert(
+                        name,
+                        Alias {
+                            location,
+                            kind,
+                            members,
+                            translation: Translation::Inline,
+                        },
+                    );
+                }
+                Entry::Defaults { scope, settings } => {
+                    self.defaults(&location, scope.as_deref(), settings)
+                }
+                Entry::UserSpec { users, privileges } => specs.push((location, users, privileges)),
+                Entry::Include(_) | Entry::IncludeDir(_) => (),
+            }
+        }
+
+        for name in &self.alias_order {
+            let translation = self.classify(&self.aliases[name], 0);
+            self.aliases.get_mut(name).unwrap().translation = translation;
+        }
+
+        let mut out = format!(
+            "# Converted from {} by pezzo-import-sudoers, review it before installing.\n",
+            source.display()
+        );
+        let mut aliases = String::new();
+        for name in &self.alias_order {
+            let alias = &self.aliases[name];
+            if alias.translation == Translation::Inline {
+                continue;
+            }
+            match self.alias_definition(name, alias) {
+                Ok(def) => _ = writeln!(aliases, "{}", def),
+                Err(err) => {
+                    self.note(&alias.location, format!("{}, alias skipped", err));
+                    self.aliases.get_mut(name).unwrap().translation = Translation::Inline;
+                }
+            }
+        }
+        if !aliases.is_empty() {
+            out.push('\n');
+            out.push_str(&aliases);
+        }
+
+        for (location, users, privileges) in specs {
+            for privilege in &privileges {
+                for rule in self.privilege_rules(&location, &users, privilege) {
+                    self.write_rule(&mut out, &rule);
+                }
+            }
+        }
+
+        (out, self.notes.into_inner())
+    }
+}
+
+fn _main() -> Result<bool> {
+    let Cli { output, file } = Cli::parse();
+
+    let mut importer = Importer::default();
+    importer.read(&file)?;
+    let (conf, notes) = importer.convert(&file);
+
+    if let Err(err) = pezzo::conf::parse(&conf) {
+        bail!(
+            "Generated configuration is invalid at byte {}: {}",
+            err.location(),
+            err
+        );
+    }
+
+    match output {
+        Some(path) => {
+            std::fs::write(&path, &conf).with_context(|| format!("Cannot write {:?}", path))?
+        }
+        None => std::io::stdout().write_all(conf.as_bytes())?,
+    }
+
+    for note in &notes {
+        eprintln!("pezzo-import-sudoers: {}", note);
+    }
+    if !notes.is_empty() {
+        eprintln!(
+            "pezzo-import-sudoers: {} construct(s) not translated",
+            notes.len()
+        );
+    }
+
+    Ok(notes.is_empty())
+}
+
+fn main() {
+    match _main() {
+        Ok(true) => (),
+        Ok(false) => std::process::exit(1),
+        Err(err) => {
+            eprintln!("{:?}", err);
+            std::process::exit(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::Importer;
+
+    #[test]
+    fn convert() {
+        let mut importer = Importer::default();
+        importer
+            .add(
+                Path::new("sudoers"),
+                r#"
+Defaults env_reset
+Defaults env_keep += "EDITOR LC_*", timestamp_timeout=5
+Defaults lecture
+
+User_Alias ADMINS = alice, bob
+User_Alias MIXED = carol, OPS, %ops
+Cmnd_Alias SERVICES = /usr/bin/systemctl, /usr/bin/journalctl
+Host_Alias WEB = WEB1, web2.example.com
+
+ADMINS WEB = (root) NOPASSWD: SERVICES, /usr/bin/id, \
+    /usr/bin/kill -HUP *
+%wheel ALL = (ALL:ALL) ALL, !/usr/bin/su
+MIXED ALL = (postgres) SETENV: /usr/bin/psql ""
+dave 10.0.0.0/8 = ALL
+erin ALL = NOEXEC: /usr/bin/vi
+"#,
+            )
+            .unwrap();
+        let (conf, notes) = importer.convert(Path::new("sudoers"));
+
+        assert_eq!(
+            conf,
+            r#"# Converted from sudoers by pezzo-import-sudoers, review it before installing.
+
+alias users ADMINS = alice | bob;
+alias commands SERVICES = /usr/bin/systemctl | /usr/bin/journalctl;
+alias hosts WEB = web1 | web2.example.com;
+
+# sudoers:11
+rule {
+    origin = ADMINS;
+    target = root;
+    host = WEB;
+    exe = SERVICES | /usr/bin/id;
+    askpass = false;
+    setenv = { EDITOR, LC_* };
+    timeout = 300;
+}
+
+# sudoers:11
+rule {
+    origin = ADMINS;
+    target = root;
+    host = WEB;
+    exe = /usr/bin/kill;
+    args = [-HUP ...];
+    askpass = false;
+    setenv = { EDITOR, LC_* };
+    timeout = 300;
+}
+
+# sudoers:13
+rule {
+    origin = :wheel;
+    setenv = { EDITOR, LC_* };
+    timeout = 300;
+}
+
+# sudoers:13
+rule {
+    action = deny;
+    origin = :wheel;
+    exe = /usr/bin/su;
+    setenv = { EDITOR, LC_* };
+    timeout = 300;
+}
+
+# sudoers:14
+rule {
+    origin = carol | "OPS" | :ops;
+    target = postgres;
+    exe = /usr/bin/psql;
+    args = [];
+    keepenv = true;
+    timeout = 300;
+}
+"#
+        );
+        assert_eq!(
+            notes,
+            [
+                "sudoers:4: Defaults lecture not supported",
+                "sudoers:14: SETENV converted to keepenv = true, which keeps the whole environment",
+                "sudoers:15: host address \"10.0.0.0/8\" not supported, rule skipped",
+                "sudoers:16: NOEXEC not supported, rule for \"/usr/bin/vi\" skipped",
+            ]
+        );
+        assert!(pezzo::conf::parse(&conf).is_ok());
+    }
+}
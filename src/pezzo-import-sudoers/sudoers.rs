@@ -0,0 +1,602 @@
+//! Parser for the commonly used subset of the sudoers format. Anything it
+//! cannot make sense of is reported as an error for the whole line.
+
+/// Tags that can precede a command, each `NO` tag cancels its counterpart.
+const TAGS: &[&str] = &[
+    "PASSWD",
+    "NOPASSWD",
+    "SETENV",
+    "NOSETENV",
+    "EXEC",
+    "NOEXEC",
+    "LOG_INPUT",
+    "NOLOG_INPUT",
+    "LOG_OUTPUT",
+    "NOLOG_OUTPUT",
+    "MAIL",
+    "NOMAIL",
+    "FOLLOW",
+    "NOFOLLOW",
+    "INTERCEPT",
+    "NOINTERCEPT",
+];
+
+/// Per command options, `NAME=value`.
+const OPTIONS: &[&str] = &[
+    "CWD",
+    "CHROOT",
+    "ROLE",
+    "TYPE",
+    "TIMEOUT",
+    "NOTBEFORE",
+    "NOTAFTER",
+    "APPARMOR_PROFILE",
+    "PRIVS",
+    "LIMITPRIVS",
+];
+
+const DIGESTS: &[&str] = &["sha224", "sha256", "sha384", "sha512"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AliasKind {
+    User,
+    Runas,
+    Host,
+    Cmnd,
+}
+
+/// An item of a list, possibly negated with `!`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    pub negated: bool,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Runas {
+    pub users: Option<Vec<Member>>,
+    pub groups: Option<Vec<Member>>,
+}
+
+/// A command with the run-as spec, options and tags in effect for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    pub runas: Option<Runas>,
+    pub options: Vec<String>,
+    pub tags: Vec<&'static str>,
+    pub command: Member,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Privilege {
+    pub hosts: Vec<Member>,
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Set,
+    Add,
+    Remove,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Setting {
+    Flag(bool, String),
+    Value(String, Operator, String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entry {
+    Alias {
+        kind: AliasKind,
+        name: String,
+        members: Vec<Member>,
+    },
+    /// `scope` is the raw binding, like `:alice` or `@host`.
+    Defaults {
+        scope: Option<String>,
+        settings: Vec<Setting>,
+    },
+    UserSpec {
+        users: Vec<Member>,
+        privileges: Vec<Privilege>,
+    },
+    Include(String),
+    IncludeDir(String),
+}
+
+/// Joins continued lines and strips comments, the line numbers are the ones
+/// of the first physical line.
+pub fn logical_lines(content: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+
+    for (i, line) in content.lines().enumerate() {
+        let (number, mut text) = current.take().unwrap_or((i + 1, String::new()));
+        match line.strip_suffix('\\') {
+            Some(line) => {
+                text.push_str(line);
+                current = Some((number, text));
+            }
+            None => {
+                text.push_str(line);
+                let text = strip_comment(&text);
+                if !text.trim().is_empty() {
+                    lines.push((number, text.trim().to_string()));
+                }
+            }
+        }
+    }
+    if let Some((number, text)) = current {
+        let text = strip_comment(&text);
+        if !text.trim().is_empty() {
+            lines.push((number, text.trim().to_string()));
+        }
+    }
+
+    lines
+}
+
+/// `#` starts a comment unless it is part of a numeric ID or of an include
+/// directive.
+fn strip_comment(line: &str) -> &str {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with("#include") {
+        return line;
+    }
+
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted && !line[i + 1..].starts_with(|c: char| c.is_ascii_digit()) => {
+                return &line[..i];
+            }
+            _ => (),
+        }
+    }
+    line
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    #[inline]
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    #[inline]
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    #[inline]
+    fn at_end(&self) -> bool {
+        self.rest().is_empty()
+    }
+
+    fn skip_ws(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        self.skip_ws();
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(match self.peek() {
+                Some(got) => format!("expected {:?}, got {:?}", c, got),
+                None => format!("expected {:?}, got end of line", c),
+            })
+        }
+    }
+
+    /// Consumes `keyword` if it is followed by a character that cannot be
+    /// part of a name.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.rest().strip_prefix(keyword) {
+            Some(rest) if !rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_') => {
+                self.pos += keyword.len();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// A name, host or quoted string, backslash escapes are resolved.
+    fn word(&mut self) -> Result<String, String> {
+        let mut word = String::new();
+
+        if self.eat('"') {
+            let mut chars = self.rest().char_indices();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        self.pos += i + 1;
+                        return Ok(word);
+                    }
+                    '\\' => match chars.next() {
+                        Some((_, c)) => word.push(c),
+                        None => break,
+                    },
+                    c => word.push(c),
+                }
+            }
+            return Err("unterminated string".to_string());
+        }
+
+        let mut chars = self.rest().char_indices().peekable();
+        let mut end = self.rest().len();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some((_, c)) => word.push(c),
+                    None => return Err("dangling backslash".to_string()),
+                },
+                c if c.is_whitespace() || ",:=()!\"".contains(c) => {
+                    end = i;
+                    break;
+                }
+                c => word.push(c),
+            }
+        }
+        self.pos += end;
+
+        if word.is_empty() {
+            Err(match self.peek() {
+                Some(got) => format!("expected a name, got {:?}", got),
+                None => "expected a name, got end of line".to_string(),
+            })
+        } else {
+            Ok(word)
+        }
+    }
+
+    fn negations(&mut self) -> bool {
+        let mut negated = false;
+        loop {
+            self.skip_ws();
+            if !self.eat('!') {
+                return negated;
+            }
+            negated = !negated;
+        }
+    }
+
+    fn member_list(&mut self) -> Result<Vec<Member>, String> {
+        let mut members = Vec::new();
+        loop {
+            let negated = self.negations();
+            let name = self.word()?;
+            members.push(Member { negated, name });
+            self.skip_ws();
+            if !self.eat(',') {
+                return Ok(members);
+            }
+        }
+    }
+
+    /// A command with its arguments, up to an unescaped `,` or `:`. Escapes
+    /// are kept, they are resolved when the arguments are split.
+    fn command(&mut self) -> Result<Member, String> {
+        let negated = self.negations();
+
+        let rest = self.rest();
+        let mut end = rest.len();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => _ = chars.next(),
+                ',' | ':' => {
+                    end = i;
+                    break;
+                }
+                _ => (),
+            }
+        }
+        let command = rest[..end].trim_end();
+        if command.is_empty() {
+            return Err("expected a command".to_string());
+        }
+        self.pos += end;
+
+        Ok(Member {
+            negated,
+            name: command.to_string(),
+        })
+    }
+
+    /// The name of the tag or option at the cursor, if followed by `sep`.
+    fn lookahead(&self, names: &[&'static str], sep: char) -> Option<&'static str> {
+        let rest = self.rest();
+        names.iter().copied().find(|name| {
+            rest.strip_prefix(name)
+                .is_some_and(|r| r.trim_start().starts_with(sep))
+        })
+    }
+
+    fn runas(&mut self) -> Result<Runas, String> {
+        let mut runas = Runas::default();
+        self.skip_ws();
+        if self.peek() != Some(':') && self.peek() != Some(')') {
+            runas.users = Some(self.member_list()?);
+        }
+        self.skip_ws();
+        if self.eat(':') {
+            self.skip_ws();
+            if self.peek() != Some(')') {
+                runas.groups = Some(self.member_list()?);
+            }
+        }
+        self.expect(')')?;
+        Ok(runas)
+    }
+
+    fn option(&mut self, name: &str) -> Result<String, String> {
+        self.pos += name.len();
+        self.expect('=')?;
+        self.skip_ws();
+        let value = if self.peek() == Some('"') {
+            self.word()?
+        } else {
+            let rest = self.rest();
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            self.pos += end;
+            rest[..end].to_string()
+        };
+        Ok(format!("{}={}", name, value))
+    }
+
+    fn privilege(&mut self) -> Result<Privilege, String> {
+        let hosts = self.member_list()?;
+        self.expect('=')?;
+
+        let mut commands = Vec::new();
+        let mut runas = None;
+        let mut options = Vec::new();
+        let mut tags: Vec<&'static str> = Vec::new();
+        loop {
+            self.skip_ws();
+            if self.eat('(') {
+                runas = Some(self.runas()?);
+            }
+            loop {
+                self.skip_ws();
+                if let Some(option) = self.lookahead(OPTIONS, '=') {
+                    options.push(self.option(option)?);
+                } else if let Some(tag) = self.lookahead(TAGS, ':') {
+                    self.pos += tag.len();
+                    self.expect(':')?;
+                    let counterpart = match tag.strip_prefix("NO") {
+                        Some(tag) => tag.to_string(),
+                        None => format!("NO{}", tag),
+                    };
+                    tags.retain(|t| *t != counterpart);
+                    if !tags.contains(&tag) {
+                        tags.push(tag);
+                    }
+                } else {
+                    break;
+                }
+            }
+            if let Some(digest) = self.lookahead(DIGESTS, ':') {
+                self.pos += digest.len();
+                self.expect(':')?;
+                self.skip_ws();
+                let hash = self.word()?;
+                options.push(format!("{}:{}", digest, hash));
+            }
+
+            commands.push(Command {
+                runas: runas.clone(),
+                options: options.clone(),
+                tags: tags.clone(),
+                command: self.command()?,
+            });
+
+            self.skip_ws();
+            if !self.eat(',') {
+                return Ok(Privilege { hosts, commands });
+            }
+        }
+    }
+
+    fn user_spec(&mut self) -> Result<Entry, String> {
+        let users = self.member_list()?;
+        let mut privileges = vec![self.privilege()?];
+        loop {
+            self.skip_ws();
+            if self.at_end() {
+                return Ok(Entry::UserSpec { users, privileges });
+            }
+            self.expect(':')?;
+            privileges.push(self.privilege()?);
+        }
+    }
+
+    fn setting(&mut self) -> Result<Setting, String> {
+        let negated = self.negations();
+        let rest = self.rest();
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        if end == 0 {
+            return Err("expected a setting".to_string());
+        }
+        let name = rest[..end].to_string();
+        self.pos += end;
+        self.skip_ws();
+
+        let operator = if self.rest().starts_with("+=") {
+            Operator::Add
+        } else if self.rest().starts_with("-=") {
+            Operator::Remove
+        } else if self.rest().starts_with('=') {
+            Operator::Set
+        } else {
+            return Ok(Setting::Flag(!negated, name));
+        };
+        self.pos += if operator == Operator::Set { 1 } else { 2 };
+        self.skip_ws();
+
+        let value = if self.peek() == Some('"') {
+            self.word()?
+        } else {
+            let rest = self.rest();
+            let end = rest
+                .find(|c: char| c == ',' || c.is_whitespace())
+                .unwrap_or(rest.len());
+            self.pos += end;
+            rest[..end].to_string()
+        };
+        Ok(Setting::Value(name, operator, value))
+    }
+
+    fn defaults(&mut self) -> Result<Entry, String> {
+        let scope = match self.peek() {
+            Some(c @ (':' | '@' | '>' | '!')) => {
+                self.pos += 1;
+                let rest = self.rest();
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                self.pos += end;
+                Some(format!("{}{}", c, &rest[..end]))
+            }
+            _ => None,
+        };
+
+        let mut settings = Vec::new();
+        loop {
+            settings.push(self.setting()?);
+            self.skip_ws();
+            if self.at_end() {
+                return Ok(Entry::Defaults { scope, settings });
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn aliases(&mut self, kind: AliasKind) -> Result<Vec<Entry>, String> {
+        let mut entries = Vec::new();
+        loop {
+            self.skip_ws();
+            let name = self.word()?;
+            if !name.starts_with(|c: char| c.is_ascii_uppercase())
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+            {
+                return Err(format!("invalid alias name {:?}", name));
+            }
+            self.expect('=')?;
+
+            let members = if kind == AliasKind::Cmnd {
+                let mut members = Vec::new();
+                loop {
+                    members.push(self.command()?);
+                    self.skip_ws();
+                    if !self.eat(',') {
+                        break members;
+                    }
+                }
+            } else {
+                self.member_list()?
+            };
+            entries.push(Entry::Alias {
+                kind,
+                name,
+                members,
+            });
+
+            self.skip_ws();
+            if self.at_end() {
+                return Ok(entries);
+            }
+            self.expect(':')?;
+        }
+    }
+}
+
+/// Parses a logical line, alias lines can define more than one alias.
+pub fn parse_line(line: &str) -> Result<Vec<Entry>, String> {
+    let mut p = Parser { s: line, pos: 0 };
+
+    for directive in ["#includedir", "@includedir", "#include", "@include"] {
+        if p.keyword(directive) {
+            p.skip_ws();
+            let path = p.word()?;
+            p.skip_ws();
+            if !p.at_end() {
+                return Err(format!("unexpected {:?} after {}", p.rest(), directive));
+            }
+            return Ok(vec![if directive.ends_with("dir") {
+                Entry::IncludeDir(path)
+            } else {
+                Entry::Include(path)
+            }]);
+        }
+    }
+
+    if p.keyword("Defaults") {
+        return p.defaults().map(|d| vec![d]);
+    }
+
+    for (keyword, kind) in [
+        ("User_Alias", AliasKind::User),
+        ("Runas_Alias", AliasKind::Runas),
+        ("Host_Alias", AliasKind::Host),
+        ("Cmnd_Alias", AliasKind::Cmnd),
+        ("Cmd_Alias", AliasKind::Cmnd),
+    ] {
+        if p.keyword(keyword) {
+            return p.aliases(kind);
+        }
+    }
+
+    p.user_spec().map(|s| vec![s])
+}
+
+/// Splits a command into words, resolving backslash escapes.
+pub fn split_command(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    word.push(c);
+                }
+            }
+            c if c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    words
+}
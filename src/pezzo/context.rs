@@ -2,42 +2,56 @@ use std::{
     cell::UnsafeCell,
     collections::HashMap,
     ffi::{CStr, CString, OsStr, OsString},
+    io,
     os::unix::prelude::{OsStrExt, OsStringExt},
 };
 
 use anyhow::{anyhow, bail, Context, Result};
 use pezzo::{
-    conf::{Env, Origin, Target},
+    conf::{Env, LocalTime, Rule, Settings},
+    policy::{self, Subject, Verdict},
     unix::{Group, IAMContext, ProcessContext, User},
 };
 
+use crate::{error::Error, util::shell_join};
+
+/// What is run for a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    /// The command given.
+    #[default]
+    Command,
+    /// `$SHELL` or the shell of the target user, running the command given
+    /// with `-c` if any.
+    Shell,
+    /// The shell of the target user as a login shell, running the command
+    /// given with `-c` if any.
+    Login,
+    /// The files given, see [`crate::edit::edit`].
+    Edit,
+}
+
 #[derive(Debug, Default)]
 pub struct MatchResult {
-    timeout: Option<u64>,
-    askpass: Option<bool>,
-    keepenv: Option<bool>,
+    settings: Settings,
     setenv: Option<Box<[Env]>>,
+    envfile: Option<CString>,
 }
 
 impl MatchResult {
     #[inline]
-    pub fn timeout(&self) -> Option<u64> {
-        self.timeout
+    pub fn settings(&self) -> &Settings {
+        &self.settings
     }
 
     #[inline]
-    pub fn askpass(&self) -> Option<bool> {
-        self.askpass
-    }
-
-    #[inline]
-    pub fn keepenv(&self) -> Option<bool> {
-        self.keepenv
+    pub fn setenv(&self) -> Option<&[Env]> {
+        self.setenv.as_ref().map(|e| e.as_ref())
     }
 
     #[inline]
-    pub fn setenv(&self) -> Option<&[Env]> {
-        self.setenv.as_ref().map(|e| e.as_ref())
+    pub fn envfile(&self) -> Option<&CStr> {
+        self.envfile.as_deref()
     }
 }
 
@@ -49,91 +63,222 @@ pub struct MatchContext {
     pub(crate) target_user: User,
     pub(crate) target_group: Group,
     pub(crate) target_home: Box<CStr>,
+    pub(crate) target_shell: Box<CStr>,
+    mode: Mode,
+    target_user_known: bool,
+    target_group_known: bool,
     pub(crate) iam: IAMContext,
     pub(crate) proc: ProcessContext,
+    hostname: CString,
+    fqdn: UnsafeCell<Option<Option<CString>>>,
     root_name: UnsafeCell<Option<Box<CStr>>>,
     groups_cache: UnsafeCell<HashMap<Box<CStr>, Vec<Box<CStr>>>>,
 }
 
 impl MatchContext {
+    /// The command is looked up in the `secure_path` of `conf`, never in the
+    /// `PATH` of the invoking user. In the shell modes the command is the
+    /// shell, so rules restricting `exe` apply to it. When editing the
+    /// arguments are the files, canonicalized.
     pub fn new(
         iam: IAMContext,
         proc: ProcessContext,
         user: Option<Box<CStr>>,
         group: Option<Box<CStr>>,
         mut arguments: Vec<OsString>,
+        mode: Mode,
+        conf: &pezzo::conf::Rules,
     ) -> Result<Self> {
-        let mut arg0 = arguments.remove(0).into_vec();
-        if arg0.last().map(|&c| c != 0).unwrap_or(true) {
-            arg0.push(0);
-        }
-        let arg0 = CString::from_vec_with_nul(arg0)?;
-        let command = if let Ok(command) = pezzo::which::which(&arg0) {
-            command
+        // No command is given when only listing privileges.
+        let arg0 = if arguments.is_empty() || mode != Mode::Command {
+            None
         } else {
-            bail!("Command {:?} not found", arg0);
+            let mut arg0 = arguments.remove(0).into_vec();
+            if arg0.last().map(|&c| c != 0).unwrap_or(true) {
+                arg0.push(0);
+            }
+            Some(CString::from_vec_with_nul(arg0)?)
         };
-        let arg0 = OsString::from_vec(arg0.into_bytes());
 
-        let pwd = if let Some(name) = user {
-            iam.pwd_by_name(name.as_ref())
-                .context("Cannot get users informations")?
-                .ok_or_else(|| anyhow!("Invalid user {:?}", name))?
-        } else {
-            iam.default_user()
-                .context("Cannot get groups informations")?
-                .ok_or_else(|| anyhow!("Invalid root user"))?
-        };
+        // The files are resolved as the invoking user, so that the errors
+        // tell nothing about the files they cannot see.
+        if mode == Mode::Edit {
+            let uid = proc.original_user.id();
+            let gid = proc.original_group.id();
+            let mut gids = proc
+                .original_groups
+                .iter()
+                .map(Group::id)
+                .collect::<Vec<_>>();
+            gids.push(gid);
+            iam.set_groups(&gids).context("Cannot set process groups")?;
+            iam.set_effective_identity(uid, gid)
+                .context("Cannot set euid and egid")?;
+            let resolved = arguments
+                .iter()
+                .map(|file| {
+                    crate::edit::resolve(file, uid, &gids)
+                        .map(|path| OsString::from_vec(path.into_bytes()))
+                })
+                .collect::<Result<_>>();
+            iam.escalate_permissions()
+                .context("Cannot set root permissions")?;
+            arguments = resolved?;
+        }
 
-        let (target_user, default_gid, target_home) =
-            (User::new(pwd.uid, pwd.name), pwd.gid, pwd.home);
+        let (target_user, default_gid, target_home, target_shell, target_user_known) = match user {
+            Some(name) => match parse_id(&name) {
+                Some(uid) => match iam
+                    .pwd_by_id(uid)
+                    .context("Cannot get users informations")?
+                {
+                    Some(pwd) => (
+                        User::new(pwd.uid, pwd.name),
+                        pwd.gid,
+                        pwd.home,
+                        pwd.shell,
+                        true,
+                    ),
+                    None => (
+                        User::new(uid, name),
+                        uid,
+                        c"/".into(),
+                        c"/bin/sh".into(),
+                        false,
+                    ),
+                },
+                None => {
+                    let pwd = iam
+                        .pwd_by_name(name.as_ref())
+                        .context("Cannot get users informations")?
+                        .ok_or_else(|| anyhow!("Invalid user {:?}", name))?;
+                    (
+                        User::new(pwd.uid, pwd.name),
+                        pwd.gid,
+                        pwd.home,
+                        pwd.shell,
+                        true,
+                    )
+                }
+            },
+            None => {
+                let pwd = iam
+                    .default_user()
+                    .context("Cannot get groups informations")?
+                    .ok_or_else(|| anyhow!("Invalid root user"))?;
+                (
+                    User::new(pwd.uid, pwd.name),
+                    pwd.gid,
+                    pwd.home,
+                    pwd.shell,
+                    true,
+                )
+            }
+        };
 
-        let target_group = group.map_or_else(
-            || {
-                iam.group_by_id(default_gid)
+        let (target_group, target_group_known) = match group {
+            Some(name) => match parse_id(&name) {
+                Some(gid) => match iam
+                    .group_by_id(gid)
                     .context("Cannot get groups informations")?
-                    .ok_or_else(|| anyhow!("Invalid group {}", default_gid))
+                {
+                    Some(group) => (group, true),
+                    None => (Group::new(gid, name), false),
+                },
+                None => (
+                    iam.group_by_name(name)
+                        .context("Cannot get groups informations")?
+                        .map_err(|name| anyhow!("Invalid group {:?}", name))?,
+                    true,
+                ),
             },
-            |name| {
-                iam.group_by_name(name)
-                    .context("Cannot get groups informations")?
-                    .map_err(|name| anyhow!("Invalid group {:?}", name))
+            None => match iam
+                .group_by_id(default_gid)
+                .context("Cannot get groups informations")?
+            {
+                Some(group) => (group, true),
+                None if !target_user_known => {
+                    (Group::new(default_gid, format_id(default_gid)), false)
+                }
+                None => bail!("Invalid group {}", default_gid),
             },
-        )?;
+        };
 
-        Ok(Self {
-            command,
-            arg0,
+        let mut ctx = Self {
+            command: CString::default(),
+            arg0: OsString::new(),
             arguments,
             target_user,
             target_group,
             target_home,
+            target_shell,
+            mode,
+            target_user_known,
+            target_group_known,
             iam,
             proc,
+            hostname: pezzo::unix::hostname(),
+            fqdn: UnsafeCell::new(None),
             root_name: UnsafeCell::new(None),
             groups_cache: UnsafeCell::new(HashMap::new()),
-        })
-    }
+        };
 
-    fn is_root(&self, name: &CStr) -> Result<bool> {
-        unsafe {
-            if let Some(root_name) = (*self.root_name.get()).as_ref() {
-                return Ok(root_name.as_ref() == name);
+        let arg0 = match mode {
+            Mode::Command | Mode::Edit => arg0,
+            Mode::Shell | Mode::Login => {
+                if !ctx.arguments.is_empty() {
+                    ctx.arguments = vec!["-c".into(), shell_join(&ctx.arguments)];
+                }
+                let shell = match std::env::var_os("SHELL") {
+                    Some(shell) if mode == Mode::Shell && !shell.is_empty() => {
+                        CString::new(shell.into_vec())?
+                    }
+                    _ => ctx.target_shell.to_owned().into(),
+                };
+                Some(shell)
             }
+        };
 
-            let root_name = self
-                .iam
-                .group_name_by_id(0)
-                .context("cannot get groups informations")?
-                .ok_or_else(|| anyhow!("Cannot get root user"))?;
-
-            let res = root_name.as_ref() == name;
-            std::ptr::write(self.root_name.get(), Some(root_name));
-            Ok(res)
+        if let Some(arg0) = arg0 {
+            let settings = policy::settings(&ctx, conf, None);
+            ctx.command = match pezzo::which::which_in(&arg0, settings.secure_path.to_bytes()) {
+                Ok(command) => command,
+                Err(_) => bail!(Error::CommandNotFound(OsString::from_vec(
+                    arg0.into_bytes()
+                ))),
+            };
+            ctx.arg0 = match mode {
+                // A leading `-` in argv[0] makes a login shell.
+                Mode::Login => {
+                    let name = ctx.command.to_bytes();
+                    let name = &name[memchr::memrchr(b'/', name).map_or(0, |pos| pos + 1)..];
+                    OsString::from_vec([b"-", name].concat())
+                }
+                _ => OsString::from_vec(arg0.into_bytes()),
+            };
         }
+
+        Ok(ctx)
+    }
+
+    #[inline]
+    pub fn hostname(&self) -> &CStr {
+        &self.hostname
+    }
+
+    /// Expands a password prompt for this request, see
+    /// [`pezzo::unix::pam::expand_prompt`].
+    pub fn expand_prompt(&self, template: &CStr) -> Vec<u8> {
+        pezzo::unix::pam::expand_prompt(template.to_bytes(), |c| match c {
+            b'u' | b'p' => Some(self.proc.original_user.name().to_bytes()),
+            b'U' => Some(self.target_user.name().to_bytes()),
+            b'h' => Some(self.hostname.to_bytes()),
+            b'H' => Some(self.fqdn().unwrap_or(&self.hostname).to_bytes()),
+            _ => None,
+        })
     }
 
-    fn get_groups(&self, name: &CStr) -> Result<&[Box<CStr>]> {
+    fn get_groups(&self, name: &CStr) -> io::Result<&[Box<CStr>]> {
         unsafe {
             {
                 let cache = &mut *self.groups_cache.get();
@@ -152,95 +297,177 @@ impl MatchContext {
         }
     }
 
+    fn command_matches(&self, rule: &Rule) -> bool {
+        if !policy::edit_matches(self, rule) {
+            return false;
+        }
+
+        if let Some(ref exe) = rule.exe {
+            if !exe.is_match(OsStr::from_bytes(self.command.to_bytes())) {
+                return false;
+            }
+        }
+
+        if let Some(ref args) = rule.args {
+            if !args.iter().any(|a| a.is_match(&self.arguments)) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Rules granted to or restricting the invoking user on this host,
+    /// regardless of target, command and time constraints.
+    pub fn applicable<'a>(
+        &'a self,
+        conf: &'a pezzo::conf::Rules,
+    ) -> impl Iterator<Item = (usize, &'a Rule)> + 'a {
+        conf.rules().iter().enumerate().filter(|(_, rule)| {
+            policy::origin_matches(self, rule)
+                && rule
+                    .host
+                    .as_ref()
+                    .is_none_or(|h| policy::host_matches(self, h))
+        })
+    }
+
+    /// Like [`MatchContext::applicable`], but only the rules matching the
+    /// command that are active at `now`.
+    pub fn applicable_to_command<'a>(
+        &'a self,
+        conf: &'a pezzo::conf::Rules,
+        now: &'a LocalTime,
+    ) -> impl Iterator<Item = (usize, &'a Rule)> + 'a {
+        self.applicable(conf)
+            .filter(|(_, rule)| rule.is_active(now) && self.command_matches(rule))
+    }
+
     pub fn matches(&self, conf: &pezzo::conf::Rules) -> Result<Option<MatchResult>> {
-        let mut last = None;
+        let rules = conf.rules();
+        let now = policy::local_time(conf.timezone()).context("Cannot get local time")?;
 
-        for rule in conf.rules().iter() {
-            {
-                let groups_match = rule.origin.iter().any(|x| match x {
-                    Origin::User(users) => users
-                        .iter()
-                        .any(|u| self.proc.original_user.name() == u.as_c_str()),
-                    Origin::Group(groups) => groups.iter().any(|g| {
-                        let g = g.as_c_str();
-                        self.proc.original_group.name() == g
-                            || self.proc.original_groups.iter().any(|og| og.name() == g)
-                    }),
-                });
-
-                if !groups_match {
-                    continue;
-                }
+        match policy::verdict(self, rules, &now).context("Cannot get users informations")? {
+            Some(Verdict::Permit(i)) => {
+                let rule = &rules[i];
+                Ok(Some(MatchResult {
+                    settings: policy::settings(self, conf, Some(rule)),
+                    setenv: rule.setenv.clone(),
+                    envfile: rule.envfile.clone(),
+                }))
             }
+            Some(Verdict::Deny(i)) if self.mode == Mode::Edit => bail!(Error::Denied(format!(
+                "{:?} is not allowed to edit {:?} as {:?} (denied by rule #{})",
+                self.proc.original_user.name(),
+                self.arguments,
+                self.target_user.name(),
+                i + 1
+            ))),
+            Some(Verdict::Deny(i)) => bail!(Error::Denied(format!(
+                "{:?} is not allowed to run {:?} as {:?} (denied by rule #{})",
+                self.proc.original_user.name(),
+                self.command,
+                self.target_user.name(),
+                i + 1
+            ))),
+            None => Ok(None),
+        }
+    }
+}
 
-            {
-                let target_match = if let Some(ref target) = rule.target {
-                    'target: {
-                        for x in target {
-                            let m = match x {
-                                Target::User(users) => 'users: {
-                                    for u in users {
-                                        if self.is_root(u.as_c_str())?
-                                            || (self.target_user.name() == u.as_c_str()
-                                                && self.get_groups(u.as_c_str())?.iter().any(
-                                                    |group| {
-                                                        group.as_ref() == self.target_group.name()
-                                                    },
-                                                ))
-                                        {
-                                            break 'users true;
-                                        }
-                                    }
-                                    false
-                                }
-                                Target::UserGroup(users, groups) => 'ug_matches: {
-                                    let users_matches = 'users: {
-                                        for u in users {
-                                            if self.is_root(u.as_c_str())? {
-                                                break 'ug_matches true;
-                                            } else if self.target_user.name() == u.as_c_str() {
-                                                break 'users true;
-                                            }
-                                        }
-                                        false
-                                    };
-                                    if !users_matches {
-                                        break 'ug_matches false;
-                                    }
-
-                                    groups
-                                        .iter()
-                                        .any(|u| self.target_group.name() == u.as_c_str())
-                                }
-                            };
-
-                            if m {
-                                break 'target true;
-                            }
-                        }
-                        false
-                    }
-                } else {
-                    true
-                };
+impl Subject for MatchContext {
+    #[inline]
+    fn user(&self) -> &User {
+        &self.proc.original_user
+    }
 
-                if !target_match {
-                    continue;
-                }
+    #[inline]
+    fn group(&self) -> &Group {
+        &self.proc.original_group
+    }
+
+    #[inline]
+    fn groups(&self) -> &[Group] {
+        &self.proc.original_groups
+    }
+
+    #[inline]
+    fn target_user(&self) -> &User {
+        &self.target_user
+    }
+
+    #[inline]
+    fn target_group(&self) -> &Group {
+        &self.target_group
+    }
+
+    #[inline]
+    fn target_user_known(&self) -> bool {
+        self.target_user_known
+    }
+
+    #[inline]
+    fn target_group_known(&self) -> bool {
+        self.target_group_known
+    }
+
+    fn root_name(&self) -> io::Result<&CStr> {
+        unsafe {
+            let root_name = &mut *self.root_name.get();
+            if root_name.is_none() {
+                *root_name = Some(self.iam.user_name_by_id(0)?.ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "cannot get root user")
+                })?);
             }
+            Ok(root_name.as_deref().unwrap_unchecked())
+        }
+    }
 
-            if rule.exe.as_ref().map_or(true, |exe| {
-                exe.is_match(OsStr::from_bytes(self.command.to_bytes()))
-            }) {
-                last = Some(MatchResult {
-                    timeout: rule.timeout,
-                    askpass: rule.askpass,
-                    keepenv: rule.keepenv,
-                    setenv: rule.setenv.clone(),
-                });
+    #[inline]
+    fn target_user_groups(&self) -> io::Result<&[Box<CStr>]> {
+        self.get_groups(self.target_user.name())
+    }
+
+    #[inline]
+    fn hostname(&self) -> &CStr {
+        &self.hostname
+    }
+
+    fn fqdn(&self) -> Option<&CStr> {
+        unsafe {
+            let fqdn = &mut *self.fqdn.get();
+            if fqdn.is_none() {
+                *fqdn = Some(pezzo::unix::fqdn(&self.hostname));
             }
+            fqdn.as_ref().unwrap_unchecked().as_deref()
         }
+    }
+
+    #[inline]
+    fn command(&self) -> &CStr {
+        &self.command
+    }
+
+    #[inline]
+    fn arguments(&self) -> &[OsString] {
+        &self.arguments
+    }
 
-        Ok(last)
+    #[inline]
+    fn edited(&self) -> Option<&[OsString]> {
+        (self.mode == Mode::Edit).then_some(self.arguments.as_slice())
     }
 }
+
+/// Parse the `#ID` form of user and group names.
+pub fn parse_id(name: &CStr) -> Option<u32> {
+    let id = name.to_bytes().strip_prefix(b"#")?;
+    if id.is_empty() || !id.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    std::str::from_utf8(id).ok()?.parse().ok()
+}
+
+pub fn format_id(id: u32) -> Box<CStr> {
+    unsafe { CString::from_vec_unchecked(format!("#{}", id).into_bytes()) }.into_boxed_c_str()
+}
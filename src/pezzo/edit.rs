@@ -0,0 +1,355 @@
+use std::{
+    ffi::{CStr, CString, OsStr, OsString},
+    fs::Metadata,
+    io::{Read, Write},
+    os::{
+        fd::AsFd,
+        unix::{
+            fs::{MetadataExt, PermissionsExt},
+            prelude::OsStrExt,
+            process::CommandExt,
+        },
+    },
+    path::Path,
+    process::Command,
+};
+
+use anyhow::{bail, Context, Result};
+use pezzo::{
+    io::OpenOptions,
+    unix::{self, Group},
+    util,
+};
+
+/// Directory of the copies the invoking user edits.
+const TEMP_DIR: &[u8] = b"/var/tmp/";
+
+/// Mode of the files created by `pezzo -e`.
+const DEFAULT_MODE: u32 = 0o644;
+
+#[inline]
+fn as_path(path: &CStr) -> &Path {
+    Path::new(OsStr::from_bytes(path.to_bytes()))
+}
+
+#[inline]
+fn file_name(path: &CStr) -> &[u8] {
+    let path = path.to_bytes();
+    &path[memchr::memrchr(b'/', path).map_or(0, |pos| pos + 1)..]
+}
+
+/// Whether the user with `uid` and `gids` can write to the file of `md`.
+fn is_writable(md: &Metadata, uid: u32, gids: &[u32]) -> bool {
+    if md.uid() == uid {
+        md.mode() & 0o200 != 0
+    } else if gids.contains(&md.gid()) {
+        md.mode() & 0o020 != 0
+    } else {
+        md.mode() & 0o002 != 0
+    }
+}
+
+/// The canonical path of `file`, which needs not exist. It cannot be a
+/// symbolic link and, unless `uid` is root, no directory above it can be
+/// writable by the user with `uid` and `gids`: they could replace the file
+/// while it is being edited.
+///
+/// Meant to be called with the effective identity of that user, so that
+/// nothing is learned about the files it cannot see.
+pub fn resolve(file: &OsStr, uid: u32, gids: &[u32]) -> Result<CString> {
+    let bytes = file.as_bytes();
+    let (dir, name) = match memchr::memrchr(b'/', bytes) {
+        Some(0) => (&b"/"[..], &bytes[1..]),
+        Some(pos) => (&bytes[..pos], &bytes[pos + 1..]),
+        None => (&b"."[..], bytes),
+    };
+    if name.is_empty() || name == b"." || name == b".." {
+        bail!("Invalid file {:?}", file);
+    }
+
+    let dir = CString::new(dir).with_context(|| format!("Invalid file {:?}", file))?;
+    let mut path = pezzo::io::canonicalize(&dir)
+        .with_context(|| format!("Cannot resolve {:?}", dir))?
+        .into_bytes();
+    if path.last() != Some(&b'/') {
+        path.push(b'/');
+    }
+    path.extend_from_slice(name);
+    let path = CString::new(path).with_context(|| format!("Invalid file {:?}", file))?;
+
+    match std::fs::symlink_metadata(as_path(&path)) {
+        Ok(md) if md.file_type().is_symlink() => bail!("{:?} is a symbolic link", file),
+        Ok(md) if !md.is_file() => bail!("{:?} is not a regular file", file),
+        Ok(_) => (),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+        Err(err) => return Err(err).with_context(|| format!("Cannot stat {:?}", file)),
+    }
+
+    if uid != 0 {
+        for dir in as_path(&path).ancestors().skip(1) {
+            let md = std::fs::metadata(dir).with_context(|| format!("Cannot stat {:?}", dir))?;
+            if is_writable(&md, uid, gids) {
+                bail!("Cannot edit {:?}: {:?} is writable by the user", file, dir);
+            }
+        }
+    }
+
+    Ok(path)
+}
+
+/// A file removed on drop, unless kept.
+struct TempFile(Option<CString>);
+
+impl TempFile {
+    #[inline]
+    fn path(&self) -> &CStr {
+        self.0.as_deref().unwrap_or_default()
+    }
+
+    #[inline]
+    fn keep(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.0 {
+            _ = pezzo::io::remove_file(path);
+        }
+    }
+}
+
+/// Opens a new file, failing if `path` exists, even as a symbolic link.
+fn create_new(path: &CStr, mode: u16) -> Result<(TempFile, std::fs::File)> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .nofollow(true)
+        .mode(mode)
+        .open_cstr(path)
+        .with_context(|| format!("Cannot create {:?}", path))?;
+    let tmp = TempFile(Some(path.to_owned()));
+    Ok((tmp, to_std(file, path)?))
+}
+
+/// Reads `path`, failing if it is a symbolic link.
+fn read(path: &CStr) -> Result<Option<(Vec<u8>, Metadata)>> {
+    let file = match OpenOptions::new().read(true).nofollow(true).open_cstr(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("Cannot open {:?}", path)),
+    };
+    let mut file = to_std(file, path)?;
+    let md = file
+        .metadata()
+        .with_context(|| format!("Cannot stat {:?}", path))?;
+    if !md.is_file() {
+        bail!("{:?} is not a regular file", path);
+    }
+    let mut content = Vec::with_capacity(md.len() as usize);
+    file.read_to_end(&mut content)
+        .with_context(|| format!("Cannot read {:?}", path))?;
+    Ok(Some((content, md)))
+}
+
+#[inline]
+fn to_std(file: pezzo::io::File, path: &CStr) -> Result<std::fs::File> {
+    Ok(file
+        .as_fd()
+        .try_clone_to_owned()
+        .with_context(|| format!("Cannot open {:?}", path))?
+        .into())
+}
+
+/// Replaces `path` with `content` atomically, keeping the owner and the mode
+/// of `original`.
+fn install(path: &CStr, content: &[u8], original: Option<&Metadata>) -> Result<()> {
+    let bytes = path.to_bytes();
+    let name = file_name(path);
+    let mut tmp = bytes[..bytes.len() - name.len()].to_vec();
+    tmp.push(b'.');
+    tmp.extend_from_slice(name);
+    tmp.extend_from_slice(format!(".pezzo-{}", std::process::id()).as_bytes());
+    let tmp = CString::new(tmp)?;
+
+    let (tmp, mut file) = create_new(&tmp, 0o600)?;
+    file.write_all(content)
+        .with_context(|| format!("Cannot write {:?}", tmp.path()))?;
+    let mode = match original {
+        Some(md) => {
+            let new = file
+                .metadata()
+                .with_context(|| format!("Cannot stat {:?}", tmp.path()))?;
+            if (new.uid(), new.gid()) != (md.uid(), md.gid()) {
+                std::os::unix::fs::fchown(&file, Some(md.uid()), Some(md.gid()))
+                    .with_context(|| format!("Cannot change owner of {:?}", tmp.path()))?;
+            }
+            md.mode() & 0o7777
+        }
+        None => DEFAULT_MODE,
+    };
+    file.set_permissions(std::fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Cannot change mode of {:?}", tmp.path()))?;
+    file.sync_all()
+        .with_context(|| format!("Cannot sync {:?}", tmp.path()))?;
+
+    std::fs::rename(as_path(tmp.path()), as_path(path))
+        .with_context(|| format!("Cannot replace {:?}", path))?;
+    tmp.keep();
+    Ok(())
+}
+
+/// Who files are accessed as.
+struct Identity {
+    uid: u32,
+    gid: u32,
+    groups: Vec<u32>,
+}
+
+/// Runs `f` with the effective identity of `id`, then goes back to root.
+fn as_identity<T, F>(ctx: &unix::Context, id: &Identity, f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+{
+    ctx.set_groups(&id.groups)
+        .context("Cannot set process groups")?;
+    ctx.set_effective_identity(id.uid, id.gid)
+        .context("Cannot set euid and egid")?;
+    let res = f();
+    ctx.escalate_permissions()
+        .context("Cannot set root permissions")?;
+    res
+}
+
+/// Edits `files`, canonical paths from [`resolve`]. Each one is read as the
+/// target user and copied to a file of the invoking user, `$VISUAL` or
+/// `$EDITOR` runs on the copies as the invoking user without any privilege
+/// and the changed copies replace the files as the target user.
+///
+/// Root permissions are expected.
+pub fn edit(ctx: &unix::Context, files: &[OsString]) -> Result<()> {
+    let user = Identity {
+        uid: ctx.original_user().id(),
+        gid: ctx.original_group().id(),
+        groups: ctx.original_groups().iter().map(Group::id).collect(),
+    };
+    let target = {
+        let gid = ctx.target_group().id();
+        let mut groups = ctx
+            .get_group_ids(ctx.target_user().name())
+            .context("Cannot get user groups")?;
+        if let Err(pos) = groups.binary_search(&gid) {
+            groups.insert(pos, gid);
+        }
+        Identity {
+            uid: ctx.target_user().id(),
+            gid,
+            groups,
+        }
+    };
+
+    let mut copies = Vec::with_capacity(files.len());
+    for (i, file) in files.iter().enumerate() {
+        let path = CString::new(file.as_bytes())?;
+        let original = as_identity(ctx, &target, || read(&path))?;
+
+        let mut name = TEMP_DIR.to_vec();
+        name.extend_from_slice(format!("pezzo-{}-{}-", std::process::id(), i).as_bytes());
+        name.extend_from_slice(file_name(&path));
+        let name = CString::new(name)?;
+        let copy = as_identity(ctx, &user, || {
+            let (copy, mut file) = create_new(&name, 0o600)?;
+            if let Some((ref content, _)) = original {
+                file.write_all(content)
+                    .with_context(|| format!("Cannot write {:?}", copy.path()))?;
+            }
+            Ok(copy)
+        })?;
+
+        copies.push((path, original, copy));
+    }
+
+    let editor = util::editor();
+    let mut cmd = Command::new(&editor[0]);
+    cmd.args(&editor[1..])
+        .args(copies.iter().map(|(_, _, c)| as_path(c.path())));
+    // std drops the supplementary groups when it sets the uid itself, so the
+    // whole identity is set here.
+    let (uid, gid, groups) = (user.uid, user.gid, user.groups.clone());
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::setgroups(groups.len() as _, groups.as_ptr()) == -1
+                || libc::setgid(gid) == -1
+                || libc::setuid(uid) == -1
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let status = cmd
+        .status()
+        .with_context(|| format!("Cannot run editor {:?}", editor[0]))?;
+    if !status.success() {
+        bail!("Editor {:?} exited with {}", editor[0], status);
+    }
+
+    for (path, original, copy) in &copies {
+        let content = as_identity(ctx, &user, || match read(copy.path())? {
+            Some((content, _)) => Ok(content),
+            None => bail!("{:?} has been removed", copy.path()),
+        })?;
+
+        let unchanged = match original {
+            Some((original, _)) => content == *original,
+            None => content.is_empty(),
+        };
+        if unchanged {
+            eprintln!("pezzo: {} unchanged", path.to_string_lossy());
+            continue;
+        }
+
+        as_identity(ctx, &target, || {
+            install(path, &content, original.as_ref().map(|(_, md)| md))
+        })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::ffi::OsStringExt;
+
+    use super::*;
+
+    #[test]
+    fn resolve() {
+        let dir = std::env::temp_dir().join(format!("pezzo-edit-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub/file"), "x").unwrap();
+        std::os::unix::fs::symlink(dir.join("sub/file"), dir.join("link")).unwrap();
+        let canonical = std::fs::canonicalize(&dir).unwrap();
+        let resolve = |file: &str, uid| {
+            super::resolve(dir.join(file).as_os_str(), uid, &[])
+                .map(|path| OsString::from_vec(path.into_bytes()))
+        };
+
+        assert_eq!(
+            resolve("sub/../sub/file", 0).unwrap(),
+            canonical.join("sub/file").into_os_string()
+        );
+        assert_eq!(
+            resolve("sub/new", 0).unwrap(),
+            canonical.join("sub/new").into_os_string()
+        );
+        assert!(resolve("link", 0).is_err());
+        assert!(resolve("sub", 0).is_err());
+        assert!(resolve("missing/file", 0).is_err());
+        // The temporary directory is writable by everyone.
+        assert!(resolve("sub/file", 1000).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+use std::{ffi::OsString, fmt};
+
+/// Exit codes of [`Error`], for `--help`.
+pub const EXIT_STATUS: &str = "\
+Exit status:
+  1    internal error: bad configuration, system failure
+  2    invalid arguments
+  3    denied by the policy
+  4    authentication failed
+  5    authentication required, with -n or without a terminal
+  6    timed out reading the password
+  127  command not found
+
+Otherwise pezzo exits with the status of the command.";
+
+/// Why pezzo did not run the command. Each kind has its own exit code, see
+/// [`EXIT_STATUS`], so that scripts can tell them apart.
+///
+/// Code that returns [`anyhow::Result`] raises them with `bail!` and
+/// [`Error::from`] gets them back, through any context added on the way.
+#[derive(Debug)]
+pub enum Error {
+    /// The policy does not allow the request.
+    Denied(String),
+    /// The password was wrong every time.
+    AuthFailed(usize),
+    /// A password is needed and cannot be asked.
+    AuthRequired(&'static str),
+    /// No password was given within `prompt_timeout`.
+    TimedOut,
+    /// The command is not in `secure_path` or cannot be found.
+    CommandNotFound(OsString),
+    Internal(anyhow::Error),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Internal(_) => 1,
+            Self::Denied(_) => 3,
+            Self::AuthFailed(_) => 4,
+            Self::AuthRequired(_) => 5,
+            Self::TimedOut => 6,
+            Self::CommandNotFound(_) => 127,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Denied(msg) => f.write_str(msg),
+            Self::AuthFailed(n) => write!(f, "{} incorrect password attempts", n),
+            Self::AuthRequired(msg) => f.write_str(msg),
+            Self::TimedOut => f.write_str("Timed out reading password"),
+            Self::CommandNotFound(cmd) => write!(f, "Command {:?} not found", cmd),
+            Self::Internal(err) => write!(f, "{:?}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<anyhow::Error> for Error {
+    #[inline]
+    fn from(err: anyhow::Error) -> Self {
+        err.downcast().unwrap_or_else(Self::Internal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes() {
+        use anyhow::Context;
+
+        let err: anyhow::Result<()> = Err(Error::Denied("no".into()).into());
+        let err = Error::from(err.context("while matching").unwrap_err());
+        assert!(matches!(err, Error::Denied(ref msg) if msg == "no"));
+        assert_eq!(err.exit_code(), 3);
+
+        let err = Error::from(anyhow::anyhow!(Error::CommandNotFound("frob".into())));
+        assert_eq!(err.to_string(), r#"Command "frob" not found"#);
+        assert_eq!(err.exit_code(), 127);
+
+        let err = Error::from(anyhow::anyhow!("Cannot open database"));
+        assert!(matches!(err, Error::Internal(_)));
+        assert_eq!(err.exit_code(), 1);
+
+        let codes = [
+            Error::Denied(String::new()),
+            Error::AuthFailed(3),
+            Error::AuthRequired(""),
+            Error::TimedOut,
+        ]
+        .map(|err| err.exit_code());
+        assert_eq!(codes, [3, 4, 5, 6]);
+    }
+}
@@ -0,0 +1,396 @@
+use std::{
+    ffi::CStr,
+    fmt,
+    io::{self, Write},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use pezzo::{
+    conf::{Action, LocalTime, Rule, Rules, Settings, Target},
+    policy::{self, Verdict},
+    unix::{Group, IAMContext, ProcessContext, User},
+};
+
+use crate::{
+    context::{format_id, parse_id, MatchContext},
+    error::Error,
+};
+
+/// Replaces the invoking user of `proc` with `name`, to list someone else's
+/// privileges.
+pub fn impersonate(
+    iam: &IAMContext,
+    mut proc: ProcessContext,
+    name: &CStr,
+) -> Result<ProcessContext> {
+    let pwd = match parse_id(name) {
+        Some(uid) => iam.pwd_by_id(uid),
+        None => iam.pwd_by_name(name),
+    }
+    .context("Cannot get users informations")?
+    .ok_or_else(|| anyhow!("Invalid user {:?}", name))?;
+
+    proc.original_group = iam
+        .group_by_id(pwd.gid)
+        .context("Cannot get groups informations")?
+        .unwrap_or_else(|| Group::new(pwd.gid, format_id(pwd.gid)));
+    proc.original_groups = iam
+        .get_groups(&pwd.name)
+        .context("Cannot get user groups")?;
+    proc.original_user = User::new(pwd.uid, pwd.name);
+
+    Ok(proc)
+}
+
+struct Targets<'a>(Option<&'a [Target]>);
+
+impl fmt::Display for Targets<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            None => f.write_str("any user"),
+            Some(targets) => {
+                for (i, target) in targets.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(" | ")?;
+                    }
+                    target.fmt(f)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn write_rule<W: Write>(
+    out: &mut W,
+    i: usize,
+    rule: &Rule,
+    settings: &Settings,
+    now: &LocalTime,
+) -> io::Result<()> {
+    let state = if rule.is_expired(now) {
+        ", expired"
+    } else if !rule.is_active(now) {
+        ", not active now"
+    } else {
+        ""
+    };
+    writeln!(out, "Rule #{} ({}{}):", i + 1, rule.action, state)?;
+    writeln!(out, "    target:      {}", Targets(rule.target.as_deref()))?;
+    match (&rule.exe, &rule.edit) {
+        (_, Some(edit)) => writeln!(out, "    edit:        {}", edit)?,
+        (Some(exe), None) => writeln!(out, "    exe:         {}", exe)?,
+        (None, None) => writeln!(out, "    exe:         any command")?,
+    }
+    if let Some(ref args) = rule.args {
+        write!(out, "    args:        ")?;
+        for (i, a) in args.iter().enumerate() {
+            write!(out, "{}{}", if i == 0 { "" } else { " | " }, a)?;
+        }
+        writeln!(out)?;
+    }
+    if let Some(ref from) = rule.valid_from {
+        writeln!(out, "    valid_from:  {}", from)?;
+    }
+    if let Some(ref until) = rule.valid_until {
+        writeln!(out, "    valid_until: {}", until)?;
+    }
+    if let Some(ref hours) = rule.hours {
+        write!(out, "    hours:       ")?;
+        for (i, h) in hours.iter().enumerate() {
+            write!(out, "{}{}", if i == 0 { "" } else { " | " }, h)?;
+        }
+        writeln!(out)?;
+    }
+    if rule.action == Action::Deny {
+        return Ok(());
+    }
+
+    writeln!(out, "    timeout:     {}", settings.timeout)?;
+    writeln!(out, "    askpass:     {}", settings.askpass)?;
+    writeln!(out, "    keepenv:     {}", settings.keepenv)?;
+    writeln!(out, "    use_pty:     {}", settings.use_pty)?;
+    if let Some(ref setenv) = rule.setenv {
+        write!(out, "    setenv:      ")?;
+        for (i, e) in setenv.iter().enumerate() {
+            write!(out, "{}{}", if i == 0 { "" } else { ", " }, e)?;
+        }
+        writeln!(out)?;
+    }
+    if let Some(ref envfile) = rule.envfile {
+        writeln!(out, "    envfile:     {}", envfile.to_string_lossy())?;
+    }
+
+    Ok(())
+}
+
+/// Prints every rule that applies to the user of `ctx` on this host, as
+/// they are at `now`.
+pub fn list_rules<W: Write>(
+    out: &mut W,
+    ctx: &MatchContext,
+    conf: &Rules,
+    now: &LocalTime,
+) -> Result<()> {
+    let mut rules = ctx.applicable(conf).peekable();
+    if rules.peek().is_none() {
+        bail!(Error::Denied(format!(
+            "User {:?} may not run pezzo on {:?}",
+            ctx.proc.original_user.name(),
+            ctx.hostname()
+        )));
+    }
+
+    writeln!(
+        out,
+        "User {} may run the following commands on {}:",
+        ctx.proc.original_user.name().to_string_lossy(),
+        ctx.hostname().to_string_lossy()
+    )?;
+    for (i, rule) in rules {
+        writeln!(out)?;
+        let settings = policy::settings(ctx, conf, Some(rule));
+        write_rule(out, i, rule, &settings, now)?;
+    }
+
+    Ok(())
+}
+
+/// Tells whether the command of `ctx` would be allowed at `now` and as
+/// whom: lists the rules matching it, then decides as pezzo would for the
+/// target user and group of `ctx`.
+pub fn check_command<W: Write>(
+    out: &mut W,
+    ctx: &MatchContext,
+    conf: &Rules,
+    now: &LocalTime,
+) -> Result<bool> {
+    write!(out, "{}", ctx.command.to_string_lossy())?;
+    for arg in &ctx.arguments {
+        write!(out, " {}", arg.to_string_lossy())?;
+    }
+    writeln!(out)?;
+
+    for (i, rule) in ctx.applicable_to_command(conf, now) {
+        let action = match rule.action {
+            Action::Permit => "permitted",
+            Action::Deny => "denied",
+        };
+        writeln!(
+            out,
+            "    {} as {} by rule #{}",
+            action,
+            Targets(rule.target.as_deref()),
+            i + 1
+        )?;
+    }
+
+    let target = format!(
+        "{}:{}",
+        ctx.target_user.name().to_string_lossy(),
+        ctx.target_group.name().to_string_lossy()
+    );
+    match policy::verdict(ctx, conf.rules(), now).context("Cannot get users informations")? {
+        Some(Verdict::Permit(i)) => {
+            writeln!(out, "    allowed as {} by rule #{}", target, i + 1)?;
+            Ok(true)
+        }
+        Some(Verdict::Deny(i)) => {
+            writeln!(
+                out,
+                "    not allowed as {}, denied by rule #{}",
+                target,
+                i + 1
+            )?;
+            Ok(false)
+        }
+        None => {
+            writeln!(out, "    not allowed as {}", target)?;
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use pezzo::conf::{DateTime, Statement};
+
+    use super::*;
+    use crate::context::Mode;
+
+    fn conf(conf: &str) -> Rules {
+        Rules::from(
+            pezzo::conf::parse(conf)
+                .unwrap()
+                .into_iter()
+                .filter_map(|s| match s {
+                    Statement::Rule(rule) => Some(*rule),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn now() -> LocalTime {
+        let datetime = DateTime {
+            year: 2024,
+            month: 1,
+            day: 1,
+            minutes: 12 * 60,
+        };
+        LocalTime {
+            datetime,
+            weekday: datetime.weekday(),
+        }
+    }
+
+    /// Alice, in the `wheel` group, asking to run `args` as `user` and
+    /// `group`. Nobody has the IDs used, so that the database of the host
+    /// does not matter.
+    fn context(
+        conf: &Rules,
+        user: Option<&CStr>,
+        group: Option<&CStr>,
+        args: &[&str],
+    ) -> MatchContext {
+        let proc = ProcessContext {
+            exe: PathBuf::new(),
+            pid: 1,
+            original_user: User::new(7001, c"alice".into()),
+            original_group: Group::new(7001, c"alice".into()),
+            original_groups: vec![Group::new(7002, c"wheel".into())],
+            sid: 1,
+            tty: None,
+        };
+        MatchContext::new(
+            IAMContext,
+            proc,
+            user.map(Into::into),
+            group.map(Into::into),
+            args.iter().map(Into::into).collect(),
+            Mode::Command,
+            conf,
+        )
+        .unwrap()
+    }
+
+    fn check(conf: &Rules, user: Option<&CStr>, group: Option<&CStr>) -> (bool, String) {
+        let ctx = context(conf, user, group, &["sh", "-c", "id"]);
+        let mut out = Vec::new();
+        let allowed = check_command(&mut out, &ctx, conf, &now()).unwrap();
+        (allowed, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn list() {
+        let conf = conf(
+            "rule { origin = :wheel; target = #7010; exe = /bin/*; valid_until = 2023-12-31; }
+            rule { origin = bob; }
+            rule { action = deny; origin = alice; exe = /bin/sh; }
+            rule { origin = alice; target = #7010:#7011; edit = /etc/hosts; hours = 08:00-10:00; }",
+        );
+        let ctx = context(&conf, None, None, &[]);
+        let mut out = Vec::new();
+        list_rules(&mut out, &ctx, &conf, &now()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(
+            out.starts_with("User alice may run the following commands on "),
+            "{}",
+            out
+        );
+        assert!(
+            out.contains(
+                "\nRule #1 (permit, expired):\n    target:      #7010\n    exe:         /bin/*\n"
+            ),
+            "{}",
+            out
+        );
+        assert!(!out.contains("Rule #2"), "{}", out);
+        // Deny rules have no settings.
+        assert!(
+            out.contains(
+                "\nRule #3 (deny):\n    target:      any user\n    exe:         /bin/sh\n\nRule #4"
+            ),
+            "{}",
+            out
+        );
+        assert!(out.contains("\nRule #4 (permit, not active now):\n    target:      #7010:#7011\n    edit:        /etc/hosts\n"), "{}", out);
+        assert!(out.contains("    timeout:     "), "{}", out);
+
+        let conf = self::conf("rule { origin = bob; }");
+        let ctx = context(&conf, None, None, &[]);
+        let err = list_rules(&mut Vec::new(), &ctx, &conf, &now()).unwrap_err();
+        assert!(matches!(Error::from(err), Error::Denied(_)));
+    }
+
+    #[test]
+    fn command() {
+        // Exe patterns match the resolved path, which depends on the host.
+        let sh = context(&conf(""), None, None, &["sh"]).command;
+        let sh = sh.to_str().unwrap();
+        let conf = conf(&format!(
+            "rule {{ origin = alice; target = #7010; exe = {sh}; }}
+            rule {{ origin = :wheel; target = #7010:#7011; exe = {sh}; }}
+            rule {{ action = deny; origin = alice; target = #7012; exe = {sh}; }}
+            rule {{ origin = alice; target = #7012; }}
+            rule {{ origin = alice; exe = /nonexistent; }}"
+        ));
+
+        let (allowed, out) = check(&conf, Some(c"#7010"), None);
+        assert!(allowed, "{}", out);
+        assert_eq!(
+            out,
+            format!(
+                "{sh} -c id
+    permitted as #7010 by rule #1
+    permitted as #7010:#7011 by rule #2
+    denied as #7012 by rule #3
+    permitted as #7012 by rule #4
+    allowed as #7010:#7010 by rule #1
+"
+            )
+        );
+
+        let (allowed, out) = check(&conf, Some(c"#7010"), Some(c"#7011"));
+        assert!(allowed, "{}", out);
+        assert!(
+            out.ends_with("    allowed as #7010:#7011 by rule #2\n"),
+            "{}",
+            out
+        );
+
+        let (allowed, out) = check(&conf, Some(c"#7012"), None);
+        assert!(!allowed, "{}", out);
+        assert!(
+            out.ends_with("    not allowed as #7012:#7012, denied by rule #3\n"),
+            "{}",
+            out
+        );
+
+        let (allowed, out) = check(&conf, Some(c"#7013"), None);
+        assert!(!allowed, "{}", out);
+        assert!(out.ends_with("    not allowed as #7013:#7013\n"), "{}", out);
+    }
+
+    #[test]
+    fn impersonate() {
+        let proc = context(&conf("rule { origin = alice; }"), None, None, &[]).proc;
+        assert!(super::impersonate(&IAMContext, proc, c"pezzo-no-such-user").is_err());
+
+        let proc = context(&conf("rule { origin = alice; }"), None, None, &[]).proc;
+        let proc = super::impersonate(&IAMContext, proc, c"#0").unwrap();
+        assert_eq!(proc.original_user.id(), 0);
+        assert_eq!(
+            proc.original_user.name(),
+            IAMContext.pwd_by_id(0).unwrap().unwrap().name.as_ref()
+        );
+        assert_eq!(
+            proc.original_group.id(),
+            IAMContext.pwd_by_id(0).unwrap().unwrap().gid
+        );
+        assert!(proc.original_groups.iter().all(|g| g.id() != 7002));
+    }
+}
@@ -1,42 +1,57 @@
 mod context;
+mod edit;
+mod error;
+mod list;
 mod util;
 
-use context::MatchContext;
+use context::{MatchContext, Mode};
+use error::Error;
 use tty_info::Dev;
 use util::*;
 
 use std::{
-    cell::RefCell,
     ffi::{CStr, OsStr, OsString},
-    io::Write,
+    io::{Read, Write},
     os::unix::{
         prelude::{OsStrExt, OsStringExt},
-        process::CommandExt,
+        process::{CommandExt, ExitStatusExt},
     },
-    rc::Rc,
 };
 
 use anyhow::{bail, Context, Result};
-use clap::Parser;
+use clap::{error::ErrorKind, CommandFactory, Parser, ValueEnum};
 use pezzo::{
-    conf::Env,
+    conf::{PasswordFrom, Settings},
     database::{Database, Entry},
+    policy,
     unix::{
-        tty::{TtyIn, TtyOut},
+        self,
+        pam::{Authenticator, PasswordInput, PezzoConversation},
         IAMContext, ProcessContext,
     },
-    DEFAULT_MAX_RETRIES, DEFAULT_PROMPT_TIMEOUT, DEFAULT_SESSION_TIMEOUT, PEZZO_NAME_CSTR,
+    util::{check_file_permissions, check_metadata_permissions, open_nofollow},
 };
 
 extern crate pezzo;
 
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Format {
+    /// Canonical configuration syntax
+    Conf,
+    /// JSON, for tools
+    Json,
+}
+
 #[derive(Debug, Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, after_help = error::EXIT_STATUS)]
 pub struct Cli {
     #[arg(
         short = 'v',
         long,
-        exclusive(true),
+        conflicts_with_all = [
+            "list", "dump_policy", "format", "askpass", "stdin", "user", "group", "shell",
+            "login", "edit", "command",
+        ],
         help("update user's timestamp without running a command")
     )]
     pub validate: bool,
@@ -49,13 +64,84 @@ pub struct Cli {
     pub remove_timestamp: bool,
     #[arg(short = 'k', long, exclusive(true), help("invalidate timestamp file"))]
     pub reset_timestamp: bool,
+    #[arg(
+        short = 'C',
+        long,
+        exclusive(true),
+        help("check the configuration and report expired or suspicious rules")
+    )]
+    pub check: bool,
+    #[arg(
+        short = 'l',
+        long,
+        help("list the privileges of the user or of USER, or check whether COMMAND is allowed as USER and GROUP")
+    )]
+    pub list: bool,
+    #[arg(
+        long,
+        conflicts_with_all = ["list", "user", "group", "command"],
+        help("print the compiled policy, with includes and aliases expanded")
+    )]
+    pub dump_policy: bool,
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with_all = ["list", "command"],
+        help("format of --dump-policy [default: conf]")
+    )]
+    pub format: Option<Format>,
     #[arg(short = 'B', long, help("ring bell when prompting"))]
     pub bell: bool,
-    #[arg(short, long, value_parser = parse_box_c_str, value_name = "USER", help("run command as specified user name or ID"))]
+    #[arg(
+        short = 'n',
+        long,
+        help("fail instead of asking for the password, if it is needed")
+    )]
+    pub non_interactive: bool,
+    #[arg(
+        short = 'A',
+        long,
+        help("read the password from the program in PEZZO_ASKPASS, run as the invoking user")
+    )]
+    pub askpass: bool,
+    #[arg(
+        short = 'S',
+        long,
+        conflicts_with = "askpass",
+        help("read the password from the standard input")
+    )]
+    pub stdin: bool,
+    #[arg(short, long, value_parser = parse_box_c_str, value_name = "PROMPT", help("use the specified password prompt, with %u %U %h %H %p escapes"))]
+    pub prompt: Option<Box<CStr>>,
+    #[arg(short, long, value_parser = parse_box_c_str, value_name = "USER", help("run command as specified user name or #ID"))]
     pub user: Option<Box<CStr>>,
-    #[arg(short, long, value_parser = parse_box_c_str, value_name = "GROUP", help("run command as the specified group name or ID"))]
+    #[arg(short, long, value_parser = parse_box_c_str, value_name = "GROUP", help("run command as the specified group name or #ID"))]
     pub group: Option<Box<CStr>>,
-    #[arg(trailing_var_arg(true), required(true))]
+    #[arg(
+        short = 's',
+        long,
+        conflicts_with_all = ["list", "dump_policy", "login"],
+        help("run $SHELL or the shell of the target user, with COMMAND if given")
+    )]
+    pub shell: bool,
+    #[arg(
+        short = 'i',
+        long,
+        conflicts_with_all = ["list", "dump_policy"],
+        help("run the shell of the target user as a login shell in its home directory, with COMMAND if given")
+    )]
+    pub login: bool,
+    #[arg(
+        short = 'e',
+        long,
+        conflicts_with_all = ["list", "dump_policy", "shell", "login"],
+        help("edit the files given as COMMAND as the target user, running $VISUAL or $EDITOR as the invoking user")
+    )]
+    pub edit: bool,
+    #[arg(
+        trailing_var_arg(true),
+        required_unless_present_any(["validate", "list", "dump_policy", "shell", "login"])
+    )]
     pub command: Vec<OsString>,
 }
 
@@ -73,9 +159,20 @@ fn _main() -> Result<()> {
         validate,
         remove_timestamp,
         reset_timestamp,
+        check,
+        list,
+        dump_policy,
+        format,
         bell,
+        non_interactive,
+        askpass,
+        stdin,
+        prompt,
         user,
         group,
+        shell,
+        login,
+        edit,
         command: args,
     } = Cli::parse();
 
@@ -90,90 +187,194 @@ fn _main() -> Result<()> {
         iam.escalate_permissions()
             .context("Cannot set root permissions")?;
         let mut db = Database::new(proc.original_user.name()).context("Cannot open database")?;
-        db.retain(|e| e.session_id() != proc.sid && e.tty() != proc.tty.device());
+        let ttyno = proc.tty.as_ref().map(|tty| tty.device());
+        db.retain(|e| e.session_id() != proc.sid && Some(e.tty()) != ttyno);
         db.save().context("Cannot save database")?;
         return Ok(());
     }
 
+    let config_path = unsafe { CStr::from_ptr(pezzo::CONFIG_PATH.as_ptr().cast()) };
+
     if validate {
         iam.escalate_permissions()
             .context("Cannot set root permissions")?;
 
+        let rules = parse_conf_cstr(config_path)?;
+        let ctx = MatchContext::new(iam, proc, None, None, Vec::new(), Mode::Command, &rules)?;
+        let settings = pezzo::policy::settings(&ctx, &rules, None);
+        let prompt = prompt
+            .as_deref()
+            .or(settings.prompt.as_deref())
+            .map(|p| ctx.expand_prompt(p));
+        let mut ctx =
+            unix::Context::new(ctx.iam, ctx.proc, ctx.target_user, ctx.target_group, bell)
+                .context("Cannot instantiate tty")?;
+        ctx.set_prompt_timeout(settings.prompt_timeout);
+        ctx.set_max_retries(settings.max_retries);
+        ctx.set_prompt(prompt);
+
         if is_expired(
-            proc.original_user.name(),
-            proc.sid,
-            proc.tty.device(),
-            DEFAULT_SESSION_TIMEOUT,
+            ctx.original_user().name(),
+            ctx.sid(),
+            ctx.ttyno(),
+            settings.timeout,
         )? {
-            let tty_info = Rc::new(
-                tty_info::TtyInfo::by_device(proc.tty.device())
-                    .context("Cannot get a valid tty")?,
-            );
+            if non_interactive {
+                bail!(Error::AuthRequired("A password is required"));
+            }
+            check_password_input(&ctx, &settings)?;
+            let mut auth = ctx
+                .authenticator()
+                .context("Cannot instantiate PAM authenticator")?;
 
-            let out = Rc::new(RefCell::new(
-                TtyOut::open(tty_info.clone()).context("Cannot get a valid tty")?,
+            autenticate(&mut auth, ctx.max_retries())?;
+        }
+
+        update_db(ctx.original_user().name(), ctx.sid(), ctx.ttyno())?;
+        return Ok(());
+    }
+
+    if check {
+        if proc.original_user.id() != 0 {
+            bail!(Error::Denied(
+                "Only root can check the configuration".into()
             ));
+        }
 
-            let mut auth = pezzo::unix::pam::Authenticator::new(
-                PEZZO_NAME_CSTR,
-                Some(proc.original_user.name()),
-                pezzo::unix::pam::PezzoConversation::from_values(
-                    DEFAULT_PROMPT_TIMEOUT,
-                    Rc::new(RefCell::new(
-                        TtyIn::open(tty_info).context("Cannot get a valid tty")?,
-                    )),
-                    out.clone(),
-                    proc.original_user.name(),
-                    bell,
-                ),
-            )
-            .context("Cannot instantiate PAM authenticator")?;
+        let rules = parse_conf_cstr(config_path)?;
+        let now = pezzo::policy::local_time(rules.timezone()).context("Cannot get local time")?;
+        for (i, rule) in rules.rules().iter().enumerate() {
+            if let Some(until) = rule.valid_until.filter(|_| rule.is_expired(&now)) {
+                eprintln!("warning: rule #{} expired (valid_until = {})", i + 1, until);
+            }
+        }
+        let warnings = pezzo::lint::lint(rules.rules(), |name| {
+            iam.pwd_by_name(name).map(|pwd| pwd.is_some())
+        })
+        .context("Cannot get users informations")?;
+        for warning in warnings {
+            eprintln!("warning: {}", warning);
+        }
+        println!("{}: parsed OK", config_path.to_string_lossy());
+        return Ok(());
+    }
+
+    if dump_policy {
+        if proc.original_user.id() != 0 {
+            bail!(Error::Denied("Only root can dump the policy".into()));
+        }
 
-            autenticate(&mut auth, DEFAULT_MAX_RETRIES, out);
+        let rules = parse_conf_cstr(config_path)?;
+        let mut out = std::io::stdout().lock();
+        match format.unwrap_or(Format::Conf) {
+            Format::Conf => write!(out, "{}", rules),
+            Format::Json => writeln!(out, "{}", rules.json()),
         }
+        .context("Cannot write policy")?;
+        return Ok(());
+    }
 
-        update_db(proc.original_user.name(), proc.sid, proc.tty.device())?;
+    if list {
+        // With a command, -u and -g are the target as when running it.
+        // Without one, -u is whose privileges are listed.
+        let check = !args.is_empty();
+        if group.is_some() && !check {
+            Cli::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "-l with -g needs a COMMAND",
+                )
+                .exit();
+        }
+        let (proc, user) = match user {
+            Some(name) if !check => {
+                if proc.original_user.id() != 0 {
+                    bail!(Error::Denied(
+                        "Only root can list the privileges of other users".into()
+                    ));
+                }
+                (list::impersonate(&iam, proc, &name)?, None)
+            }
+            user => (proc, user),
+        };
+        let rules = parse_conf_cstr(config_path)?;
+        let ctx = MatchContext::new(iam, proc, user, group, args, Mode::Command, &rules)?;
+        let now = policy::local_time(rules.timezone()).context("Cannot get local time")?;
+        let mut out = std::io::stdout().lock();
+
+        if check {
+            if !list::check_command(&mut out, &ctx, &rules, &now)? {
+                bail!(Error::Denied(format!("{:?} is not allowed", ctx.command)));
+            }
+        } else {
+            list::list_rules(&mut out, &ctx, &rules, &now)?;
+        }
         return Ok(());
     }
 
-    let ctx = MatchContext::new(iam, proc, user, group, args)?;
+    let password_input = password_input(stdin, askpass)?;
+    let rules = parse_conf_cstr(config_path)?;
 
-    let rules = parse_conf_cstr(unsafe { CStr::from_ptr(pezzo::CONFIG_PATH.as_ptr().cast()) })?;
+    let mode = if edit {
+        Mode::Edit
+    } else if login {
+        Mode::Login
+    } else if shell {
+        Mode::Shell
+    } else {
+        Mode::Command
+    };
+    let ctx = MatchContext::new(iam, proc, user, group, args, mode, &rules)?;
 
     let match_res = if let Some(res) = ctx.matches(&rules)? {
         res
     } else {
-        bail!("Cannot match any rule");
+        bail!(Error::Denied("Cannot match any rule".into()));
     };
 
     ctx.iam
         .escalate_permissions()
         .context("Cannot set root permissions")?;
 
-    let (ctx, arg0, command, arguments, home) = {
+    let settings = match_res.settings();
+    let prompt = prompt
+        .as_deref()
+        .or(settings.prompt.as_deref())
+        .map(|p| ctx.expand_prompt(p));
+    let (mut ctx, arg0, command, arguments, home, shell) = {
         (
-            pezzo::unix::Context::new(ctx.iam, ctx.proc, ctx.target_user, ctx.target_group, bell)
+            unix::Context::new(ctx.iam, ctx.proc, ctx.target_user, ctx.target_group, bell)
                 .context("Cannot instantiate tty")?,
             ctx.arg0,
             ctx.command,
             ctx.arguments,
             ctx.target_home,
+            ctx.target_shell,
         )
     };
 
-    if match_res.askpass().unwrap_or(true)
+    ctx.set_prompt_timeout(settings.prompt_timeout);
+    ctx.set_max_retries(settings.max_retries);
+    ctx.set_prompt(prompt);
+    ctx.set_password_input(password_input);
+
+    if settings.askpass
         && is_expired(
             ctx.original_user().name(),
             ctx.sid(),
             ctx.ttyno(),
-            match_res.timeout().unwrap_or(DEFAULT_SESSION_TIMEOUT),
+            settings.timeout,
         )?
     {
+        if non_interactive {
+            bail!(Error::AuthRequired("A password is required"));
+        }
+        check_password_input(&ctx, settings)?;
         let mut auth = ctx
             .authenticator()
             .context("Cannot instantiate PAM authenticator")?;
 
-        autenticate(&mut auth, ctx.max_retries(), ctx.tty_out());
+        autenticate(&mut auth, ctx.max_retries())?;
     }
 
     update_db(ctx.original_user().name(), ctx.sid(), ctx.ttyno())?;
@@ -181,6 +382,27 @@ fn _main() -> Result<()> {
     ctx.escalate_permissions()
         .context("Cannot set root permissions")?;
 
+    if mode == Mode::Edit {
+        return edit::edit(&ctx, &arguments);
+    }
+
+    // Read while still root, the target user may not be able to.
+    let envfile = match match_res.envfile() {
+        Some(path) => {
+            let canonical = pezzo::io::canonicalize(path)
+                .with_context(|| format!("Cannot resolve env file {:?}", path))?;
+            let (mut file, md) = open_nofollow(&canonical, false)
+                .with_context(|| format!("Cannot open env file {:?}", path))?;
+            check_metadata_permissions(path, &md)?;
+            let mut buf = Vec::with_capacity(md.len() as usize);
+            file.read_to_end(&mut buf)
+                .with_context(|| format!("Cannot read env file {:?}", path))?;
+            pezzo::env::parse_envfile(&buf)
+                .with_context(|| format!("Invalid env file {:?}", path))?
+        }
+        None => Vec::new(),
+    };
+
     {
         let uid = ctx.target_user().id();
         let gid = ctx.target_group().id();
@@ -208,54 +430,75 @@ fn _main() -> Result<()> {
     proc.arg0(arg0);
     proc.args(arguments);
 
-    fn set_default_path(proc: &mut std::process::Command) -> &mut std::process::Command {
-        proc.env(
-            "PATH",
-            OsStr::from_bytes(b"/usr/local/sbin:/usr/local/bin:/usr/bin".as_slice()),
-        )
-    }
-
-    let keepenv = match_res.keepenv().unwrap_or(false);
-    if !keepenv {
-        proc.env_clear();
-        set_default_path(&mut proc);
-    } else if matches!(std::env::var_os("PATH"), None) {
-        set_default_path(&mut proc);
-    }
-
-    if let Some(envs) = match_res.setenv() {
-        for env in envs {
-            match env {
-                Env::Unset(ref name) if keepenv => {
-                    proc.env_remove(&***name);
-                }
-                Env::Copy(ref name) if !keepenv => {
-                    if let Ok(value) = std::env::var(&***name) {
-                        proc.env(&***name, value);
-                    }
-                }
-                Env::Set(ref name, ref template) => {
-                    let value = template.format();
-                    if !value.is_empty() {
-                        proc.env(&***name, value);
-                    }
-                }
-                _ => (),
-            }
+    let builtins = pezzo::env::Builtins {
+        user: OsStr::from_bytes(ctx.original_user().name().to_bytes()).to_owned(),
+        uid: ctx.original_user().id(),
+        gid: ctx.original_group().id(),
+        target_user: OsStr::from_bytes(ctx.target_user().name().to_bytes()).to_owned(),
+        target_uid: ctx.target_user().id(),
+        target_gid: ctx.target_group().id(),
+        target_home: OsStr::from_bytes(home.to_bytes()).to_owned(),
+        tty: OsStr::from_bytes(ctx.tty_path().unwrap_or_default().to_bytes()).to_owned(),
+        command: cmd.clone(),
+    };
+    // A login shell starts from a clean environment, as after login(1).
+    let settings = if mode == Mode::Login {
+        proc.current_dir(OsStr::from_bytes(home.to_bytes()));
+        &pezzo::conf::Settings {
+            keepenv: false,
+            ..settings.clone()
         }
-    }
-
+    } else {
+        settings
+    };
+    proc.env_clear()
+        .envs(pezzo::env::build(
+            std::env::vars_os(),
+            settings,
+            match_res.setenv().unwrap_or_default(),
+            &builtins,
+        )?)
+        // Over keepenv and setenv, but not over the variables below.
+        .envs(envfile);
+
+    let target_name = OsStr::from_bytes(ctx.target_user().name().to_bytes());
+    let mut mail = OsString::from("/var/mail/");
+    mail.push(target_name);
     proc.env("HOME", OsStr::from_bytes(home.as_ref().to_bytes()))
-        .env("SUDO_COMMAND", cmd)
+        .env("SHELL", OsStr::from_bytes(shell.to_bytes()))
+        .env("USER", target_name)
+        .env("LOGNAME", target_name)
+        .env("MAIL", mail)
+        .env("SUDO_COMMAND", &cmd)
         .env(
             "SUDO_USER",
             OsStr::from_bytes(ctx.original_user().name().to_bytes()),
         )
         .env("SUDO_UID", ctx.original_user().id().to_string())
-        .env("SUDO_GID", ctx.original_group().id().to_string())
-        .exec();
+        .env("SUDO_GID", ctx.original_group().id().to_string());
 
-    Ok(())
+    if settings.use_pty {
+        let (tty_in, tty_out) = (ctx.tty_in(), ctx.tty_out());
+        let status = match unix::pty::run(proc, tty_in.as_deref(), tty_out.as_deref()) {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                bail!(Error::CommandNotFound(cmd))
+            }
+            res => res.with_context(|| format!("Cannot execute {:?}", cmd))?,
+        };
+        drop((tty_in, tty_out, ctx));
+        std::process::exit(
+            status
+                .code()
+                .or_else(|| status.signal().map(|sig| 128 + sig))
+                .unwrap_or(1),
+        );
+    }
+
+    let err = proc.exec();
+    if err.kind() == std::io::ErrorKind::NotFound {
+        bail!(Error::CommandNotFound(cmd));
+    }
+    Err(err).with_context(|| format!("Cannot execute {:?}", cmd))
 }
 
 fn main() {
@@ -263,12 +506,55 @@ fn main() {
     linux_syscalls::init();
 
     if let Err(err) = _main() {
-        eprintln!("{:?}", err);
-        std::process::exit(1);
+        let err = Error::from(err);
+        eprintln!("{}", err);
+        std::process::exit(err.exit_code());
+    }
+}
+
+/// Where `-S` and `-A` ask to read the password from, the terminal by
+/// default.
+fn password_input(stdin: bool, askpass: bool) -> Result<PasswordInput> {
+    if stdin {
+        Ok(PasswordInput::Stdin)
+    } else if askpass {
+        match std::env::var_os("PEZZO_ASKPASS").filter(|p| !p.is_empty()) {
+            Some(program) => Ok(PasswordInput::Askpass(program.into())),
+            None => bail!("No askpass program specified, set PEZZO_ASKPASS"),
+        }
+    } else {
+        Ok(PasswordInput::Tty)
+    }
+}
+
+/// Fails if the policy forbids reading the password where `ctx` would, or
+/// if it would read it from a terminal there is not.
+fn check_password_input(ctx: &unix::Context, settings: &Settings) -> Result<()> {
+    let from = match ctx.password_input() {
+        PasswordInput::Tty => PasswordFrom::Tty,
+        PasswordInput::Stdin => PasswordFrom::Stdin,
+        PasswordInput::Askpass(_) => PasswordFrom::Askpass,
+    };
+    if !settings.allows_password_from(from) {
+        bail!(Error::Denied(format!(
+            "Reading the password from {} is not allowed",
+            from
+        )));
     }
+    if from == PasswordFrom::Tty && ctx.ttyno().is_none() {
+        bail!(Error::AuthRequired(
+            "A terminal is required to read the password, use -S or -A"
+        ));
+    }
+    Ok(())
 }
 
-fn update_db(user_name: &CStr, sid: u32, ttyno: Dev) -> Result<()> {
+/// Timestamps are kept per terminal: without one the password is always
+/// asked and never remembered.
+fn update_db(user_name: &CStr, sid: u32, ttyno: Option<Dev>) -> Result<()> {
+    let Some(ttyno) = ttyno else {
+        return Ok(());
+    };
     let mut db = Database::new(user_name).context("Failed to open database")?;
     db.retain(|e| e.session_id() != sid && e.tty() != ttyno);
     db.push(Entry {
@@ -279,7 +565,10 @@ fn update_db(user_name: &CStr, sid: u32, ttyno: Dev) -> Result<()> {
     db.save().context("Unable to write database")
 }
 
-fn is_expired(user_name: &CStr, sid: u32, ttyno: Dev, timeout: u64) -> Result<bool> {
+fn is_expired(user_name: &CStr, sid: u32, ttyno: Option<Dev>, timeout: u64) -> Result<bool> {
+    let Some(ttyno) = ttyno else {
+        return Ok(true);
+    };
     let db = Database::new(user_name).context("Failed to open database")?;
     if let Some(entry) = db
         .iter()
@@ -293,29 +582,39 @@ fn is_expired(user_name: &CStr, sid: u32, ttyno: Dev, timeout: u64) -> Result<bo
     Ok(true)
 }
 
-fn autenticate(
-    auth: &mut pezzo::unix::pam::Authenticator<pezzo::unix::pam::PezzoConversation>,
-    max_retries: usize,
-    out: Rc<RefCell<TtyOut>>,
-) {
+fn autenticate(auth: &mut Authenticator<PezzoConversation>, max_retries: usize) -> Result<()> {
     for i in 1..=max_retries {
         if matches!(auth.authenticate(), Ok(_)) {
-            return;
+            return Ok(());
         }
 
         if auth.get_conv().is_timedout() {
-            break;
+            bail!(Error::TimedOut);
         }
 
-        {
-            let mut out = out.borrow_mut();
-            if i == max_retries {
-                _ = writeln!(out, "pezzo: {} incorrect password attempts", i);
-            } else {
-                _ = writeln!(out, "Sorry, try again.");
-            }
-            _ = out.flush();
+        if i != max_retries {
+            _ = auth
+                .get_conv_mut()
+                .get_mut()
+                .print_message(b"Sorry, try again.");
         }
     }
-    std::process::exit(1);
+    bail!(Error::AuthFailed(max_retries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_arguments() {
+        let parse = |args: &[&str]| Cli::try_parse_from(["pezzo"].iter().chain(args));
+
+        let cli = parse(&["-nv"]).unwrap();
+        assert!(cli.validate && cli.non_interactive);
+        assert!(parse(&["-v", "-B", "-p", "pw: "]).is_ok());
+        assert!(parse(&["-v", "-u", "bob"]).is_err());
+        assert!(parse(&["-v", "id"]).is_err());
+        assert!(parse(&["-v", "-k"]).is_err());
+    }
 }
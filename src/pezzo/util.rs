@@ -1,9 +1,9 @@
 use std::{
-    ffi::{CStr, CString},
-    path::Path,
+    ffi::{CStr, CString, OsString},
+    os::unix::prelude::{OsStrExt, OsStringExt},
 };
 
-use anyhow::{bail, Context, Result};
+use anyhow::Result;
 
 pub fn parse_box_c_str(input: &str) -> Result<Box<CStr>, &'static str> {
     match memchr::memchr(b'\0', input.as_bytes()) {
@@ -19,146 +19,49 @@ pub fn parse_box_c_str(input: &str) -> Result<Box<CStr>, &'static str> {
     }
 }
 
-#[inline]
-pub fn run_path_with_cstr<T, E, F>(path: &Path, f: F) -> Result<T, E>
-where
-    E: From<std::io::Error>,
-    F: FnOnce(&CStr) -> Result<T, E>,
-{
-    use std::os::unix::prelude::OsStrExt;
-
-    run_with_cstr(path.as_os_str().as_bytes(), f)
-}
-
-#[inline]
-pub fn run_with_cstr<T, E, F>(bytes: &[u8], f: F) -> Result<T, E>
-where
-    E: From<std::io::Error>,
-    F: FnOnce(&CStr) -> Result<T, E>,
-{
-    const MAX_STACK_ALLOCATION: usize = 384;
-
-    if bytes.len() >= MAX_STACK_ALLOCATION {
-        return run_with_cstr_allocating(bytes, f);
-    }
-
-    let mut buf = std::mem::MaybeUninit::<[u8; MAX_STACK_ALLOCATION]>::uninit();
-    let buf_ptr = buf.as_mut_ptr() as *mut u8;
-
-    unsafe {
-        core::ptr::copy_nonoverlapping(bytes.as_ptr(), buf_ptr, bytes.len());
-        buf_ptr.add(bytes.len()).write(0);
-    }
-
-    match CStr::from_bytes_with_nul(unsafe {
-        core::slice::from_raw_parts(buf_ptr, bytes.len() + 1)
-    }) {
-        Ok(s) => f(s),
-        Err(_) => Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "file name contained an unexpected NUL byte",
-        )
-        .into()),
-    }
-}
-
-#[cold]
-#[inline(never)]
-fn run_with_cstr_allocating<T, E, F>(bytes: &[u8], f: F) -> Result<T, E>
-where
-    E: From<std::io::Error>,
-    F: FnOnce(&CStr) -> Result<T, E>,
-{
-    match CString::new(bytes) {
-        Ok(s) => f(&s),
-        Err(_) => Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "file name contained an unexpected NUL byte",
-        )
-        .into()),
-    }
-}
-
-pub fn check_file_permissions<P: AsRef<Path>>(path: P) -> Result<()> {
-    run_path_with_cstr(path.as_ref(), |p| check_file_permissions_cstr(p))
+pub fn parse_conf_cstr<P: AsRef<CStr>>(path: P) -> Result<pezzo::conf::Rules> {
+    pezzo::conf::load(path.as_ref(), pezzo::util::check_metadata_permissions)
 }
 
-#[cfg(not(target_os = "linux"))]
-pub fn check_file_permissions_cstr<P: AsRef<CStr>>(path: P) -> Result<()> {
-    let path = path.as_ref();
-
-    let mut buf = std::mem::MaybeUninit::<libc::stat>::uninit();
-    let md = loop {
-        if unsafe { libc::stat(path.as_ptr().cast(), buf.as_mut_ptr()) == -1 } {
-            let err = std::io::Error::last_os_error();
-            if err.kind() != std::io::ErrorKind::Interrupted {
-                bail!("Cannot stat file {:?}", path);
+/// Joins `args` into the command line of `sh -c`, escaping with a backslash
+/// every ASCII character but letters, digits, `_`, `-` and `$`, as sudo
+/// does.
+pub fn shell_join(args: &[OsString]) -> OsString {
+    let mut res = Vec::new();
+    for (i, arg) in args.iter().enumerate() {
+        if i != 0 {
+            res.push(b' ');
+        }
+        for &c in arg.as_bytes() {
+            if c.is_ascii() && !(c.is_ascii_alphanumeric() || matches!(c, b'_' | b'-' | b'$')) {
+                res.push(b'\\');
             }
-        } else {
-            break unsafe { buf.assume_init() };
+            res.push(c);
         }
-    };
-
-    if md.st_uid != 0 || md.st_mode & 0o022 != 0 {
-        bail!(
-            "Wrong permissions on file {:?}. Your system has been compromised",
-            path
-        );
     }
-
-    Ok(())
+    OsString::from_vec(res)
 }
 
-#[cfg(target_os = "linux")]
-pub fn check_file_permissions_cstr<P: AsRef<CStr>>(path: P) -> Result<()> {
-    let path = path.as_ref();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let md = loop {
-        match linux_stat::stat_cstr(path) {
-            Err(linux_stat::Errno::EINTR) => (),
-            Err(_) => bail!("Cannot stat file {:?}", path),
-            Ok(md) => break md,
-        }
-    };
+    #[test]
+    fn shell_join() {
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
 
-    if md.uid() != 0 || md.mode().as_u16() & 0o022 != 0 {
-        bail!(
-            "Wrong permissions on file {:?}. Your system has been compromised",
-            path
+        assert_eq!(super::shell_join(&[]), OsString::new());
+        assert_eq!(
+            super::shell_join(&args(&["ls", "-la", "/root"])),
+            OsString::from("ls -la \\/root")
+        );
+        assert_eq!(
+            super::shell_join(&args(&["echo", "a b", "$HOME", "x;y'"])),
+            OsString::from("echo a\\ b $HOME x\\;y\\'")
+        );
+        assert_eq!(
+            super::shell_join(&args(&["printf", "\u{e8}"])),
+            OsString::from("printf \u{e8}")
         );
     }
-
-    Ok(())
-}
-
-#[inline(always)]
-fn _parse_conf<F: FnOnce() -> std::io::Result<Vec<u8>>>(f: F) -> Result<pezzo::conf::Rules> {
-    let content = f().context("Cannot read configuration file")?;
-    match pezzo::conf::parse(&content) {
-        Ok(c) => Ok(c),
-        Err(err) => {
-            let buf = &content[..err.location];
-            let mut line = 1;
-            let mut pos = 0;
-            for p in memchr::memchr_iter(b'\n', buf) {
-                line += 1;
-                pos = p;
-            }
-
-            let col = buf.len() - pos;
-            bail!(
-                "{}:{}: expected {}, got {}",
-                line,
-                col + 1,
-                err.expected,
-                content[err.location]
-            );
-        }
-    }
-}
-
-pub fn parse_conf_cstr<P: AsRef<CStr>>(path: P) -> Result<pezzo::conf::Rules> {
-    let path = path.as_ref();
-    check_file_permissions_cstr(path)?;
-    _parse_conf(|| pezzo::util::slurp_cstr(path))
 }
@@ -0,0 +1,308 @@
+use std::{
+    ffi::{CStr, CString, OsStr},
+    fs::Metadata,
+    io::{BufRead, Write},
+    os::unix::{fs::PermissionsExt, prelude::OsStrExt},
+    path::Path,
+    process::Command,
+};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use pezzo::{
+    conf::Syntax,
+    io::{FileExt, OpenOptions},
+};
+
+extern crate pezzo;
+
+/// Mode of newly created configuration files.
+const DEFAULT_MODE: u32 = 0o440;
+
+/// Edits the pezzo configuration safely: the file is locked against
+/// concurrent edits, changed on a copy and installed only if it parses.
+#[derive(Debug, Parser)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    #[arg(short, long, value_parser = parse_c_string, value_name = "FILE", help("configuration file, the installed one by default"))]
+    pub file: Option<CString>,
+}
+
+fn parse_c_string(input: &str) -> Result<CString, &'static str> {
+    CString::new(input).map_err(|_| "Invalid string")
+}
+
+#[inline]
+fn as_path(path: &CStr) -> &Path {
+    Path::new(OsStr::from_bytes(path.to_bytes()))
+}
+
+fn with_suffix(path: &CStr, suffix: &[u8]) -> CString {
+    let mut buf = Vec::with_capacity(path.to_bytes().len() + suffix.len() + 1);
+    buf.extend_from_slice(path.to_bytes());
+    buf.extend_from_slice(suffix);
+    unsafe { CString::from_vec_unchecked(buf) }
+}
+
+/// A root-only copy of the configuration, removed on drop unless installed.
+struct TempFile {
+    path: CString,
+    installed: bool,
+}
+
+impl TempFile {
+    fn create(path: CString, content: &[u8]) -> Result<Self> {
+        // Only whoever holds the lock can get here, anything left is stale.
+        _ = pezzo::io::remove_file(&path);
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open_cstr(&path)
+            .with_context(|| format!("Cannot create temporary file {:?}", path))?;
+        let tmp = Self {
+            path,
+            installed: false,
+        };
+        file.write_all(content)
+            .with_context(|| format!("Cannot write temporary file {:?}", tmp.path))?;
+
+        Ok(tmp)
+    }
+
+    /// Replaces `path` with this file, owned by root and with `mode`.
+    fn install(mut self, path: &CStr, mode: u32) -> Result<()> {
+        let file = std::fs::File::open(as_path(&self.path))
+            .with_context(|| format!("Cannot open temporary file {:?}", self.path))?;
+        std::os::unix::fs::fchown(&file, Some(0), Some(0))
+            .with_context(|| format!("Cannot change owner of {:?}", self.path))?;
+        file.set_permissions(std::fs::Permissions::from_mode(mode))
+            .with_context(|| format!("Cannot change mode of {:?}", self.path))?;
+        file.sync_all()
+            .with_context(|| format!("Cannot sync {:?}", self.path))?;
+
+        std::fs::rename(as_path(&self.path), as_path(path))
+            .with_context(|| format!("Cannot install {:?}", path))?;
+        self.installed = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.installed {
+            _ = pezzo::io::remove_file(&self.path);
+        }
+    }
+}
+
+fn edit(tmp: &TempFile) -> Result<()> {
+    let editor = pezzo::util::editor();
+    let status = Command::new(&editor[0])
+        .args(&editor[1..])
+        .arg(OsStr::from_bytes(tmp.path.to_bytes()))
+        .status()
+        .with_context(|| format!("Cannot run editor {:?}", editor[0]))?;
+    if !status.success() {
+        bail!("Editor {:?} exited with {}", editor[0], status);
+    }
+    Ok(())
+}
+
+/// Parses the edited copy of `path` the way pezzo would parse `path`, with
+/// `check_permissions` on every file loaded.
+fn validate<F>(tmp: &TempFile, path: &CStr, check_permissions: F) -> Result<()>
+where
+    F: FnMut(&CStr, &Metadata) -> Result<()>,
+{
+    let syntax = Syntax::from_path(path.to_bytes());
+    pezzo::conf::load_as(&tmp.path, syntax, check_permissions)?;
+    Ok(())
+}
+
+/// Asks whether to edit again, `false` means giving up the changes.
+fn ask_edit_again() -> Result<bool> {
+    let stdin = std::io::stdin();
+    let mut line = String::new();
+    loop {
+        print!("What now? (e)dit again, e(x)it without saving: ");
+        std::io::stdout().flush()?;
+
+        line.clear();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            println!();
+            return Ok(false);
+        }
+        match line.trim() {
+            "e" | "E" => return Ok(true),
+            "x" | "X" => return Ok(false),
+            _ => (),
+        }
+    }
+}
+
+fn _main() -> Result<()> {
+    let Cli { file } = Cli::parse();
+
+    if unsafe { libc::getuid() } != 0 {
+        bail!("pezzoedit must be run as root");
+    }
+
+    let path = match file {
+        Some(ref file) => file.as_c_str(),
+        None => unsafe { CStr::from_ptr(pezzo::CONFIG_PATH.as_ptr().cast()) },
+    };
+
+    let lock_path = with_suffix(path, b".lock");
+    let mut lock = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .mode(0o600)
+        .open_cstr(&lock_path)
+        .with_context(|| format!("Cannot open lock file {:?}", lock_path))?;
+    if !lock
+        .try_lock_exclusive()
+        .with_context(|| format!("Cannot lock {:?}", lock_path))?
+    {
+        bail!("{} is busy, try again later", path.to_string_lossy());
+    }
+
+    let (original, mode) = match std::fs::metadata(as_path(path)) {
+        Ok(md) => (
+            pezzo::util::slurp_cstr(path)
+                .with_context(|| format!("Cannot read configuration file {:?}", path))?,
+            md.permissions().mode() & 0o755,
+        ),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => (Vec::new(), DEFAULT_MODE),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Cannot stat configuration file {:?}", path))
+        }
+    };
+
+    let tmp = TempFile::create(with_suffix(path, b".tmp"), &original)?;
+    loop {
+        edit(&tmp)?;
+
+        match validate(&tmp, path, pezzo::util::check_metadata_permissions) {
+            Ok(_) => break,
+            Err(err) => {
+                eprintln!("pezzoedit: {:#}", err);
+                if !ask_edit_again()? {
+                    println!("pezzoedit: {} not changed", path.to_string_lossy());
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    let content = pezzo::util::slurp_cstr(&tmp.path)
+        .with_context(|| format!("Cannot read temporary file {:?}", tmp.path))?;
+    if content == original {
+        println!("pezzoedit: {} not changed", path.to_string_lossy());
+        return Ok(());
+    }
+
+    tmp.install(path, mode)
+}
+
+fn main() {
+    #[cfg(target_os = "linux")]
+    linux_syscalls::init();
+
+    if let Err(err) = _main() {
+        eprintln!("{:?}", err);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch directory, removed on drop.
+    struct Dir(String);
+
+    impl Dir {
+        fn new(name: &str) -> Self {
+            let dir = format!(
+                "{}/pezzoedit-{}-{}",
+                std::env::temp_dir().display(),
+                name,
+                std::process::id()
+            );
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn path(&self, name: &str) -> CString {
+            CString::new(format!("{}/{}", self.0, name)).unwrap()
+        }
+    }
+
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// The check of pezzo, but for files owned by whoever runs the tests.
+    fn owned(path: &CStr, md: &Metadata) -> Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        if md.uid() != unsafe { libc::geteuid() } || md.mode() & 0o022 != 0 {
+            bail!("Wrong permissions on file {:?}", path);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn validate_copy() {
+        let dir = Dir::new("validate");
+        let path = dir.path("pezzo.conf");
+        let tmp_path = with_suffix(&path, b".tmp");
+
+        // A broken copy is rejected and removed.
+        let tmp = TempFile::create(tmp_path.clone(), b"rule { origin = ; }\n").unwrap();
+        assert!(validate(&tmp, &path, owned).is_err());
+        drop(tmp);
+        assert!(!as_path(&tmp_path).exists());
+
+        let tmp = TempFile::create(tmp_path, b"rule { origin = bob; target = root; }\n").unwrap();
+        validate(&tmp, &path, owned).unwrap();
+        // Writable by others, like the copy of a compromised editor.
+        std::fs::set_permissions(as_path(&tmp.path), std::fs::Permissions::from_mode(0o666))
+            .unwrap();
+        assert!(validate(&tmp, &path, owned).is_err());
+    }
+
+    #[test]
+    fn doas_syntax_by_name() {
+        let dir = Dir::new("doas");
+        let path = dir.path("doas.conf");
+        let tmp = TempFile::create(with_suffix(&path, b".tmp"), b"permit alice as root\n").unwrap();
+        validate(&tmp, &path, owned).unwrap();
+        assert!(validate(&tmp, &dir.path("pezzo.conf"), owned).is_err());
+    }
+
+    #[test]
+    #[ignore = "needs root"]
+    fn install() {
+        let dir = Dir::new("install");
+        let path = dir.path("pezzo.conf");
+        std::fs::write(as_path(&path), b"rule { origin = alice; target = root; }\n").unwrap();
+
+        // The copy replaces the original, owned by root and with the given
+        // mode.
+        let content = b"rule { origin = bob; target = root; }\n";
+        let tmp_path = with_suffix(&path, b".tmp");
+        let tmp = TempFile::create(tmp_path.clone(), content).unwrap();
+        tmp.install(&path, 0o440).unwrap();
+        assert!(!as_path(&tmp_path).exists());
+        assert_eq!(std::fs::read(as_path(&path)).unwrap(), content);
+        let md = std::fs::metadata(as_path(&path)).unwrap();
+        assert_eq!(md.permissions().mode() & 0o777, 0o440);
+        assert_eq!(std::os::unix::fs::MetadataExt::uid(&md), 0);
+    }
+}
@@ -0,0 +1,701 @@
+use std::{
+    ffi::{CStr, OsStr, OsString},
+    fmt, io,
+    os::unix::ffi::OsStrExt,
+};
+
+use crate::{
+    conf::{Action, DateTime, Ident, LocalTime, Origin, Patterns, Rule, Rules, Settings, Target},
+    unix::{Group, User},
+};
+
+/// Everything rules are matched against. Implemented over the real user
+/// database by pezzo and over command line values by pezzo-check.
+pub trait Subject {
+    fn user(&self) -> &User;
+
+    fn group(&self) -> &Group;
+
+    fn groups(&self) -> &[Group];
+
+    fn target_user(&self) -> &User;
+
+    fn target_group(&self) -> &Group;
+
+    /// Users and groups that are not in the database can only be matched by
+    /// ID.
+    fn target_user_known(&self) -> bool;
+
+    fn target_group_known(&self) -> bool;
+
+    /// Name of the user with ID 0. Named in a target, like `#0`, it stands
+    /// for any target user and group.
+    fn root_name(&self) -> io::Result<&CStr>;
+
+    /// Names of the groups the target user belongs to.
+    fn target_user_groups(&self) -> io::Result<&[Box<CStr>]>;
+
+    fn hostname(&self) -> &CStr;
+
+    fn fqdn(&self) -> Option<&CStr>;
+
+    fn command(&self) -> &CStr;
+
+    fn arguments(&self) -> &[OsString];
+
+    /// Canonical paths of the files to edit, `None` unless editing with
+    /// `pezzo -e`.
+    fn edited(&self) -> Option<&[OsString]>;
+}
+
+/// The first reason a rule does not match, in the order [`check`] tests
+/// them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mismatch {
+    Origin,
+    Target,
+    Host,
+    Edit,
+    Exe,
+    Args,
+    NotYetValid,
+    Expired,
+    Hours,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Origin => "origin does not match",
+            Self::Target => "target does not match",
+            Self::Host => "host does not match",
+            Self::Edit => "edit does not match",
+            Self::Exe => "exe does not match",
+            Self::Args => "args do not match",
+            Self::NotYetValid => "not valid yet",
+            Self::Expired => "expired",
+            Self::Hours => "outside of its hours",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Permit(usize),
+    Deny(usize),
+}
+
+#[inline]
+fn ident_matches(ident: &Ident, user: &User) -> bool {
+    match ident {
+        Ident::Id(id) => user.id() == *id,
+        Ident::Name(name) => user.name() == name.as_c_str(),
+    }
+}
+
+#[inline]
+fn ident_matches_group(ident: &Ident, group: &Group) -> bool {
+    match ident {
+        Ident::Id(id) => group.id() == *id,
+        Ident::Name(name) => group.name() == name.as_c_str(),
+    }
+}
+
+fn is_root<S: Subject + ?Sized>(subject: &S, ident: &Ident) -> io::Result<bool> {
+    match ident {
+        Ident::Id(id) => Ok(*id == 0),
+        Ident::Name(name) => Ok(subject.root_name()? == name.as_c_str()),
+    }
+}
+
+fn target_user_matches<S: Subject + ?Sized>(subject: &S, ident: &Ident) -> bool {
+    match ident {
+        Ident::Id(id) => subject.target_user().id() == *id,
+        Ident::Name(name) => {
+            subject.target_user_known() && subject.target_user().name() == name.as_c_str()
+        }
+    }
+}
+
+fn target_group_matches<S: Subject + ?Sized>(subject: &S, ident: &Ident) -> bool {
+    match ident {
+        Ident::Id(id) => subject.target_group().id() == *id,
+        Ident::Name(name) => {
+            subject.target_group_known() && subject.target_group().name() == name.as_c_str()
+        }
+    }
+}
+
+fn target_group_is_member<S: Subject + ?Sized>(subject: &S) -> io::Result<bool> {
+    if !subject.target_user_known() {
+        return Ok(subject.target_group().id() == subject.target_user().id());
+    }
+    if !subject.target_group_known() {
+        return Ok(false);
+    }
+
+    Ok(subject
+        .target_user_groups()?
+        .iter()
+        .any(|group| group.as_ref() == subject.target_group().name()))
+}
+
+#[inline]
+pub fn origin_matches<S: Subject + ?Sized>(subject: &S, rule: &Rule) -> bool {
+    origins_match(subject, &rule.origin)
+}
+
+pub fn origins_match<S: Subject + ?Sized>(subject: &S, origin: &[Origin]) -> bool {
+    origin.iter().any(|x| match x {
+        Origin::User(users) => users.iter().any(|u| ident_matches(u, subject.user())),
+        Origin::Group(groups) => groups.iter().any(|g| {
+            ident_matches_group(g, subject.group())
+                || subject.groups().iter().any(|og| ident_matches_group(g, og))
+        }),
+    })
+}
+
+/// Rules without a target match any user in the database, the root wildcard
+/// too requires a known target.
+pub fn target_matches<S: Subject + ?Sized>(subject: &S, rule: &Rule) -> io::Result<bool> {
+    let target_known = subject.target_user_known() && subject.target_group_known();
+    let targets = match rule.target {
+        Some(ref targets) => targets,
+        None => return Ok(target_known),
+    };
+
+    for target in targets {
+        let m = match target {
+            Target::User(users) => 'users: {
+                for u in users {
+                    if (target_known && is_root(subject, u)?)
+                        || (target_user_matches(subject, u) && target_group_is_member(subject)?)
+                    {
+                        break 'users true;
+                    }
+                }
+                false
+            }
+            Target::UserGroup(users, groups) => 'ug_matches: {
+                let users_matches = 'users: {
+                    for u in users {
+                        if target_known && is_root(subject, u)? {
+                            break 'ug_matches true;
+                        } else if target_user_matches(subject, u) {
+                            break 'users true;
+                        }
+                    }
+                    false
+                };
+                if !users_matches {
+                    break 'ug_matches false;
+                }
+
+                groups.iter().any(|g| target_group_matches(subject, g))
+            }
+        };
+
+        if m {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Match against the short host name, the node name and, only if needed,
+/// the fully qualified domain name.
+pub fn host_matches<S: Subject + ?Sized>(subject: &S, host: &Patterns) -> bool {
+    let hostname = subject.hostname().to_bytes();
+    let short = match memchr::memchr(b'.', hostname) {
+        Some(pos) => &hostname[..pos],
+        None => hostname,
+    };
+
+    host.is_match(OsStr::from_bytes(short))
+        || host.is_match(OsStr::from_bytes(hostname))
+        || subject
+            .fqdn()
+            .is_some_and(|fqdn| host.is_match(OsStr::from_bytes(fqdn.to_bytes())))
+}
+
+/// Rules with `edit` match when every file is in it. Permit rules without
+/// `edit` only match when not editing, deny rules without it match both.
+pub fn edit_matches<S: Subject + ?Sized>(subject: &S, rule: &Rule) -> bool {
+    match (subject.edited(), &rule.edit) {
+        (Some(files), Some(edit)) => files.iter().all(|file| edit.is_match(file)),
+        (None, Some(_)) => false,
+        (Some(_), None) => rule.action == Action::Deny,
+        (None, None) => true,
+    }
+}
+
+/// Why `rule` does not match `subject` at `now`, or `None` if it does. The
+/// time is checked last: a rule for somebody else is reported as such, not
+/// as expired.
+pub fn check<S: Subject + ?Sized>(
+    subject: &S,
+    rule: &Rule,
+    now: &LocalTime,
+) -> io::Result<Option<Mismatch>> {
+    if !origin_matches(subject, rule) {
+        return Ok(Some(Mismatch::Origin));
+    }
+    if !target_matches(subject, rule)? {
+        return Ok(Some(Mismatch::Target));
+    }
+    if let Some(ref host) = rule.host {
+        if !host_matches(subject, host) {
+            return Ok(Some(Mismatch::Host));
+        }
+    }
+    if !edit_matches(subject, rule) {
+        return Ok(Some(Mismatch::Edit));
+    }
+    if let Some(ref exe) = rule.exe {
+        if !exe.is_match(OsStr::from_bytes(subject.command().to_bytes())) {
+            return Ok(Some(Mismatch::Exe));
+        }
+    }
+    if let Some(ref args) = rule.args {
+        if !args.iter().any(|a| a.is_match(subject.arguments())) {
+            return Ok(Some(Mismatch::Args));
+        }
+    }
+    if rule.valid_from.is_some_and(|from| now.datetime < from) {
+        return Ok(Some(Mismatch::NotYetValid));
+    }
+    if rule.is_expired(now) {
+        return Ok(Some(Mismatch::Expired));
+    }
+    if !rule.is_active(now) {
+        return Ok(Some(Mismatch::Hours));
+    }
+
+    Ok(None)
+}
+
+/// The last matching `permit` rule wins, but any matching `deny` rule stops
+/// the evaluation.
+pub fn evaluate<E, F>(rules: &[Rule], mut is_match: F) -> Result<Option<Verdict>, E>
+where
+    F: FnMut(&Rule) -> Result<bool, E>,
+{
+    let mut last = None;
+
+    for (i, rule) in rules.iter().enumerate() {
+        if !is_match(rule)? {
+            continue;
+        }
+
+        match rule.action {
+            Action::Permit => last = Some(Verdict::Permit(i)),
+            Action::Deny => return Ok(Some(Verdict::Deny(i))),
+        }
+    }
+
+    Ok(last)
+}
+
+/// [`evaluate`] for `subject` at `now`.
+#[inline]
+pub fn verdict<S: Subject + ?Sized>(
+    subject: &S,
+    rules: &[Rule],
+    now: &LocalTime,
+) -> io::Result<Option<Verdict>> {
+    evaluate(rules, |rule| check(subject, rule, now).map(|m| m.is_none()))
+}
+
+/// The settings for `subject`: `defaults` blocks apply in order if they
+/// have no origin or if it matches, then `rule` overrides them.
+pub fn settings<S: Subject + ?Sized>(subject: &S, conf: &Rules, rule: Option<&Rule>) -> Settings {
+    let mut settings = Settings::default();
+    for defaults in conf.defaults() {
+        if defaults
+            .origin
+            .as_ref()
+            .is_none_or(|origin| origins_match(subject, origin))
+        {
+            settings.apply_defaults(defaults);
+        }
+    }
+    if let Some(rule) = rule {
+        settings.apply_rule(rule);
+    }
+    settings
+}
+
+/// Current wall-clock time in `timezone`, or in the system timezone if
+/// `None`.
+pub fn local_time(timezone: Option<&CStr>) -> io::Result<LocalTime> {
+    let tm = crate::unix::time::localtime(crate::unix::time::wall_clock(), timezone)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid local time"))?;
+    Ok(LocalTime {
+        datetime: DateTime {
+            year: tm.year.clamp(0, 9999) as u16,
+            month: tm.month,
+            day: tm.day,
+            minutes: u16::from(tm.hour) * 60 + u16::from(tm.minute),
+        },
+        weekday: tm.weekday,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::rules;
+
+    fn cstr(s: &str) -> Box<CStr> {
+        std::ffi::CString::new(s).unwrap().into_boxed_c_str()
+    }
+
+    struct Fake {
+        user: User,
+        group: Group,
+        target_user: User,
+        target_group: Group,
+        root: Box<CStr>,
+        hostname: Box<CStr>,
+        fqdn: Option<Box<CStr>>,
+        command: Box<CStr>,
+        edited: Option<Vec<OsString>>,
+    }
+
+    impl Default for Fake {
+        fn default() -> Self {
+            Self {
+                user: User::new(1000, cstr("alice")),
+                group: Group::new(1000, cstr("alice")),
+                target_user: User::new(0, cstr("root")),
+                target_group: Group::new(0, cstr("root")),
+                root: cstr("root"),
+                hostname: cstr("web1"),
+                fqdn: None,
+                command: cstr("/usr/bin/id"),
+                edited: None,
+            }
+        }
+    }
+
+    impl Subject for Fake {
+        fn user(&self) -> &User {
+            &self.user
+        }
+
+        fn group(&self) -> &Group {
+            &self.group
+        }
+
+        fn groups(&self) -> &[Group] {
+            &[]
+        }
+
+        fn target_user(&self) -> &User {
+            &self.target_user
+        }
+
+        fn target_group(&self) -> &Group {
+            &self.target_group
+        }
+
+        fn target_user_known(&self) -> bool {
+            true
+        }
+
+        fn target_group_known(&self) -> bool {
+            true
+        }
+
+        fn root_name(&self) -> io::Result<&CStr> {
+            Ok(&self.root)
+        }
+
+        fn target_user_groups(&self) -> io::Result<&[Box<CStr>]> {
+            Ok(std::slice::from_ref(&self.target_group.name))
+        }
+
+        fn hostname(&self) -> &CStr {
+            &self.hostname
+        }
+
+        fn fqdn(&self) -> Option<&CStr> {
+            self.fqdn.as_deref()
+        }
+
+        fn command(&self) -> &CStr {
+            &self.command
+        }
+
+        fn arguments(&self) -> &[OsString] {
+            &[]
+        }
+
+        fn edited(&self) -> Option<&[OsString]> {
+            self.edited.as_deref()
+        }
+    }
+
+    fn rule(conf: &str) -> Rule {
+        rules(conf).pop().unwrap()
+    }
+
+    fn now() -> LocalTime {
+        LocalTime {
+            datetime: DateTime {
+                year: 2024,
+                month: 1,
+                day: 1,
+                minutes: 0,
+            },
+            weekday: 1,
+        }
+    }
+
+    fn mismatch(subject: &Fake, conf: &str) -> Option<Mismatch> {
+        check(subject, &rule(conf), &now()).unwrap()
+    }
+
+    #[test]
+    fn root_wildcard() {
+        // Group 0 is not named after user 0 on every system.
+        let subject = Fake {
+            target_user: User::new(70, cstr("postgres")),
+            target_group: Group::new(70, cstr("postgres")),
+            root: cstr("toor"),
+            ..Default::default()
+        };
+        for target in ["toor", "#0", "toor:wheel", "postgres"] {
+            let conf = format!("rule {{ origin = alice; target = {}; }}", target);
+            assert_eq!(mismatch(&subject, &conf), None, "{}", target);
+        }
+        for target in ["root", "wheel", "#70:wheel"] {
+            let conf = format!("rule {{ origin = alice; target = {}; }}", target);
+            assert_eq!(
+                mismatch(&subject, &conf),
+                Some(Mismatch::Target),
+                "{}",
+                target
+            );
+        }
+    }
+
+    #[test]
+    fn deny_for_a_target() {
+        let rules = [
+            rule("rule { origin = alice; }"),
+            rule("rule { action = deny; origin = alice; target = postgres; exe = /usr/bin/id; }"),
+        ];
+        let subject = Fake::default();
+        assert_eq!(
+            verdict(&subject, &rules, &now()).unwrap(),
+            Some(Verdict::Permit(0))
+        );
+
+        let subject = Fake {
+            target_user: User::new(70, cstr("postgres")),
+            target_group: Group::new(70, cstr("postgres")),
+            ..Default::default()
+        };
+        assert_eq!(
+            verdict(&subject, &rules, &now()).unwrap(),
+            Some(Verdict::Deny(1))
+        );
+    }
+
+    #[test]
+    fn host() {
+        let subject = Fake {
+            hostname: cstr("web1.example.com"),
+            fqdn: Some(cstr("web1.dc1.example.com")),
+            ..Default::default()
+        };
+        let mismatch = |host: &str| {
+            mismatch(
+                &subject,
+                &format!("rule {{ origin = alice; host = {}; }}", host),
+            )
+        };
+
+        for host in [
+            "web1",
+            "web1.example.com",
+            "web1.dc1.example.com",
+            "db1 | web1",
+            "web*",
+            "*.dc1.example.com",
+            "WEB1.EXAMPLE.COM",
+            "Web?.Example.COM",
+        ] {
+            assert_eq!(mismatch(host), None, "{}", host);
+        }
+        for host in [
+            "web2",
+            "db*",
+            "web1.example",
+            "*.dc2.example.com",
+            "WEB2.EXAMPLE.COM",
+        ] {
+            assert_eq!(mismatch(host), Some(Mismatch::Host), "{}", host);
+        }
+    }
+
+    #[test]
+    fn edit() {
+        let rules = [
+            rule("rule { origin = alice; edit = /etc/nginx/**; }"),
+            rule("rule { origin = alice; exe = /usr/bin/id; }"),
+        ];
+        let editing = Fake {
+            command: cstr("/usr/bin/vi"),
+            edited: Some(vec!["/etc/nginx/nginx.conf".into()]),
+            ..Default::default()
+        };
+        assert_eq!(
+            verdict(&editing, &rules, &now()).unwrap(),
+            Some(Verdict::Permit(0))
+        );
+        assert_eq!(
+            verdict(&Fake::default(), &rules, &now()).unwrap(),
+            Some(Verdict::Permit(1))
+        );
+
+        let subject = Fake {
+            edited: Some(vec!["/etc/shadow".into()]),
+            ..editing
+        };
+        assert_eq!(
+            mismatch(&subject, "rule { origin = alice; edit = /etc/nginx/**; }"),
+            Some(Mismatch::Edit)
+        );
+        assert_eq!(
+            mismatch(&subject, "rule { origin = alice; }"),
+            Some(Mismatch::Edit)
+        );
+
+        // Only permit rules need `edit`.
+        let rules = [
+            rule("rule { origin = alice; edit = /etc/**; }"),
+            rule("rule { action = deny; origin = alice; }"),
+        ];
+        assert_eq!(
+            verdict(&subject, &rules, &now()).unwrap(),
+            Some(Verdict::Deny(1))
+        );
+    }
+
+    /// [`evaluate`] with rules that only match on `exe`.
+    fn evaluate_exe(rules: &[Rule], command: &str) -> Option<Verdict> {
+        evaluate::<(), _>(rules, |rule| {
+            Ok(rule.exe.as_ref().is_none_or(|exe| exe.is_match(command)))
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn permit_by_default() {
+        let rules = rules("rule { origin = :wheel; }");
+        assert_eq!(
+            evaluate_exe(&rules, "/usr/bin/id"),
+            Some(Verdict::Permit(0))
+        );
+    }
+
+    #[test]
+    fn no_match() {
+        let rules = rules("rule { origin = :wheel; exe = /usr/bin/id; }");
+        assert_eq!(evaluate_exe(&rules, "/usr/bin/passwd"), None);
+    }
+
+    #[test]
+    fn deny_carves_exception() {
+        let rules = rules(
+            "rule { origin = :wheel; }
+            rule { action = deny; origin = :wheel; exe = /usr/bin/passwd; }",
+        );
+        assert_eq!(
+            evaluate_exe(&rules, "/usr/bin/id"),
+            Some(Verdict::Permit(0))
+        );
+        assert_eq!(
+            evaluate_exe(&rules, "/usr/bin/passwd"),
+            Some(Verdict::Deny(1))
+        );
+    }
+
+    #[test]
+    fn deny_wins_over_later_permit() {
+        let rules = rules(
+            "rule { action = deny; origin = :wheel; exe = /usr/bin/passwd; }
+            rule { origin = :wheel; }
+            rule { action = permit; origin = :wheel; exe = /usr/bin/*; }",
+        );
+        assert_eq!(
+            evaluate_exe(&rules, "/usr/bin/passwd"),
+            Some(Verdict::Deny(0))
+        );
+        assert_eq!(
+            evaluate_exe(&rules, "/usr/bin/id"),
+            Some(Verdict::Permit(2))
+        );
+        assert_eq!(evaluate_exe(&rules, "/bin/sh"), Some(Verdict::Permit(1)));
+    }
+
+    #[test]
+    fn first_deny_stops_evaluation() {
+        let rules = rules(
+            "rule { origin = :wheel; exe = /usr/bin/*; action = deny; }
+            rule { origin = :wheel; exe = /usr/bin/passwd; action = deny; }",
+        );
+        assert_eq!(
+            evaluate_exe(&rules, "/usr/bin/passwd"),
+            Some(Verdict::Deny(0))
+        );
+    }
+
+    #[test]
+    fn action_defined_twice() {
+        assert!(
+            crate::conf::parse("rule { origin = :wheel; action = deny; action = permit; }")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn time_last() {
+        let subject = Fake::default();
+        for (conf, expected) in [
+            (
+                "rule { origin = bob; valid_until = 2023-12-31; }",
+                Mismatch::Origin,
+            ),
+            (
+                "rule { origin = alice; target = postgres; hours = 08:00-18:00; }",
+                Mismatch::Target,
+            ),
+            (
+                "rule { origin = alice; host = db1; valid_from = 2025-01-01; }",
+                Mismatch::Host,
+            ),
+            (
+                "rule { origin = alice; exe = /usr/bin/su; valid_until = 2023-12-31; }",
+                Mismatch::Exe,
+            ),
+            (
+                "rule { origin = alice; valid_from = 2025-01-01; }",
+                Mismatch::NotYetValid,
+            ),
+            (
+                "rule { origin = alice; valid_until = 2023-12-31; }",
+                Mismatch::Expired,
+            ),
+            (
+                "rule { origin = alice; hours = 08:00-18:00; }",
+                Mismatch::Hours,
+            ),
+        ] {
+            assert_eq!(mismatch(&subject, conf), Some(expected), "{}", conf);
+        }
+    }
+}
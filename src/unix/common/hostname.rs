@@ -3,7 +3,7 @@ use std::ffi::{CStr, CString};
 cfg_if::cfg_if! {
     if #[cfg(target_os = "linux")] {
         pub fn hostname() -> CString {
-            unsafe { CStr::from_ptr(linux_syscalls::env::uname().domainname.as_ptr().cast()).into() }
+            unsafe { CStr::from_ptr(linux_syscalls::env::uname().nodename.as_ptr().cast()).into() }
         }
     } else {
         use crate::unix::__errno;
@@ -39,3 +39,46 @@ cfg_if::cfg_if! {
         }
     }
 }
+
+/// Resolve the canonical (fully qualified) name of `hostname`.
+pub fn fqdn<S: AsRef<CStr>>(hostname: S) -> Option<CString> {
+    unsafe {
+        let mut hints = core::mem::zeroed::<libc::addrinfo>();
+        hints.ai_family = libc::AF_UNSPEC;
+        hints.ai_flags = libc::AI_CANONNAME;
+
+        let mut res = core::ptr::null_mut();
+        if libc::getaddrinfo(
+            hostname.as_ref().as_ptr(),
+            core::ptr::null(),
+            &hints,
+            &mut res,
+        ) != 0
+        {
+            return None;
+        }
+
+        let name = if (*res).ai_canonname.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr((*res).ai_canonname).into())
+        };
+        libc::freeaddrinfo(res);
+
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hostname_is_the_node_name() {
+        let mut buf = [0 as libc::c_char; 256];
+        assert_eq!(unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) }, 0);
+        let expected = unsafe { CStr::from_ptr(buf.as_ptr()) };
+        assert!(!expected.is_empty());
+        assert_eq!(hostname().as_c_str(), expected);
+    }
+}
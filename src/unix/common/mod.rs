@@ -1,3 +1,3 @@
 mod hostname;
 
-pub use hostname::hostname;
+pub use hostname::{fqdn, hostname};
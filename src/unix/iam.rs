@@ -27,12 +27,18 @@ impl IAMContext {
             let gid = (*raw).pw_gid;
             let name = CStr::from_ptr((*raw).pw_name).to_owned().into_boxed_c_str();
             let home = CStr::from_ptr((*raw).pw_dir).to_owned().into_boxed_c_str();
+            // An empty shell means the default one, as for login(1).
+            let shell = match (*raw).pw_shell {
+                shell if shell.is_null() || *shell == 0 => c"/bin/sh".into(),
+                shell => CStr::from_ptr(shell).to_owned().into_boxed_c_str(),
+            };
 
             Pwd {
                 uid,
                 gid,
                 name,
                 home,
+                shell,
             }
         }
     }
@@ -1,6 +1,7 @@
 mod common;
 mod iam;
 pub mod pam;
+pub mod pty;
 pub mod tty;
 pub mod which;
 #[macro_use]
@@ -31,7 +32,7 @@ mod process;
 #[allow(unused_imports)]
 pub use imp::*;
 
-pub use common::hostname;
+pub use common::{fqdn, hostname};
 pub use iam::IAMContext;
 pub use process::*;
 use tty_info::TtyInfo;
@@ -41,6 +42,18 @@ use crate::{io, DEFAULT_MAX_RETRIES, DEFAULT_PROMPT_TIMEOUT, PEZZO_NAME_CSTR};
 use self::tty::{TtyIn, TtyOut};
 
 pub mod time {
+    use std::{
+        ffi::{CStr, OsStr},
+        mem::MaybeUninit,
+        os::unix::ffi::OsStrExt,
+    };
+
+    extern "C" {
+        fn tzset();
+    }
+
+    /// Monotonic seconds, not affected by changes to the system clock. Use
+    /// [`wall_clock`] for calendar time.
     pub fn now() -> u64 {
         unsafe {
             core::mem::transmute(
@@ -50,6 +63,61 @@ pub mod time {
             )
         }
     }
+
+    /// Seconds since the Unix epoch.
+    pub fn wall_clock() -> i64 {
+        match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+            Ok(d) => d.as_secs() as i64,
+            Err(err) => -(err.duration().as_secs() as i64),
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct Tm {
+        pub year: i32,
+        pub month: u8,
+        pub day: u8,
+        pub hour: u8,
+        pub minute: u8,
+        /// Days since Sunday.
+        pub weekday: u8,
+    }
+
+    /// Broken-down local time in `timezone`, or in the system timezone if
+    /// `None`. The `TZ` variable of the caller is never trusted and is
+    /// restored before returning.
+    pub fn localtime(secs: i64, timezone: Option<&CStr>) -> Option<Tm> {
+        let saved = std::env::var_os("TZ");
+        match timezone {
+            Some(tz) => std::env::set_var("TZ", OsStr::from_bytes(tz.to_bytes())),
+            None => std::env::remove_var("TZ"),
+        }
+
+        let mut tm = MaybeUninit::<libc::tm>::uninit();
+        let res = unsafe {
+            tzset();
+            libc::localtime_r(&(secs as libc::time_t), tm.as_mut_ptr())
+        };
+
+        match saved {
+            Some(tz) => std::env::set_var("TZ", tz),
+            None => std::env::remove_var("TZ"),
+        }
+        unsafe { tzset() };
+
+        if res.is_null() {
+            return None;
+        }
+        let tm = unsafe { tm.assume_init() };
+        Some(Tm {
+            year: tm.tm_year + 1900,
+            month: (tm.tm_mon + 1) as u8,
+            day: tm.tm_mday as u8,
+            hour: tm.tm_hour as u8,
+            minute: tm.tm_min as u8,
+            weekday: tm.tm_wday as u8,
+        })
+    }
 }
 
 pub struct Pwd {
@@ -57,6 +125,7 @@ pub struct Pwd {
     pub uid: u32,
     pub home: Box<CStr>,
     pub gid: u32,
+    pub shell: Box<CStr>,
 }
 
 #[cfg(any(target_os = "linux", target_os = "dragonfly"))]
@@ -80,12 +149,16 @@ pub use libc::__errno;
 pub struct Context {
     iam: IAMContext,
     proc_ctx: ProcessContext,
-    tty_ctx: Rc<TtyInfo>,
-    tty_in: Rc<RefCell<TtyIn>>,
-    tty_out: Rc<RefCell<TtyOut>>,
+    tty_ctx: Option<Rc<TtyInfo>>,
+    tty_in: Option<Rc<RefCell<TtyIn>>>,
+    tty_out: Option<Rc<RefCell<TtyOut>>>,
+    password_input: pam::PasswordInput,
     target_user: User,
     target_group: Group,
     bell: bool,
+    prompt_timeout: u32,
+    max_retries: usize,
+    prompt: Option<Vec<u8>>,
 }
 
 impl Context {
@@ -97,8 +170,13 @@ impl Context {
         bell: bool,
     ) -> io::Result<Self> {
         let tty_ctx = proc_ctx.tty.clone();
-        let tty_in = Rc::new(RefCell::new(TtyIn::open(tty_ctx.clone())?));
-        let tty_out = Rc::new(RefCell::new(TtyOut::open(tty_ctx.clone())?));
+        let (tty_in, tty_out) = match tty_ctx {
+            Some(ref tty) => (
+                Some(Rc::new(RefCell::new(TtyIn::open(tty.clone())?))),
+                Some(Rc::new(RefCell::new(TtyOut::open(tty.clone())?))),
+            ),
+            None => (None, None),
+        };
 
         Ok(Self {
             iam,
@@ -106,9 +184,13 @@ impl Context {
             tty_ctx,
             tty_in,
             tty_out,
+            password_input: pam::PasswordInput::default(),
             target_user,
             target_group,
             bell,
+            prompt_timeout: DEFAULT_PROMPT_TIMEOUT,
+            max_retries: DEFAULT_MAX_RETRIES,
+            prompt: None,
         })
     }
 
@@ -132,6 +214,11 @@ impl Context {
         &self.proc_ctx.original_group
     }
 
+    #[inline]
+    pub fn original_groups(&self) -> &[Group] {
+        &self.proc_ctx.original_groups
+    }
+
     #[inline]
     pub fn target_user(&self) -> &User {
         &self.target_user
@@ -148,43 +235,74 @@ impl Context {
     }
 
     #[inline]
-    pub fn ttyno(&self) -> Dev {
-        self.proc_ctx.tty.device()
+    pub fn ttyno(&self) -> Option<Dev> {
+        self.tty_ctx.as_ref().map(|tty| tty.device())
     }
 
     #[inline]
-    pub fn tty_path(&self) -> &CStr {
-        self.tty_ctx.path()
+    pub fn tty_path(&self) -> Option<&CStr> {
+        self.tty_ctx.as_ref().map(|tty| tty.path())
     }
 
     #[inline]
-    pub fn tty_name(&self) -> &CStr {
-        self.tty_ctx.name()
+    pub fn tty_name(&self) -> Option<&CStr> {
+        self.tty_ctx.as_ref().map(|tty| tty.name())
     }
 
     #[inline]
-    pub fn tty_in(&self) -> Rc<RefCell<TtyIn>> {
+    pub fn tty_in(&self) -> Option<Rc<RefCell<TtyIn>>> {
         self.tty_in.clone()
     }
 
     #[inline]
-    pub fn tty_out(&self) -> Rc<RefCell<TtyOut>> {
+    pub fn tty_out(&self) -> Option<Rc<RefCell<TtyOut>>> {
         self.tty_out.clone()
     }
 
     #[inline]
-    pub fn tty_inout(&self) -> (Rc<RefCell<TtyIn>>, Rc<RefCell<TtyOut>>) {
-        (self.tty_in.clone(), self.tty_out.clone())
+    pub fn tty_inout(&self) -> Option<(Rc<RefCell<TtyIn>>, Rc<RefCell<TtyOut>>)> {
+        Some((self.tty_in.clone()?, self.tty_out.clone()?))
+    }
+
+    #[inline]
+    pub fn password_input(&self) -> &pam::PasswordInput {
+        &self.password_input
+    }
+
+    #[inline]
+    pub fn set_password_input(&mut self, password_input: pam::PasswordInput) {
+        self.password_input = password_input;
     }
 
     #[inline]
     pub fn prompt_timeout(&self) -> u32 {
-        DEFAULT_PROMPT_TIMEOUT
+        self.prompt_timeout
+    }
+
+    #[inline]
+    pub fn set_prompt_timeout(&mut self, prompt_timeout: u32) {
+        self.prompt_timeout = prompt_timeout;
     }
 
     #[inline]
     pub fn max_retries(&self) -> usize {
-        DEFAULT_MAX_RETRIES
+        self.max_retries
+    }
+
+    #[inline]
+    pub fn set_max_retries(&mut self, max_retries: usize) {
+        self.max_retries = max_retries;
+    }
+
+    /// Password prompt, already expanded, the built-in one if `None`.
+    #[inline]
+    pub fn prompt(&self) -> Option<&[u8]> {
+        self.prompt.as_deref()
+    }
+
+    #[inline]
+    pub fn set_prompt(&mut self, prompt: Option<Vec<u8>>) {
+        self.prompt = prompt;
     }
 
     #[inline]
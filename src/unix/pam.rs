@@ -1,8 +1,14 @@
 use std::{
     cell::RefCell,
-    ffi::CStr,
-    io::{self, Write},
+    ffi::{CStr, OsStr},
+    io::{self, BufRead, BufReader, IsTerminal, Read, Write},
     mem::{self},
+    os::{
+        fd::{AsFd, AsRawFd, RawFd},
+        unix::{ffi::OsStrExt, process::CommandExt},
+    },
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
     rc::Rc,
 };
 
@@ -42,46 +48,190 @@ impl<'a> Iterator for LinesIterator<'a> {
     }
 }
 
+/// Expands the escapes of a password prompt like sudo does: `%u` is the
+/// invoking user, `%U` the target user, `%h` the hostname, `%H` the fully
+/// qualified one and `%p` the user whose password is asked. `vars` returns
+/// the value of each of them, other escapes are kept as they are and `%%`
+/// is a literal `%`.
+pub fn expand_prompt<'a, F>(template: &[u8], mut vars: F) -> Vec<u8>
+where
+    F: FnMut(u8) -> Option<&'a [u8]>,
+{
+    let mut res = Vec::with_capacity(template.len());
+    let mut it = template.iter().copied();
+    while let Some(c) = it.next() {
+        if c != b'%' {
+            res.push(c);
+            continue;
+        }
+        match it.next() {
+            Some(b'%') => res.push(b'%'),
+            Some(c) => match vars(c) {
+                Some(value) => res.extend_from_slice(value),
+                None => res.extend_from_slice(&[b'%', c]),
+            },
+            None => res.push(b'%'),
+        }
+    }
+    res
+}
+
+/// Where [`PezzoConversation`] reads the answers to the prompts from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum PasswordInput {
+    /// The controlling terminal, prompts and messages are written to it.
+    #[default]
+    Tty,
+    /// The standard input, which needs not be a terminal. Prompts and
+    /// messages are written to the standard error.
+    Stdin,
+    /// The first line written by a program run as the invoking user, with
+    /// the prompt as its only argument. Messages are written to the standard
+    /// error.
+    Askpass(PathBuf),
+}
+
+/// A reader that takes one byte at a time, so that nothing past the answer
+/// is consumed from a pipe the command may read next.
+struct Unbuffered<R>(BufReader<R>);
+
+impl<R: Read> Unbuffered<R> {
+    #[inline]
+    fn new(inner: R) -> Self {
+        Self(BufReader::with_capacity(1, inner))
+    }
+}
+
+impl<R: Read> Read for Unbuffered<R> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl<R: Read> BufRead for Unbuffered<R> {
+    #[inline]
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.0.fill_buf()
+    }
+
+    #[inline]
+    fn consume(&mut self, amt: usize) {
+        self.0.consume(amt)
+    }
+}
+
+impl<R: AsRawFd> AsRawFd for Unbuffered<R> {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.get_ref().as_raw_fd()
+    }
+}
+
+fn read_stdin(echo: bool, timeout: u32) -> io::Result<secure_read::CBuffer> {
+    let stdin = std::fs::File::from(io::stdin().as_fd().try_clone_to_owned()?);
+    let noecho = !echo && stdin.is_terminal();
+    let mut stdin = Unbuffered::new(stdin);
+    if noecho {
+        secure_read::secure_read_noecho(&mut stdin, secure_read::CBuffer::new(), timeout)
+    } else {
+        secure_read::secure_read_notty(&mut stdin, secure_read::CBuffer::new(), timeout)
+    }
+}
+
+fn read_askpass(
+    program: &Path,
+    (uid, gid): (u32, u32),
+    prompt: &[u8],
+    timeout: u32,
+) -> io::Result<secure_read::CBuffer> {
+    let mut child = Command::new(program)
+        .arg(OsStr::from_bytes(prompt))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .uid(uid)
+        .gid(gid)
+        .spawn()?;
+    let res = match child.stdout.take() {
+        Some(out) => secure_read::secure_read_notty(
+            &mut Unbuffered::new(out),
+            secure_read::CBuffer::new(),
+            timeout,
+        ),
+        None => Err(io::ErrorKind::BrokenPipe.into()),
+    };
+    if res.is_err() {
+        _ = child.kill();
+    }
+    let status = child.wait()?;
+    if res.is_ok() && !status.success() {
+        return Err(io::Error::other(format!(
+            "{} exited with {}",
+            program.display(),
+            status
+        )));
+    }
+    res
+}
+
 pub struct PezzoConversation<'a> {
     name: &'a CStr,
+    prompt: Option<&'a [u8]>,
     timedout: bool,
     timeout: u32,
-    tty_in: Rc<RefCell<TtyIn>>,
-    tty_out: Rc<RefCell<TtyOut>>,
+    input: PasswordInput,
+    tty_in: Option<Rc<RefCell<TtyIn>>>,
+    tty_out: Option<Rc<RefCell<TtyOut>>>,
+    user: (u32, u32),
     bell: bool,
 }
 
 impl<'a> PezzoConversation<'a> {
     #[inline]
     pub fn new(ctx: &'a super::Context) -> Self {
-        Self::from_values(
-            ctx.prompt_timeout(),
-            ctx.tty_in(),
-            ctx.tty_out(),
-            ctx.original_user().name(),
-            ctx.bell(),
-        )
+        Self {
+            timeout: ctx.prompt_timeout(),
+            timedout: false,
+            input: ctx.password_input().clone(),
+            tty_in: ctx.tty_in(),
+            tty_out: ctx.tty_out(),
+            user: (ctx.original_user().id(), ctx.original_group().id()),
+            name: ctx.original_user().name(),
+            prompt: ctx.prompt(),
+            bell: ctx.bell(),
+        }
     }
 
     #[inline]
-    pub fn from_values(
-        timeout: u32,
-        tty_in: Rc<RefCell<TtyIn>>,
-        tty_out: Rc<RefCell<TtyOut>>,
-        name: &'a CStr,
-        bell: bool,
-    ) -> Self {
-        Self {
-            timeout,
-            timedout: false,
-            tty_in,
-            tty_out,
-            name,
-            bell,
+    fn eol(&self) -> &'static [u8] {
+        if self.input == PasswordInput::Tty {
+            b"\r\n"
+        } else {
+            b"\n"
         }
     }
 
-    fn _prompt(&mut self, prompt: &CStr, echo: bool) -> ConvResult<secure_read::CBuffer> {
+    /// Writes with `f` to the terminal, or to the standard error when the
+    /// password is not read from it or there is none.
+    fn write_out<F>(&self, f: F) -> ConvResult<()>
+    where
+        F: FnOnce(&mut dyn Write) -> io::Result<()>,
+    {
+        match self.tty_out {
+            Some(ref out) if self.input == PasswordInput::Tty => {
+                let mut out = out.borrow_mut();
+                f(&mut *out).and_then(|_| out.flush())
+            }
+            _ => {
+                let mut out = io::stderr().lock();
+                f(&mut out).and_then(|_| out.flush())
+            }
+        }
+        .map_err(|_| ConvError::Generic)
+    }
+
+    /// The text of `prompt`, the password prompt if it asks for the password.
+    fn prompt_text(&self, prompt: &CStr) -> Vec<u8> {
         fn base_prompt_is_password(prompt: &CStr, name: &CStr) -> bool {
             if let Some(rest) = prompt.to_bytes().strip_prefix(b"Password:") {
                 return rest.is_empty() || rest == b" ";
@@ -122,104 +272,124 @@ impl<'a> PezzoConversation<'a> {
         }
 
         if prompt_is_password(prompt, self.name) {
-            self.print_prompt_password()?;
-        } else {
-            let mut out = self.tty_out.borrow_mut();
-            let mut it = LinesIterator::new(prompt.to_bytes());
-            if let Some(mut prev) = it.next() {
-                for mut line in it {
-                    mem::swap(&mut line, &mut prev);
+            return self.password_prompt();
+        }
 
-                    out.write_all(line).map_err(|_| ConvError::Generic)?;
-                    out.write_all(b"\r\n").map_err(|_| ConvError::Generic)?;
-                }
-                let line = prev;
-                if !line.is_empty() {
-                    out.write_all(line).map_err(|_| ConvError::Generic)?;
-                    if unsafe { *line.get_unchecked(line.len() - 1) } != b' ' {
-                        out.write_all(b" ").map_err(|_| ConvError::Generic)?;
-                    }
-                }
-            }
+        let mut text = Vec::with_capacity(prompt.to_bytes().len() + 1);
+        let mut it = LinesIterator::new(prompt.to_bytes());
+        if let Some(mut prev) = it.next() {
+            for mut line in it {
+                mem::swap(&mut line, &mut prev);
 
-            if self.bell {
-                out.write_all(b"\x07").map_err(|_| ConvError::Generic)?;
+                text.extend_from_slice(line);
+                text.extend_from_slice(self.eol());
+            }
+            let line = prev;
+            if !line.is_empty() {
+                text.extend_from_slice(line);
+                if unsafe { *line.get_unchecked(line.len() - 1) } != b' ' {
+                    text.push(b' ');
+                }
             }
-
-            out.flush().map_err(|_| ConvError::Generic)?;
         }
+        text
+    }
 
+    fn _prompt(&mut self, prompt: &CStr, echo: bool) -> ConvResult<secure_read::CBuffer> {
+        let text = self.prompt_text(prompt);
         let timeout = self.prompt_timeout();
-        let buf = {
-            let mut inp = self.tty_in.borrow_mut();
-            let line_res = if echo {
-                inp.c_readline(timeout)
-            } else {
-                inp.c_readline_noecho(timeout)
-            };
-            match line_res {
-                Err(err) => {
-                    {
-                        let mut out = self.tty_out.borrow_mut();
-                        _ = out.write_all(b"\n");
-                        _ = out.flush();
-
-                        if err.kind() == io::ErrorKind::TimedOut {
-                            self.timedout = true;
-                            _ = out.write_all(b"pezzo: timed out reading password\n");
-                            _ = out.flush();
-                        }
-                    }
-                    Err(ConvError::Generic)
-                }
-                Ok(mut buf) => {
-                    if buf.as_slice().last().map_or(false, |&c| c == b'\n') {
-                        if let Some(l) = buf.len().checked_sub(1) {
-                            buf.truncate(l)
-                        }
-                    } else {
-                        let mut out = self.tty_out.borrow_mut();
-                        _ = out.write_all(b"\n");
-                        _ = out.flush();
+        let askpass = matches!(self.input, PasswordInput::Askpass(_));
+
+        let line_res = match self.input {
+            PasswordInput::Tty => {
+                let Some(ref inp) = self.tty_in else {
+                    _ = self.write_out(|out| {
+                        out.write_all(b"pezzo: a terminal is required to read the password\n")
+                    });
+                    return Err(ConvError::Generic);
+                };
+                self.write_out(|out| {
+                    out.write_all(&text)?;
+                    if self.bell {
+                        out.write_all(b"\x07")?;
                     }
-                    Ok(buf)
+                    Ok(())
+                })?;
+                let mut inp = inp.borrow_mut();
+                if echo {
+                    inp.c_readline(timeout)
+                } else {
+                    inp.c_readline_noecho(timeout)
                 }
-            }?
+            }
+            PasswordInput::Stdin => {
+                self.write_out(|out| out.write_all(&text))?;
+                read_stdin(echo, timeout)
+            }
+            PasswordInput::Askpass(ref program) => read_askpass(program, self.user, &text, timeout),
         };
 
-        Ok(buf)
+        match line_res {
+            Err(err) => {
+                let timedout = err.kind() == io::ErrorKind::TimedOut;
+                _ = self.write_out(|out| {
+                    if !askpass {
+                        out.write_all(b"\n")?;
+                    }
+                    if askpass && !timedout {
+                        writeln!(out, "pezzo: cannot read password: {}", err)?;
+                    }
+                    Ok(())
+                });
+                self.timedout = timedout;
+                Err(ConvError::Generic)
+            }
+            Ok(mut buf) => {
+                if buf.as_slice().last().map_or(false, |&c| c == b'\n') {
+                    if let Some(l) = buf.len().checked_sub(1) {
+                        buf.truncate(l)
+                    }
+                } else if !askpass {
+                    _ = self.write_out(|out| out.write_all(b"\n"));
+                }
+                Ok(buf)
+            }
+        }
     }
 
-    fn print_message(&mut self, message: &[u8]) -> ConvResult<()> {
-        let mut out = self.tty_out.borrow_mut();
-        let mut it = LinesIterator::new(message);
-        if let Some(mut prev) = it.next() {
-            for mut line in it {
-                mem::swap(&mut line, &mut prev);
+    /// Writes `message` line by line.
+    pub fn print_message(&mut self, message: &[u8]) -> ConvResult<()> {
+        let eol = self.eol();
+        self.write_out(|out| {
+            let mut it = LinesIterator::new(message);
+            if let Some(mut prev) = it.next() {
+                for mut line in it {
+                    mem::swap(&mut line, &mut prev);
 
-                out.write_all(line).map_err(|_| ConvError::Generic)?;
-                out.write_all(b"\r\n").map_err(|_| ConvError::Generic)?;
-            }
-            let line = prev;
-            if !line.is_empty() {
-                out.write_all(line).map_err(|_| ConvError::Generic)?;
-                out.write_all(b"\r\n").map_err(|_| ConvError::Generic)?;
+                    out.write_all(line)?;
+                    out.write_all(eol)?;
+                }
+                let line = prev;
+                if !line.is_empty() {
+                    out.write_all(line)?;
+                    out.write_all(eol)?;
+                }
             }
-        }
-        out.flush().map_err(|_| ConvError::Generic)
+            Ok(())
+        })
     }
 
-    pub fn print_prompt_password(&mut self) -> ConvResult<()> {
-        let mut out = self.tty_out.borrow_mut();
-        out.write_all(b"[pezzo] Password for ")
-            .map_err(|_| ConvError::Generic)?;
-        out.write_all(self.name.to_bytes())
-            .map_err(|_| ConvError::Generic)?;
-        out.write_all(b": ").map_err(|_| ConvError::Generic)?;
-        if self.bell {
-            out.write_all(b"\x07").map_err(|_| ConvError::Generic)?;
+    /// The prompt for the password, with the `prompt` given or the built-in
+    /// one.
+    pub fn password_prompt(&self) -> Vec<u8> {
+        if let Some(prompt) = self.prompt {
+            prompt.to_vec()
+        } else {
+            let mut text = b"[pezzo] Password for ".to_vec();
+            text.extend_from_slice(self.name.to_bytes());
+            text.extend_from_slice(b": ");
+            text
         }
-        out.flush().map_err(|_| ConvError::Generic)
     }
 
     #[inline]
@@ -258,3 +428,47 @@ impl<'a> Conversation for PezzoConversation<'a> {
         self.print_message(message.to_bytes())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::{rules, Settings};
+
+    #[test]
+    fn prompt() {
+        let rules = rules(
+            r#"rule {
+                origin = :wheel;
+                exe = /usr/sbin/reboot;
+                prompt_timeout = 10;
+                max_retries = 1;
+                prompt = "[%h] reboot as %U, password for %p: ";
+            }"#,
+        );
+        let mut settings = Settings::default();
+        settings.apply_rule(&rules[0]);
+        assert_eq!(settings.prompt_timeout, 10);
+        assert_eq!(settings.max_retries, 1);
+        let prompt = settings.prompt.unwrap();
+
+        let vars = |c| match c {
+            b'u' | b'p' => Some(b"alice".as_slice()),
+            b'U' => Some(b"root".as_slice()),
+            b'h' => Some(b"web1".as_slice()),
+            b'H' => Some(b"web1.example.com".as_slice()),
+            _ => None,
+        };
+        assert_eq!(
+            expand_prompt(prompt.to_bytes(), vars),
+            b"[web1] reboot as root, password for alice: "
+        );
+        assert_eq!(
+            expand_prompt(b"%H %% %x 100%", vars),
+            b"web1.example.com % %x 100%"
+        );
+
+        let text = rules[0].to_string();
+        assert!(text.contains("    prompt = \"[%h] reboot as %U, password for %p: \";\n"));
+        assert!(crate::conf::parse("rule { origin = bob; max_retries = 0; }").is_err());
+    }
+}
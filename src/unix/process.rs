@@ -39,6 +39,11 @@ impl User {
 }
 
 impl Group {
+    #[inline]
+    pub fn new(id: u32, name: Box<CStr>) -> Group {
+        Group { id, name }
+    }
+
     #[inline]
     pub fn name(&self) -> &CStr {
         self.name.as_ref()
@@ -63,19 +68,14 @@ pub struct ProcessContext {
     pub original_group: Group,
     pub original_groups: Vec<Group>,
     pub sid: u32,
-    pub tty: Rc<TtyInfo>,
+    /// The controlling terminal, if any.
+    pub tty: Option<Rc<TtyInfo>>,
 }
 
 impl ProcessContext {
     pub fn current(iam: &IAMContext) -> io::Result<Self> {
         let (pid, uid, gid, session, tty) = super::process_infos()?;
 
-        let tty = if let Some(tty) = tty {
-            tty
-        } else {
-            return Err(io::ErrorKind::NotFound.into());
-        };
-
         iam.set_effective_identity(uid, gid)?;
 
         let exe = std::fs::canonicalize(std::env::current_exe()?)?;
@@ -111,7 +111,7 @@ impl ProcessContext {
             original_group,
             original_groups,
             sid: session,
-            tty: Rc::new(tty),
+            tty: tty.map(Rc::new),
         })
     }
 }
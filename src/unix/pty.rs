@@ -0,0 +1,760 @@
+//! Commands run on a new pseudo-terminal, with `use_pty`.
+//!
+//! The command gets a terminal of its own instead of the one of the invoking
+//! user, so it cannot push input into it with `TIOCSTI` or `TIOCLINUX`.
+//! pezzo stays in the middle: it relays what is typed and printed, the
+//! window size and the signals it gets, and exits when the command does.
+//!
+//! pezzo stays in the session of the user and forks a monitor, which leads
+//! a new session with the pty as its controlling terminal and runs the
+//! command in the foreground of it, in a process group of its own. The
+//! command can thus be stopped from the pty like from any terminal: the
+//! monitor tells pezzo, which stops too when there is a terminal to give
+//! back to the user, and has the command resumed once continued.
+
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString, OsStr},
+    fs::{File, OpenOptions},
+    io::{self, BufRead, Read, Write},
+    mem::MaybeUninit,
+    os::{
+        fd::{AsRawFd, FromRawFd, RawFd},
+        unix::{
+            ffi::OsStrExt,
+            fs::OpenOptionsExt,
+            net::UnixStream,
+            process::{CommandExt, ExitStatusExt},
+        },
+    },
+    process::{Command, ExitStatus, Stdio},
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+use libc::c_int;
+
+use super::tty::{TtyIn, TtyOut};
+
+/// Signals sent to pezzo or to the monitor that are passed on to the
+/// command.
+const FORWARDED: [c_int; 6] = [
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTERM,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+];
+
+/// Write end of the pipe signal numbers are sent through, `-1` if unset.
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+#[inline]
+fn cvt(res: c_int) -> io::Result<c_int> {
+    if res == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res)
+    }
+}
+
+fn set_flags(fd: RawFd, nonblock: bool) -> io::Result<()> {
+    unsafe {
+        cvt(libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC))?;
+        if nonblock {
+            let flags = cvt(libc::fcntl(fd, libc::F_GETFL))?;
+            cvt(libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn ptsname(fd: RawFd) -> io::Result<CString> {
+    let mut buf = [0 as libc::c_char; 128];
+    match unsafe { libc::ptsname_r(fd, buf.as_mut_ptr(), buf.len()) } {
+        0 => Ok(unsafe { CStr::from_ptr(buf.as_ptr()) }.to_owned()),
+        err => Err(io::Error::from_raw_os_error(err)),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn ptsname(fd: RawFd) -> io::Result<CString> {
+    let name = unsafe { libc::ptsname(fd) };
+    if name.is_null() {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(unsafe { CStr::from_ptr(name) }.to_owned())
+    }
+}
+
+/// A new pseudo-terminal.
+struct Pty {
+    master: File,
+    slave: File,
+}
+
+impl Pty {
+    fn open() -> io::Result<Self> {
+        let master = cvt(unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY) })?;
+        let master = unsafe { File::from_raw_fd(master) };
+        set_flags(master.as_raw_fd(), false)?;
+        cvt(unsafe { libc::grantpt(master.as_raw_fd()) })?;
+        cvt(unsafe { libc::unlockpt(master.as_raw_fd()) })?;
+
+        let name = ptsname(master.as_raw_fd())?;
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(OsStr::from_bytes(name.to_bytes()))?;
+
+        Ok(Self { master, slave })
+    }
+}
+
+/// Copies the window size of `from` to `to`.
+fn copy_winsize(from: RawFd, to: RawFd) -> io::Result<()> {
+    let mut ws = MaybeUninit::<libc::winsize>::uninit();
+    unsafe {
+        cvt(libc::ioctl(from, libc::TIOCGWINSZ, ws.as_mut_ptr()))?;
+        cvt(libc::ioctl(to, libc::TIOCSWINSZ, ws.as_ptr()))?;
+    }
+    Ok(())
+}
+
+/// The terminal of `fd` in raw mode, put back as it was on drop.
+struct RawMode(RawFd, libc::termios);
+
+impl RawMode {
+    fn enable(fd: RawFd) -> io::Result<Self> {
+        let mut termios = MaybeUninit::<libc::termios>::uninit();
+        let termios = unsafe {
+            cvt(libc::tcgetattr(fd, termios.as_mut_ptr()))?;
+            termios.assume_init()
+        };
+        let mut raw = termios;
+        unsafe {
+            libc::cfmakeraw(&mut raw);
+            cvt(libc::tcsetattr(fd, libc::TCSADRAIN, &raw))?;
+        }
+        Ok(Self(fd, termios))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(self.0, libc::TCSADRAIN, &self.1) };
+    }
+}
+
+extern "C" fn on_signal(sig: c_int) {
+    let fd = SIGNAL_PIPE.load(Ordering::Relaxed);
+    if fd != -1 {
+        unsafe {
+            let errno = *super::__errno();
+            let sig = sig as u8;
+            libc::write(fd, (&sig as *const u8).cast(), 1);
+            *super::__errno() = errno;
+        }
+    }
+}
+
+/// Signal handlers writing to a pipe, put back as they were on drop.
+struct Signals {
+    read: File,
+    _write: File,
+    previous: Vec<(c_int, libc::sigaction)>,
+}
+
+impl Signals {
+    fn install() -> io::Result<Self> {
+        let mut fds = [-1; 2];
+        cvt(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+        let (read, write) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        set_flags(read.as_raw_fd(), true)?;
+        set_flags(write.as_raw_fd(), true)?;
+        SIGNAL_PIPE.store(write.as_raw_fd(), Ordering::Relaxed);
+
+        let mut signals = Self {
+            read,
+            _write: write,
+            previous: Vec::new(),
+        };
+        for sig in FORWARDED.into_iter().chain([libc::SIGCHLD, libc::SIGWINCH]) {
+            unsafe {
+                let mut action = MaybeUninit::<libc::sigaction>::zeroed().assume_init();
+                action.sa_sigaction = on_signal as extern "C" fn(c_int) as libc::sighandler_t;
+                action.sa_flags = libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                let mut previous = MaybeUninit::<libc::sigaction>::uninit();
+                cvt(libc::sigaction(sig, &action, previous.as_mut_ptr()))?;
+                signals.previous.push((sig, previous.assume_init()));
+            }
+        }
+        Ok(signals)
+    }
+
+    /// The signals received since the last call.
+    fn pending(&mut self) -> Vec<c_int> {
+        let mut buf = [0u8; 64];
+        let mut res = Vec::new();
+        while let Ok(n @ 1..) = self.read.read(&mut buf) {
+            res.extend(buf[..n].iter().map(|&sig| sig as c_int));
+        }
+        res
+    }
+}
+
+impl Drop for Signals {
+    fn drop(&mut self) {
+        for (sig, action) in self.previous.drain(..) {
+            unsafe { libc::sigaction(sig, &action, std::ptr::null_mut()) };
+        }
+        SIGNAL_PIPE.store(-1, Ordering::Relaxed);
+    }
+}
+
+/// Whether `err` means the other side of the pty is gone.
+#[inline]
+fn is_closed(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::EIO)
+}
+
+fn poll(fds: &mut [libc::pollfd]) -> io::Result<()> {
+    loop {
+        match cvt(unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, -1) }) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            res => return res.map(|_| ()),
+        }
+    }
+}
+
+fn read_fd(fd: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match cvt(unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) } as c_int) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            res => return res.map(|n| n as usize),
+        }
+    }
+}
+
+fn write_fd(fd: RawFd, buf: &[u8]) -> io::Result<usize> {
+    loop {
+        match cvt(unsafe { libc::write(fd, buf.as_ptr().cast(), buf.len()) } as c_int) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            res => return res.map(|n| n as usize),
+        }
+    }
+}
+
+/// Writes all of `buf` to `fd`, waiting for room if it is non-blocking.
+fn write_all(fd: RawFd, mut buf: &[u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        match write_fd(fd, buf) {
+            Ok(n) => buf = &buf[n..],
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => poll(&mut [libc::pollfd {
+                fd,
+                events: libc::POLLOUT,
+                revents: 0,
+            }])?,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// The status of `pid` if it exited, was killed or was stopped.
+fn try_wait(pid: libc::pid_t) -> io::Result<Option<c_int>> {
+    let mut status = 0;
+    loop {
+        match cvt(unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG | libc::WUNTRACED) }) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(status)),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// What the monitor tells pezzo about the command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Report {
+    /// The command could not be run, with the `errno` of the failure.
+    Error(c_int),
+    /// The command was stopped, with its wait status.
+    Stopped(c_int),
+    /// The command exited or was killed, with its wait status.
+    Exited(c_int),
+}
+
+impl Report {
+    fn send(self, stream: &mut UnixStream) -> io::Result<()> {
+        let (kind, value) = match self {
+            Self::Error(errno) => (0, errno),
+            Self::Stopped(status) => (1, status),
+            Self::Exited(status) => (2, status),
+        };
+        let mut buf = [kind; 5];
+        buf[1..].copy_from_slice(&value.to_ne_bytes());
+        stream.write_all(&buf)
+    }
+
+    /// The next report, `None` if the monitor is gone.
+    fn receive(stream: &mut UnixStream) -> io::Result<Option<Self>> {
+        let mut buf = [0u8; 5];
+        match stream.read_exact(&mut buf) {
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            res => res?,
+        }
+        let value = c_int::from_ne_bytes([buf[1], buf[2], buf[3], buf[4]]);
+        match buf[0] {
+            0 => Ok(Some(Self::Error(value))),
+            1 => Ok(Some(Self::Stopped(value))),
+            2 => Ok(Some(Self::Exited(value))),
+            _ => Err(io::Error::from(io::ErrorKind::InvalidData)),
+        }
+    }
+}
+
+/// Leads the session of the pty of `slave`, as its controlling terminal,
+/// and runs `cmd` in the foreground of it. Signal numbers read from
+/// `pezzo` are sent to the command, and what becomes of it is reported
+/// there until it exits.
+fn monitor(
+    mut cmd: Command,
+    slave: File,
+    streams: [bool; 3],
+    pezzo: &mut UnixStream,
+) -> io::Result<()> {
+    cvt(unsafe { libc::setsid() })?;
+    cvt(unsafe { libc::ioctl(slave.as_raw_fd(), libc::TIOCSCTTY as _, 0) })?;
+    let mut signals = Signals::install()?;
+
+    let [stdin, stdout, stderr] = streams;
+    let stdio = |pty: bool| -> io::Result<Stdio> {
+        Ok(if pty {
+            slave.try_clone()?.into()
+        } else {
+            Stdio::inherit()
+        })
+    };
+    cmd.stdin(stdio(stdin)?)
+        .stdout(stdio(stdout)?)
+        .stderr(stdio(stderr)?);
+    let slave_fd = slave.as_raw_fd();
+    unsafe {
+        cmd.pre_exec(move || {
+            cvt(libc::setpgid(0, 0))?;
+            // Still in the background, which SIGTTOU is sent to.
+            libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+            let res = cvt(libc::tcsetpgrp(slave_fd, libc::getpid()));
+            libc::signal(libc::SIGTTOU, libc::SIG_DFL);
+            res.map(|_| ())
+        });
+    }
+    let child = cmd.spawn()?;
+    // Only the command holds the slave now: reading the master fails with
+    // EIO once it and its children are gone.
+    drop((cmd, slave));
+    let pid = child.id() as libc::pid_t;
+
+    let mut connected = true;
+    loop {
+        let mut fds = [
+            libc::pollfd {
+                fd: signals.read.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: if connected { pezzo.as_raw_fd() } else { -1 },
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        poll(&mut fds)?;
+
+        if fds[0].revents != 0 {
+            for sig in signals.pending() {
+                if FORWARDED.contains(&sig) {
+                    unsafe { libc::kill(pid, sig) };
+                }
+            }
+        }
+
+        if fds[1].revents != 0 {
+            let mut sig = [0u8];
+            match pezzo.read(&mut sig)? {
+                // pezzo is gone, like the terminal of the command.
+                0 => {
+                    connected = false;
+                    unsafe { libc::kill(pid, libc::SIGHUP) };
+                }
+                // The whole process group was stopped.
+                _ if c_int::from(sig[0]) == libc::SIGCONT => unsafe {
+                    libc::killpg(pid, libc::SIGCONT);
+                },
+                _ => unsafe {
+                    libc::kill(pid, c_int::from(sig[0]));
+                },
+            }
+        }
+
+        // SIGCHLD can be merged, the command is checked every time.
+        while let Some(status) = try_wait(pid)? {
+            if !libc::WIFSTOPPED(status) {
+                _ = Report::Exited(status).send(pezzo);
+                return Ok(());
+            }
+            if !connected || Report::Stopped(status).send(pezzo).is_err() {
+                unsafe { libc::killpg(pid, libc::SIGCONT) };
+            }
+        }
+    }
+}
+
+/// What the command is relayed between.
+struct Relay {
+    master: File,
+    /// Where what the user types is read from, `None` at the end of it.
+    input: Option<RawFd>,
+    /// Where what the command prints goes.
+    output: RawFd,
+    /// Terminal of the user, whose settings and window size the pty follows.
+    terminal: Option<RawFd>,
+    /// Input the pty could not take yet.
+    pending: Vec<u8>,
+}
+
+impl Relay {
+    /// Sends what the command printed to `output`, until there is nothing
+    /// left to read. Fails with `EIO` when the command is gone.
+    fn output(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        loop {
+            match read_fd(self.master.as_raw_fd(), buf) {
+                Ok(0) => return Err(io::Error::from_raw_os_error(libc::EIO)),
+                Ok(n) => write_all(self.output, &buf[..n])?,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Reads what the user typed, until the end of the input.
+    fn input(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        if let Some(fd) = self.input {
+            match read_fd(fd, buf) {
+                Ok(0) => self.input = None,
+                Ok(n) => self.pending.extend_from_slice(&buf[..n]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => (),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes as much of the pending input as the pty takes.
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.pending.is_empty() {
+            match write_fd(self.master.as_raw_fd(), &self.pending) {
+                Ok(n) => drop(self.pending.drain(..n)),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                // Nobody is left to read it.
+                Err(err) if is_closed(&err) => self.pending.clear(),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops pezzo like the command, until it is continued. Without a
+    /// terminal nobody could continue it, so it goes on right away.
+    fn suspend(&mut self, raw: &mut Option<RawMode>) {
+        let Some(terminal) = self.terminal else {
+            return;
+        };
+        drop(raw.take());
+        unsafe { libc::kill(libc::getpid(), libc::SIGTSTP) };
+        *raw = RawMode::enable(terminal).ok();
+        _ = copy_winsize(terminal, self.master.as_raw_fd());
+    }
+
+    /// Relays until the `monitor` reports that the command exited and
+    /// returns its status.
+    fn supervise(
+        &mut self,
+        monitor: &mut UnixStream,
+        signals: &mut Signals,
+    ) -> io::Result<ExitStatus> {
+        let mut buf = [0u8; 8192];
+        let mut output = true;
+        let mut raw = self.terminal.and_then(|fd| RawMode::enable(fd).ok());
+
+        loop {
+            self.flush()?;
+            let mut events = libc::POLLIN;
+            if !self.pending.is_empty() {
+                events |= libc::POLLOUT;
+            }
+            let mut fds = [
+                libc::pollfd {
+                    fd: signals.read.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: if output { self.master.as_raw_fd() } else { -1 },
+                    events,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    // Nothing more is read until the pty takes what is pending.
+                    fd: match self.input {
+                        Some(fd) if self.pending.is_empty() => fd,
+                        _ => -1,
+                    },
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: monitor.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+            poll(&mut fds)?;
+
+            if fds[0].revents != 0 {
+                for sig in signals.pending() {
+                    match sig {
+                        libc::SIGCHLD => (),
+                        libc::SIGWINCH => {
+                            if let Some(terminal) = self.terminal {
+                                _ = copy_winsize(terminal, self.master.as_raw_fd());
+                            }
+                        }
+                        sig => monitor.write_all(&[sig as u8])?,
+                    }
+                }
+            }
+
+            if fds[1].revents & !libc::POLLOUT != 0 {
+                match self.output(&mut buf) {
+                    Err(err) if is_closed(&err) => {
+                        output = false;
+                        self.pending.clear();
+                    }
+                    res => res?,
+                }
+            }
+
+            if fds[2].revents != 0 {
+                self.input(&mut buf)?;
+            }
+
+            if fds[3].revents != 0 {
+                match Report::receive(monitor)? {
+                    Some(Report::Stopped(_)) => {
+                        self.suspend(&mut raw);
+                        monitor.write_all(&[libc::SIGCONT as u8])?;
+                    }
+                    Some(Report::Exited(status)) => {
+                        // The last output of the command may still be in
+                        // the pty.
+                        if output {
+                            match self.output(&mut buf) {
+                                Err(err) if !is_closed(&err) => return Err(err),
+                                _ => (),
+                            }
+                        }
+                        return Ok(ExitStatus::from_raw(status));
+                    }
+                    Some(Report::Error(errno)) => return Err(io::Error::from_raw_os_error(errno)),
+                    None => return Err(io::Error::other("the monitor exited unexpectedly")),
+                }
+            }
+        }
+    }
+}
+
+/// Runs `cmd` on the pty of `slave` and `relay.master`, from a monitor, and
+/// returns its exit status. `streams` tells which of stdin, stdout and
+/// stderr are replaced by the pty.
+fn spawn(
+    cmd: Command,
+    slave: File,
+    streams: [bool; 3],
+    mut relay: Relay,
+) -> io::Result<ExitStatus> {
+    if let Some(terminal) = relay.terminal {
+        let mut termios = MaybeUninit::<libc::termios>::uninit();
+        unsafe {
+            if libc::tcgetattr(terminal, termios.as_mut_ptr()) == 0 {
+                libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, termios.as_ptr());
+            }
+        }
+        _ = copy_winsize(terminal, slave.as_raw_fd());
+    }
+
+    let (mut monitor, mut pezzo) = UnixStream::pair()?;
+    let mut signals = Signals::install()?;
+    let pid = match cvt(unsafe { libc::fork() })? {
+        0 => {
+            // The monitor relays nothing and has signal handlers of its own.
+            drop((relay, monitor, signals));
+            let res = self::monitor(cmd, slave, streams, &mut pezzo);
+            if let Err(ref err) = res {
+                _ = Report::Error(err.raw_os_error().unwrap_or(libc::EIO)).send(&mut pezzo);
+            }
+            unsafe { libc::_exit(res.is_err() as c_int) }
+        }
+        pid => pid,
+    };
+    drop((cmd, slave, pezzo));
+
+    let res = set_flags(relay.master.as_raw_fd(), true)
+        .and_then(|()| relay.supervise(&mut monitor, &mut signals));
+    if res.is_err() {
+        _ = monitor.write_all(&[libc::SIGKILL as u8]);
+    }
+    _ = wait(pid);
+    res
+}
+
+fn wait(pid: libc::pid_t) -> io::Result<c_int> {
+    let mut status = 0;
+    loop {
+        match cvt(unsafe { libc::waitpid(pid, &mut status, 0) }) {
+            Ok(_) => return Ok(status),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Runs `cmd` on a new pseudo-terminal, as its controlling terminal, and
+/// returns its exit status.
+///
+/// The standard streams of `cmd` that are terminals are replaced by the pty,
+/// the others are kept. With a terminal, the pty starts with the settings
+/// and the window size of `tty_out`, which is put in raw mode until the
+/// command exits: what is typed on `tty_in`, if given, goes to the command
+/// and what the command prints goes to `tty_out`. Without one, what the
+/// command prints on the pty goes to the standard output. Window size
+/// changes follow and `SIGHUP`, `SIGINT`, `SIGQUIT`, `SIGTERM`, `SIGUSR1`
+/// and `SIGUSR2` sent to pezzo are sent to the command.
+pub fn run(
+    cmd: Command,
+    tty_in: Option<&RefCell<TtyIn>>,
+    tty_out: Option<&RefCell<TtyOut>>,
+) -> io::Result<ExitStatus> {
+    let Pty { master, slave } = Pty::open()?;
+
+    // Whatever was typed after the password is for the command.
+    let mut pending = Vec::new();
+    if let Some(tty_in) = tty_in {
+        let mut tty_in = tty_in.borrow_mut();
+        let len = tty_in.inner.buffer().len();
+        pending.extend_from_slice(tty_in.inner.buffer());
+        tty_in.inner.consume(len);
+    }
+    let terminal = match tty_out {
+        Some(tty_out) => {
+            let mut tty_out = tty_out.borrow_mut();
+            tty_out.flush()?;
+            Some(tty_out.as_raw_fd())
+        }
+        None => None,
+    };
+
+    let streams = [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO]
+        .map(|fd| unsafe { libc::isatty(fd) } == 1);
+    let relay = Relay {
+        master,
+        input: tty_in.map(|tty_in| tty_in.borrow().as_raw_fd()),
+        output: terminal.unwrap_or(libc::STDOUT_FILENO),
+        terminal,
+        pending,
+    };
+    spawn(cmd, slave, streams, relay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipe() -> (File, File) {
+        let mut fds = [-1; 2];
+        cvt(unsafe { libc::pipe(fds.as_mut_ptr()) }).unwrap();
+        set_flags(fds[0], false).unwrap();
+        set_flags(fds[1], false).unwrap();
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) }
+    }
+
+    /// Runs `script` on a pty, typing `typed`, and returns its status and
+    /// what it printed.
+    fn run_script(script: &str, typed: Vec<u8>) -> (ExitStatus, String) {
+        let Pty { master, slave } = Pty::open().unwrap();
+        let (input, mut typist) = pipe();
+        let (mut printed, output) = pipe();
+
+        let typist = std::thread::spawn(move || typist.write_all(&typed).unwrap());
+        let reader = std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            printed.read_to_end(&mut buf).unwrap();
+            buf
+        });
+
+        let mut cmd = Command::new("/bin/sh");
+        cmd.args(["-c", script]);
+        let relay = Relay {
+            master,
+            input: Some(input.as_raw_fd()),
+            output: output.as_raw_fd(),
+            terminal: None,
+            pending: Vec::new(),
+        };
+        let status = spawn(cmd, slave, [true, true, false], relay).unwrap();
+        drop((input, output));
+
+        typist.join().unwrap();
+        let printed = String::from_utf8(reader.join().unwrap()).unwrap();
+        (status, printed)
+    }
+
+    #[test]
+    fn relay() {
+        // Far more than the pty takes at once, then the end of the input.
+        let mut typed = b"hello\n".to_vec();
+        for _ in 0..2000 {
+            typed.extend_from_slice(b"0123456789abcdef0123456789abcde\n");
+        }
+        typed.push(b'\x04');
+
+        let (status, printed) = run_script("read x; echo got:$x; wc -l | tr -d ' '; exit 7", typed);
+        assert_eq!(status.code(), Some(7));
+        // What is typed is echoed by the pty, in between.
+        assert!(printed.contains("got:hello\r\n"), "{}", printed);
+        assert!(printed.ends_with("2000\r\n"), "{}", printed);
+    }
+
+    #[test]
+    fn monitor() {
+        // The command is in the foreground of a session the monitor leads.
+        let (status, printed) = run_script(
+            "set -- $(cat /proc/$$/stat); [ $5 = $8 ] && [ $5 != $6 ] && echo foreground",
+            Vec::new(),
+        );
+        assert!(status.success(), "{}", printed);
+        assert_eq!(printed, "foreground\r\n");
+
+        // Stopped, then resumed though there is no terminal to stop pezzo.
+        let (status, printed) = run_script(
+            "kill -TSTP $$; echo resumed; kill -STOP $$; kill -TERM $$",
+            Vec::new(),
+        );
+        assert_eq!(status.signal(), Some(libc::SIGTERM));
+        assert_eq!(printed, "resumed\r\n");
+    }
+}
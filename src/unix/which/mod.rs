@@ -24,7 +24,15 @@ fn canonicalize<P: AsRef<CStr>>(path: P) -> std::io::Result<Option<CString>> {
     }
 }
 
+/// Looks `binary_name` up in the `PATH` of the process.
+#[inline]
 pub fn which<T: AsRef<CStr>>(binary_name: T) -> std::io::Result<CString> {
+    which_in(binary_name, raw_path().unwrap_or_default())
+}
+
+/// Looks `binary_name` up in `search_path`, a colon separated list of
+/// directories, instead of the `PATH` of the process.
+pub fn which_in<T: AsRef<CStr>>(binary_name: T, search_path: &[u8]) -> std::io::Result<CString> {
     let binary_checker = build_binary_checker();
     let binary_name = binary_name.as_ref();
 
@@ -35,18 +43,15 @@ pub fn which<T: AsRef<CStr>>(binary_name: T) -> std::io::Result<CString> {
         } else {
             Err(std::io::ErrorKind::NotFound.into())
         };
-    } else if let Some(raw_path) = raw_path() {
+    } else {
         let mut buf = Vec::new();
-        let mut prev = 0;
-        for pos in memchr::memchr_iter(b':', raw_path) {
+        for dir in search_path.split(|&c| c == b':') {
             let path = {
-                let slice = unsafe { raw_path.get_unchecked(prev..pos) };
-                if slice.is_empty() {
+                if dir.is_empty() {
                     continue;
                 }
                 buf.clear();
-                buf.extend_from_slice(slice);
-                prev = pos + 1;
+                buf.extend_from_slice(dir);
                 buf.push(b'/');
                 buf.extend_from_slice(binary_name.to_bytes_with_nul());
                 unsafe { CStr::from_ptr(buf.as_ptr().cast()) }
@@ -68,3 +73,14 @@ fn build_binary_checker() -> CompositeChecker {
         .add_checker(Box::new(ExistedChecker::new()))
         .add_checker(Box::new(ExecutableChecker::new()))
 }
+
+#[cfg(test)]
+mod tests {
+
+    #[test]
+    fn which_in() {
+        assert!(super::which_in(c"sh", b"/nonexistent::/bin").is_ok());
+        assert!(super::which_in(c"sh", b"").is_err());
+        assert!(super::which_in(c"sh", b"bin").is_err());
+    }
+}
@@ -80,3 +80,181 @@ cfg_if::cfg_if! {
         }
     }
 }
+
+/// `$VISUAL` or `$EDITOR` split on whitespace, `vi` if neither is set.
+#[cfg(unix)]
+pub fn editor() -> Vec<std::ffi::OsString> {
+    use std::os::unix::ffi::OsStrExt;
+
+    for var in ["VISUAL", "EDITOR"] {
+        if let Some(value) = std::env::var_os(var) {
+            let words = value
+                .as_bytes()
+                .split(|c| c.is_ascii_whitespace())
+                .filter(|w| !w.is_empty())
+                .map(|w| std::ffi::OsStr::from_bytes(w).to_os_string())
+                .collect::<Vec<_>>();
+            if !words.is_empty() {
+                return words;
+            }
+        }
+    }
+
+    vec![std::ffi::OsString::from("vi")]
+}
+
+#[cfg(unix)]
+#[inline]
+pub fn run_path_with_cstr<T, E, F>(path: &std::path::Path, f: F) -> Result<T, E>
+where
+    E: From<std::io::Error>,
+    F: FnOnce(&std::ffi::CStr) -> Result<T, E>,
+{
+    use std::os::unix::ffi::OsStrExt;
+
+    run_with_cstr(path.as_os_str().as_bytes(), f)
+}
+
+#[cfg(unix)]
+#[inline]
+pub fn run_with_cstr<T, E, F>(bytes: &[u8], f: F) -> Result<T, E>
+where
+    E: From<std::io::Error>,
+    F: FnOnce(&std::ffi::CStr) -> Result<T, E>,
+{
+    const MAX_STACK_ALLOCATION: usize = 384;
+
+    if bytes.len() >= MAX_STACK_ALLOCATION {
+        return run_with_cstr_allocating(bytes, f);
+    }
+
+    let mut buf = std::mem::MaybeUninit::<[u8; MAX_STACK_ALLOCATION]>::uninit();
+    let buf_ptr = buf.as_mut_ptr() as *mut u8;
+
+    unsafe {
+        core::ptr::copy_nonoverlapping(bytes.as_ptr(), buf_ptr, bytes.len());
+        buf_ptr.add(bytes.len()).write(0);
+    }
+
+    match std::ffi::CStr::from_bytes_with_nul(unsafe {
+        core::slice::from_raw_parts(buf_ptr, bytes.len() + 1)
+    }) {
+        Ok(s) => f(s),
+        Err(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "file name contained an unexpected NUL byte",
+        )
+        .into()),
+    }
+}
+
+#[cfg(unix)]
+#[cold]
+#[inline(never)]
+fn run_with_cstr_allocating<T, E, F>(bytes: &[u8], f: F) -> Result<T, E>
+where
+    E: From<std::io::Error>,
+    F: FnOnce(&std::ffi::CStr) -> Result<T, E>,
+{
+    match std::ffi::CString::new(bytes) {
+        Ok(s) => f(&s),
+        Err(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "file name contained an unexpected NUL byte",
+        )
+        .into()),
+    }
+}
+
+/// Fails unless `md`, the metadata of the open `path`, says it is owned by
+/// root and not writable by group or others.
+#[cfg(unix)]
+pub fn check_metadata_permissions(
+    path: &std::ffi::CStr,
+    md: &std::fs::Metadata,
+) -> anyhow::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    if md.uid() != 0 || md.mode() & 0o022 != 0 {
+        anyhow::bail!(
+            "Wrong permissions on file {:?}. Your system has been compromised",
+            path
+        );
+    }
+
+    Ok(())
+}
+
+/// Opens the already canonical `path` without following a symlink swapped in
+/// since, the checks are then made on what is actually read.
+#[cfg(unix)]
+pub fn open_nofollow(
+    path: &std::ffi::CStr,
+    directory: bool,
+) -> std::io::Result<(std::fs::File, std::fs::Metadata)> {
+    use std::os::unix::{ffi::OsStrExt, fs::OpenOptionsExt};
+
+    let mut flags = libc::O_NOFOLLOW;
+    if directory {
+        flags |= libc::O_DIRECTORY;
+    }
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(flags)
+        .open(std::ffi::OsStr::from_bytes(path.to_bytes()))?;
+    let md = file.metadata()?;
+    Ok((file, md))
+}
+
+#[cfg(unix)]
+pub fn check_file_permissions<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<()> {
+    run_path_with_cstr(path.as_ref(), |p| check_file_permissions_cstr(p))
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn check_file_permissions_cstr<P: AsRef<std::ffi::CStr>>(path: P) -> anyhow::Result<()> {
+    let path = path.as_ref();
+
+    let mut buf = std::mem::MaybeUninit::<libc::stat>::uninit();
+    let md = loop {
+        if unsafe { libc::stat(path.as_ptr().cast(), buf.as_mut_ptr()) == -1 } {
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::Interrupted {
+                anyhow::bail!("Cannot stat file {:?}", path);
+            }
+        } else {
+            break unsafe { buf.assume_init() };
+        }
+    };
+
+    if md.st_uid != 0 || md.st_mode & 0o022 != 0 {
+        anyhow::bail!(
+            "Wrong permissions on file {:?}. Your system has been compromised",
+            path
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub fn check_file_permissions_cstr<P: AsRef<std::ffi::CStr>>(path: P) -> anyhow::Result<()> {
+    let path = path.as_ref();
+
+    let md = loop {
+        match linux_stat::stat_cstr(path) {
+            Err(linux_stat::Errno::EINTR) => (),
+            Err(_) => anyhow::bail!("Cannot stat file {:?}", path),
+            Ok(md) => break md,
+        }
+    };
+
+    if md.uid() != 0 || md.mode().as_u16() & 0o022 != 0 {
+        anyhow::bail!(
+            "Wrong permissions on file {:?}. Your system has been compromised",
+            path
+        );
+    }
+
+    Ok(())
+}